@@ -0,0 +1,262 @@
+//! Weighs up moves from what a single player can see, rather than from the whole board
+//!
+//! A player only sees their own pieces, but knows how many pieces everyone else has dropped,
+//! that every piece rests on the one below it, and which columns turned out to be full when
+//! they tried to play into them. Boards which fit all of that are drawn at random, and each
+//! move is scored by the solver on every one of them.
+use crate::solver;
+use crate::types::GameState;
+
+/// Everything a player knows about the game when it is their turn
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerView {
+    /// The board as the player sees it, with only their own pieces on it
+    pub board: Vec<Vec<usize>>,
+    /// Where the player is in the turn order, which must be the player to move
+    pub player_index: usize,
+    pub players: usize,
+    /// How many pieces everyone has dropped so far
+    pub move_count: usize,
+    pub connect_length: usize,
+    /// Columns a play into was refused for being full
+    pub full_columns: Vec<usize>,
+}
+
+impl PlayerView {
+    /// How many pieces a player has dropped, when turns were taken in order from the first player
+    fn pieces_of(&self, player_index: usize) -> usize {
+        self.move_count / self.players + usize::from(player_index < self.move_count % self.players)
+    }
+}
+
+/// How a column fared over the sampled boards
+#[derive(Debug, Clone, PartialEq)]
+pub struct MoveEstimate {
+    pub column: usize,
+    /// How likely playing the column wins, over the boards it could be played on
+    pub win_probability: f64,
+    /// How many of the boards the column was already full on
+    pub full_probability: f64,
+}
+
+/// What the sampled boards made of each column
+#[derive(Debug, Clone, PartialEq)]
+pub struct Belief {
+    /// `None` when no board fit what the player knows
+    pub best_column: Option<usize>,
+    /// Best first
+    pub moves: Vec<MoveEstimate>,
+    /// How many boards the estimates were taken over
+    pub samples: usize,
+}
+
+/// Score every column over `samples` boards which fit the view, looking `depth` moves ahead on each
+///
+/// The same seed always draws the same boards
+pub fn evaluate(view: &PlayerView, samples: usize, depth: u32, seed: u64) -> Belief {
+    let width = view.board.len();
+    let mut rng = Rng::new(seed);
+    let mut totals = vec![0.0; width];
+    let mut open = vec![0usize; width];
+    let mut drawn = 0;
+
+    // boards where someone already won are thrown away, so a few more are drawn than are kept
+    for _ in 0..samples * SAMPLE_ATTEMPTS {
+        if drawn == samples {
+            break;
+        }
+        let board = match sample(view, &mut rng) {
+            Some(board) => board,
+            None => continue,
+        };
+        drawn += 1;
+        for (column, score) in solver::score_columns(&board, depth) {
+            totals[column] += solver::win_probability(score);
+            open[column] += 1;
+        }
+    }
+
+    let mut moves: Vec<MoveEstimate> = (0..width)
+        .filter(|column| drawn > 0 && open[*column] > 0)
+        .map(|column| MoveEstimate {
+            column,
+            win_probability: totals[column] / open[column] as f64,
+            full_probability: 1.0 - open[column] as f64 / drawn as f64,
+        })
+        .collect();
+    let middle = width as f64 / 2.0 - 0.5;
+    moves.sort_by(|a, b| {
+        b.win_probability.total_cmp(&a.win_probability).then(
+            (a.column as f64 - middle)
+                .abs()
+                .total_cmp(&(b.column as f64 - middle).abs()),
+        )
+    });
+    Belief {
+        best_column: moves.first().map(|estimate| estimate.column),
+        moves,
+        samples: drawn,
+    }
+}
+
+/// How many boards may be drawn for every one which is kept
+const SAMPLE_ATTEMPTS: usize = 10;
+
+/// Draw a whole board which fits the view, or `None` when the one drawn could not have been played
+///
+/// Hidden pieces fill every cell below the pieces of the player, and the rest are stacked onto
+/// columns at random. Which opponent each hidden piece belongs to is shuffled.
+fn sample(view: &PlayerView, rng: &mut Rng) -> Option<GameState> {
+    let height = view.board.first().map_or(0, Vec::len);
+    let mut board = view.board.clone();
+    let mut heights: Vec<usize> = view
+        .board
+        .iter()
+        .enumerate()
+        .map(
+            |(column, cells)| match view.full_columns.contains(&column) {
+                true => height,
+                false => cells
+                    .iter()
+                    .rposition(|cell| *cell == view.player_index)
+                    .map_or(0, |row| row + 1),
+            },
+        )
+        .collect();
+
+    let mut hidden: Vec<(usize, usize)> = Vec::new();
+    for (column, cells) in board.iter().enumerate() {
+        for (row, cell) in cells.iter().enumerate().take(heights[column]) {
+            if *cell != view.player_index {
+                hidden.push((column, row));
+            }
+        }
+    }
+    let mut owners: Vec<usize> = (0..view.players)
+        .filter(|player| *player != view.player_index)
+        .flat_map(|player| std::iter::repeat_n(player, view.pieces_of(player)))
+        .collect();
+    let extra = owners.len().checked_sub(hidden.len())?;
+
+    for _ in 0..extra {
+        let open: Vec<usize> = (0..heights.len())
+            .filter(|column| heights[*column] < height)
+            .collect();
+        if open.is_empty() {
+            return None;
+        }
+        let column = open[rng.below(open.len())];
+        hidden.push((column, heights[column]));
+        heights[column] += 1;
+    }
+
+    for index in (1..owners.len()).rev() {
+        owners.swap(index, rng.below(index + 1));
+    }
+    for ((column, row), owner) in hidden.into_iter().zip(owners) {
+        board[column][row] = owner;
+    }
+
+    let state = GameState {
+        board,
+        turn_index: view.player_index,
+        player_turn_order: (0..view.players).map(|player| player.to_string()).collect(),
+        connect_length: view.connect_length,
+        moves: Vec::new(),
+    };
+    match solver::has_line(&state) {
+        true => None,
+        false => Some(state),
+    }
+}
+
+/// A small xorshift generator, so that the same seed always draws the same boards
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // the state must never be zero
+        Rng(seed ^ 0x9E37_79B9_7F4A_7C15 | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// A number from 0 up to but not including `bound`
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::create_game_board;
+
+    /// The view of the first of two players once each dropped `move_count / 2` pieces,
+    /// with their own pieces stacked in the first column
+    fn view(move_count: usize) -> PlayerView {
+        let mut board = create_game_board(7, 6);
+        for cell in board[0].iter_mut().take(move_count / 2) {
+            *cell = 0;
+        }
+        PlayerView {
+            board,
+            player_index: 0,
+            players: 2,
+            move_count,
+            connect_length: 4,
+            full_columns: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn samples_fit_the_view() {
+        let mut view = view(6);
+        view.board[0][2] = usize::MAX;
+        view.board[3][2] = 0;
+        let mut rng = Rng::new(7);
+        for _ in 0..50 {
+            let board = sample(&view, &mut rng).unwrap().board;
+            // the pieces under the one in the middle can only be the opponent's
+            assert_eq!((board[3][0], board[3][1], board[3][2]), (1, 1, 0));
+            assert_eq!((board[0][0], board[0][1]), (0, 0));
+            assert_eq!(board.iter().flatten().filter(|cell| **cell == 1).count(), 3);
+            for column in &board {
+                let height = column.iter().filter(|cell| **cell != usize::MAX).count();
+                assert!(column[height..].iter().all(|cell| *cell == usize::MAX));
+            }
+        }
+    }
+
+    #[test]
+    fn likely_wins_are_preferred() {
+        let belief = evaluate(&view(6), 40, 2, 1);
+        assert_eq!(belief.samples, 40);
+        assert_eq!(belief.best_column, Some(0));
+        assert!(belief.moves[0].win_probability > belief.moves[1].win_probability);
+        // the win is gone whenever the opponent stacked onto the column
+        assert!(belief.moves[0].win_probability < 1.0);
+    }
+
+    #[test]
+    fn full_columns_are_never_played() {
+        let mut view = view(6);
+        view.move_count = 12;
+        view.board[3] = vec![0, usize::MAX, 0, usize::MAX, 0, usize::MAX];
+        view.full_columns.push(3);
+        let belief = evaluate(&view, 10, 1, 1);
+        assert!(belief.samples > 0);
+        assert!(belief.moves.iter().all(|estimate| estimate.column != 3));
+
+        // the opponent has not dropped enough pieces to have filled another column
+        view.full_columns.push(5);
+        let belief = evaluate(&view, 10, 1, 1);
+        assert_eq!(belief.samples, 0);
+        assert_eq!(belief.best_column, None);
+    }
+}
@@ -1 +1,3 @@
+pub mod belief;
+pub mod solver;
 pub mod types;
@@ -0,0 +1,455 @@
+//! Looks ahead from a position with the whole board in view, as though nothing were hidden
+//!
+//! The search is alpha-beta over every column with a transposition table, deepened one move
+//! at a time up to a limit. Games of more than two players are searched as though everyone
+//! else played against the player to move.
+use crate::types::GameState;
+use std::collections::HashMap;
+
+/// The score of winning on the spot, which is lowered by one for every move it takes to get there
+const WIN_SCORE: i32 = 1_000_000;
+/// Any score closer to a win or loss than this is one which was found, rather than guessed
+const PROVEN_SCORE: i32 = WIN_SCORE - 1_000;
+/// How many points of a guessed score move the win probability from even to about 73%
+const PROBABILITY_SCALE: f64 = 20.0;
+
+/// What the search found for the player to move
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    /// `None` when the board is full
+    pub best_column: Option<usize>,
+    /// How good the position is for the player to move, where wins and losses are worth
+    /// `WIN_SCORE` less the moves it takes to reach them and anything else is a guess
+    pub score: i32,
+    /// How likely the player to move is to win, which is certain once a win or loss was found
+    pub win_probability: f64,
+    /// The columns both sides are expected to play from here, starting with `best_column`
+    pub principal_variation: Vec<usize>,
+    /// How many moves ahead the search looked
+    pub depth: u32,
+}
+
+/// Search the position for the best move of the player whose turn it is, looking at most `max_depth` moves ahead
+pub fn analyze(state: &GameState, max_depth: u32) -> Analysis {
+    let mut position = Position::from(state);
+    let mut search = Search {
+        root: position.turn,
+        table: HashMap::new(),
+    };
+
+    let mut score = 0;
+    let mut depth = 0;
+    for limit in 1..=max_depth.max(1) {
+        score = search.search(&mut position, limit, 0, -WIN_SCORE, WIN_SCORE);
+        depth = limit;
+        if score.abs() >= PROVEN_SCORE {
+            break;
+        }
+    }
+
+    let principal_variation = search.principal_variation(&mut position, depth);
+    Analysis {
+        best_column: principal_variation.first().copied(),
+        score,
+        win_probability: win_probability(score),
+        principal_variation,
+        depth,
+    }
+}
+
+/// Score every open column for the player to move, looking `depth` moves ahead once it is played
+pub fn score_columns(state: &GameState, depth: u32) -> Vec<(usize, i32)> {
+    let mut position = Position::from(state);
+    let mut search = Search {
+        root: position.turn,
+        table: HashMap::new(),
+    };
+    position
+        .columns(None)
+        .into_iter()
+        .map(|column| {
+            let score = match position.play(column) {
+                true => WIN_SCORE - 1,
+                false if depth <= 1 => position.evaluate(search.root),
+                false => search.search(&mut position, depth - 1, 1, -WIN_SCORE, WIN_SCORE),
+            };
+            position.undo(column);
+            (column, score)
+        })
+        .collect()
+}
+
+/// Whether anyone has a line long enough to have won already
+pub(crate) fn has_line(state: &GameState) -> bool {
+    let position = Position::from(state);
+    (0..position.width)
+        .any(|column| (0..position.heights[column]).any(|row| position.connects(column, row)))
+}
+
+/// How likely a score makes a win for the player it belongs to
+pub fn win_probability(score: i32) -> f64 {
+    if score >= PROVEN_SCORE {
+        1.0
+    } else if score <= -PROVEN_SCORE {
+        0.0
+    } else {
+        1.0 / (1.0 + (-score as f64 / PROBABILITY_SCALE).exp())
+    }
+}
+
+/// A compact copy of a board which moves can be played into and taken back
+#[derive(Clone)]
+struct Position {
+    width: usize,
+    height: usize,
+    connect_length: usize,
+    players: usize,
+    /// Column by column from the bottom, holding 0 when empty and the player index plus 1 otherwise
+    cells: Vec<u8>,
+    /// How many pieces each column holds
+    heights: Vec<usize>,
+    turn: usize,
+}
+
+impl From<&GameState> for Position {
+    fn from(state: &GameState) -> Self {
+        let height = state.board.first().map_or(0, Vec::len);
+        let mut cells = Vec::with_capacity(state.board.len() * height);
+        let mut heights = Vec::with_capacity(state.board.len());
+        for column in &state.board {
+            cells.extend(column.iter().map(|cell| match *cell == usize::MAX {
+                true => 0,
+                false => *cell as u8 + 1,
+            }));
+            heights.push(column.iter().filter(|cell| **cell != usize::MAX).count());
+        }
+        Position {
+            width: state.board.len(),
+            height,
+            connect_length: state.connect_length,
+            players: state.player_turn_order.len().max(1),
+            cells,
+            heights,
+            turn: state.turn_index,
+        }
+    }
+}
+
+impl Position {
+    fn cell(&self, column: usize, row: usize) -> u8 {
+        self.cells[column * self.height + row]
+    }
+
+    fn is_full(&self) -> bool {
+        self.heights.iter().all(|height| *height == self.height)
+    }
+
+    /// The open columns, middle first since those take part in the most lines
+    fn columns(&self, first: Option<usize>) -> Vec<usize> {
+        let middle = self.width as isize / 2;
+        let mut columns: Vec<usize> = (0..self.width)
+            .filter(|column| self.heights[*column] < self.height)
+            .collect();
+        columns.sort_by_key(|column| {
+            (
+                Some(*column) != first,
+                (*column as isize - middle).abs(),
+                *column,
+            )
+        });
+        columns
+    }
+
+    /// Drop a piece of the player to move, returning whether it won them the game
+    fn play(&mut self, column: usize) -> bool {
+        let row = self.heights[column];
+        self.cells[column * self.height + row] = self.turn as u8 + 1;
+        self.heights[column] += 1;
+        let won = self.connects(column, row);
+        self.turn = (self.turn + 1) % self.players;
+        won
+    }
+
+    fn undo(&mut self, column: usize) {
+        self.heights[column] -= 1;
+        self.cells[column * self.height + self.heights[column]] = 0;
+        self.turn = (self.turn + self.players - 1) % self.players;
+    }
+
+    /// Whether the piece in this cell is part of a line long enough to win
+    fn connects(&self, column: usize, row: usize) -> bool {
+        let piece = self.cell(column, row);
+        [(1, 0), (0, 1), (1, 1), (1, -1)].iter().any(|(dc, dr)| {
+            let run = |sign: isize| {
+                (1..)
+                    .map(|step| {
+                        (
+                            column as isize + dc * step * sign,
+                            row as isize + dr * step * sign,
+                        )
+                    })
+                    .take_while(|(c, r)| {
+                        *c >= 0
+                            && *r >= 0
+                            && (*c as usize) < self.width
+                            && (*r as usize) < self.height
+                            && self.cell(*c as usize, *r as usize) == piece
+                    })
+                    .count()
+            };
+            1 + run(1) + run(-1) >= self.connect_length
+        })
+    }
+
+    /// Guess how good the position is for a player from the lines each player could still complete
+    fn evaluate(&self, player: usize) -> i32 {
+        let length = self.connect_length as isize;
+        let mut score = 0;
+        for (dc, dr) in [(1, 0), (0, 1), (1, 1), (1, -1)] {
+            for column in 0..self.width as isize {
+                for row in 0..self.height as isize {
+                    let end = (column + dc * (length - 1), row + dr * (length - 1));
+                    if end.0 >= self.width as isize || end.1 < 0 || end.1 >= self.height as isize {
+                        continue;
+                    }
+                    let mut owner = 0;
+                    let mut pieces = 0;
+                    let mut shared = false;
+                    for step in 0..length {
+                        let cell =
+                            self.cell((column + dc * step) as usize, (row + dr * step) as usize);
+                        if cell == 0 {
+                            continue;
+                        }
+                        if owner != 0 && owner != cell {
+                            shared = true;
+                            break;
+                        }
+                        owner = cell;
+                        pieces += 1;
+                    }
+                    if shared || pieces == 0 {
+                        continue;
+                    }
+                    match owner as usize - 1 == player {
+                        true => score += pieces * pieces,
+                        false => score -= pieces * pieces,
+                    }
+                }
+            }
+        }
+        score
+    }
+
+    fn key(&self) -> Vec<u8> {
+        let mut key = self.cells.clone();
+        key.push(self.turn as u8);
+        key
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Bound {
+    Exact,
+    /// The score is at least this
+    Lower,
+    /// The score is at most this
+    Upper,
+}
+
+/// What was learned about a position searched before
+struct Entry {
+    depth: u32,
+    score: i32,
+    bound: Bound,
+    best_column: Option<usize>,
+}
+
+struct Search {
+    /// The player the scores belong to
+    root: usize,
+    table: HashMap<Vec<u8>, Entry>,
+}
+
+impl Search {
+    /// Score a position for the root player, looking `depth` moves ahead of it
+    fn search(
+        &mut self,
+        position: &mut Position,
+        depth: u32,
+        ply: i32,
+        mut alpha: i32,
+        mut beta: i32,
+    ) -> i32 {
+        if position.is_full() {
+            return 0;
+        }
+        if depth == 0 {
+            return position.evaluate(self.root);
+        }
+
+        let key = position.key();
+        let (original_alpha, original_beta) = (alpha, beta);
+        let mut first = None;
+        if let Some(entry) = self.table.get(&key) {
+            first = entry.best_column;
+            if entry.depth >= depth {
+                let score = from_table(entry.score, ply);
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower => alpha = alpha.max(score),
+                    Bound::Upper => beta = beta.min(score),
+                }
+                if alpha >= beta {
+                    return score;
+                }
+            }
+        }
+
+        let maximizing = position.turn == self.root;
+        let mut best_score = if maximizing { i32::MIN } else { i32::MAX };
+        let mut best_column = None;
+        for column in position.columns(first) {
+            let mover = position.turn;
+            let score = match position.play(column) {
+                true if mover == self.root => WIN_SCORE - (ply + 1),
+                true => -(WIN_SCORE - (ply + 1)),
+                false => self.search(position, depth - 1, ply + 1, alpha, beta),
+            };
+            position.undo(column);
+
+            if maximizing && score > best_score || !maximizing && score < best_score {
+                best_score = score;
+                best_column = Some(column);
+            }
+            match maximizing {
+                true => alpha = alpha.max(score),
+                false => beta = beta.min(score),
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        let bound = if best_score <= original_alpha {
+            Bound::Upper
+        } else if best_score >= original_beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.table.insert(
+            key,
+            Entry {
+                depth,
+                score: to_table(best_score, ply),
+                bound,
+                best_column,
+            },
+        );
+        best_score
+    }
+
+    /// Follow the best columns found from the position, until a move ends the game or nothing more is known
+    fn principal_variation(&self, position: &mut Position, depth: u32) -> Vec<usize> {
+        let mut line = Vec::new();
+        while line.len() < depth as usize {
+            let column = match self
+                .table
+                .get(&position.key())
+                .and_then(|entry| entry.best_column)
+            {
+                Some(column) => column,
+                None => break,
+            };
+            line.push(column);
+            if position.play(column) || position.is_full() {
+                break;
+            }
+        }
+        for column in line.iter().rev() {
+            position.undo(*column);
+        }
+        line
+    }
+}
+
+/// Wins and losses are kept in the table as moves from the position rather than from the root
+fn to_table(score: i32, ply: i32) -> i32 {
+    match score {
+        score if score >= PROVEN_SCORE => score + ply,
+        score if score <= -PROVEN_SCORE => score - ply,
+        score => score,
+    }
+}
+
+fn from_table(score: i32, ply: i32) -> i32 {
+    match score {
+        score if score >= PROVEN_SCORE => score - ply,
+        score if score <= -PROVEN_SCORE => score + ply,
+        score => score,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::create_game_board;
+
+    /// A game of two players where the columns were played in turn, starting with player 0
+    fn played(columns: &[usize]) -> GameState {
+        let mut state = GameState {
+            board: create_game_board(7, 6),
+            turn_index: 0,
+            player_turn_order: vec![String::from("alice"), String::from("bob")],
+            connect_length: 4,
+            moves: Vec::new(),
+        };
+        for (turn, column) in columns.iter().enumerate() {
+            state.play(*column, turn % 2).unwrap();
+        }
+        state
+    }
+
+    #[test]
+    fn wins_are_taken() {
+        let analysis = analyze(&played(&[0, 6, 0, 6, 0, 5]), 4);
+        assert_eq!(analysis.best_column, Some(0));
+        assert_eq!(analysis.score, WIN_SCORE - 1);
+        assert_eq!(analysis.win_probability, 1.0);
+        assert_eq!(analysis.principal_variation, [0]);
+    }
+
+    #[test]
+    fn threats_are_blocked() {
+        let analysis = analyze(&played(&[0, 6, 0, 6, 0]), 4);
+        assert_eq!(analysis.best_column, Some(0));
+        assert!(analysis.score.abs() < PROVEN_SCORE);
+        assert_eq!(analysis.principal_variation.first(), Some(&0));
+    }
+
+    #[test]
+    fn forced_losses_are_found() {
+        // alice has two ways to finish a line along the bottom, so bob cannot block both
+        let analysis = analyze(&played(&[2, 2, 3, 3, 4, 4]), 4);
+        assert_eq!(analysis.score, WIN_SCORE - 1);
+
+        let analysis = analyze(&played(&[2, 2, 3, 3, 4]), 4);
+        assert!(analysis.score <= -PROVEN_SCORE);
+        assert_eq!(analysis.win_probability, 0.0);
+    }
+
+    #[test]
+    fn full_boards_have_no_move() {
+        let mut state = played(&[]);
+        for column in state.board.iter_mut() {
+            for (row, cell) in column.iter_mut().enumerate() {
+                *cell = row % 2;
+            }
+        }
+        let analysis = analyze(&state, 4);
+        assert_eq!(analysis.best_column, None);
+        assert_eq!(analysis.score, 0);
+        assert_eq!(analysis.win_probability, 0.5);
+    }
+}
@@ -1,10 +1,21 @@
-use std::usize::MAX;
-
 #[derive(Clone)]
 pub struct GameState {
     pub board: GameBoard,
     pub turn_index: usize,
     pub player_turn_order: Vec<String>,
+    /// How many pieces in a row win the game
+    pub connect_length: usize,
+    /// Every move played so far, in order
+    pub moves: Vec<Move>,
+}
+
+/// A single piece dropped into the board
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Move {
+    pub player_index: usize,
+    pub column: usize,
+    /// Whether the player was given a hint before playing it
+    pub hinted: bool,
 }
 
 impl GameState {
@@ -20,15 +31,27 @@ impl GameState {
             }
         };
 
-        match grid_column.iter().position(|id| *id == MAX) {
+        match grid_column.iter().position(|id| *id == usize::MAX) {
             // update the owner of the grid cell
             Some(play_index) => {
                 // increment index and wrap around
                 self.turn_index = (self.turn_index + 1) % self.player_turn_order.len();
                 // update the ownership in the board
                 self.board[column_index][play_index] = player_index;
+                self.moves.push(Move {
+                    player_index,
+                    column: column_index,
+                    hinted: false,
+                });
                 // determine if this is a winning move
-                Ok(self.find_connected(4, column_index, play_index, player_index))
+                Ok(
+                    self.find_connected(
+                        self.connect_length,
+                        column_index,
+                        play_index,
+                        player_index,
+                    ),
+                )
             }
             // error
             None => Err(format!(
@@ -53,9 +76,9 @@ impl GameState {
             if length > 0
                 && ((column as i8 + direction[0]) as usize) < board.len()
                 && ((row as i8 + direction[1]) as usize)
-                    < board[((column as i8 + direction[0]) as usize)].len()
-                && board[((column as i8 + direction[0]) as usize)]
-                    [((row as i8 + direction[1]) as usize)]
+                    < board[(column as i8 + direction[0]) as usize].len()
+                && board[(column as i8 + direction[0]) as usize]
+                    [(row as i8 + direction[1]) as usize]
                     == player_code
             {
                 find_recursive(
@@ -89,6 +112,13 @@ impl GameState {
             >= length - 1
     }
 
+    /// Whether every cell of the board has been played, so that no one can win anymore
+    pub fn is_full(&self) -> bool {
+        self.board
+            .iter()
+            .all(|column| column.iter().all(|cell| *cell != usize::MAX))
+    }
+
     pub fn get_turn_player(&self) -> String {
         self.player_turn_order[self.turn_index].clone()
     }
@@ -98,10 +128,13 @@ impl GameState {
     }
 }
 
+/// Names of the rule sets which games can be played with
+pub const RULE_VARIANTS: [&str; 1] = ["classic"];
+
 type GameBoard = Vec<Vec<usize>>;
 /// Create the 2D grid board for a Game
 pub fn create_game_board(width: usize, height: usize) -> GameBoard {
-    vec![vec![MAX; height]; width]
+    vec![vec![usize::MAX; height]; width]
 }
 
 #[cfg(test)]
@@ -0,0 +1,433 @@
+//! Optional player accounts, so that a player keeps the same identity across connections
+//!
+//! Guests may still connect with any id which no account has claimed
+use crate::{data_types, metrics, persistence};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use warp::hyper::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+type Result<T> = std::result::Result<T, Rejection>;
+
+/// The cookie a login token is kept in by browsers
+const TOKEN_COOKIE: &str = "citd_token";
+
+const MIN_USERNAME_LENGTH: usize = 3;
+const MAX_USERNAME_LENGTH: usize = 20;
+const MIN_PASSWORD_LENGTH: usize = 8;
+/// Argon2 only reads this much of a password anyway, and hashing more wastes time
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Account {
+    username: String,
+    password_hash: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+struct AccountsFile {
+    accounts: Vec<Account>,
+}
+
+/// A token handed out on login, valid until it expires or the player logs out
+struct Login {
+    username: String,
+    expires: Instant,
+}
+
+/// Every registered account and the tokens of those logged in
+///
+/// Tokens are only held in memory, so players log in again after a restart
+#[derive(Default)]
+pub struct Accounts {
+    path: Option<PathBuf>,
+    /// Keyed by the lowercase username, so names only differing in case are one account
+    accounts: HashMap<String, Account>,
+    logins: HashMap<String, Login>,
+}
+
+impl Accounts {
+    /// Read the accounts from their file, which may not exist yet
+    pub fn load(path: Option<PathBuf>) -> std::result::Result<Accounts, String> {
+        let mut accounts = Accounts {
+            path,
+            ..Accounts::default()
+        };
+        let path = match &accounts.path {
+            Some(path) if path.exists() => path,
+            _ => return Ok(accounts),
+        };
+        let contents = fs::read(path)
+            .map_err(|e| format!("could not read accounts {}: {}", path.display(), e))?;
+        let file: AccountsFile = serde_json::from_slice(&contents)
+            .map_err(|e| format!("invalid accounts {}: {}", path.display(), e))?;
+        for account in file.accounts {
+            accounts
+                .accounts
+                .insert(account.username.to_lowercase(), account);
+        }
+        Ok(accounts)
+    }
+
+    pub fn is_registered(&self, username: &str) -> bool {
+        self.accounts.contains_key(&username.to_lowercase())
+    }
+
+    fn password_hash(&self, username: &str) -> Option<(String, String)> {
+        self.accounts
+            .get(&username.to_lowercase())
+            .map(|account| (account.username.clone(), account.password_hash.clone()))
+    }
+
+    /// Add an account and write every account back to the file
+    ///
+    /// Returns false when the username was taken in the meantime
+    fn insert(&mut self, account: Account) -> std::result::Result<bool, String> {
+        let key = account.username.to_lowercase();
+        if self.accounts.contains_key(&key) {
+            return Ok(false);
+        }
+        self.accounts.insert(key.clone(), account);
+        if let Err(e) = self.save() {
+            self.accounts.remove(&key);
+            return Err(e);
+        }
+        Ok(true)
+    }
+
+    fn save(&self) -> std::result::Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut file = AccountsFile::default();
+        file.accounts.extend(self.accounts.values().cloned());
+        let contents = serde_json::to_vec(&file).map_err(|e| e.to_string())?;
+        persistence::write_atomically(path, &contents)
+            .map_err(|e| format!("could not write accounts {}: {}", path.display(), e))
+    }
+
+    /// Hand out a new login token for an account
+    fn issue_token(&mut self, username: &str, ttl: Duration) -> String {
+        let now = Instant::now();
+        self.logins.retain(|_, login| login.expires > now);
+        let token = nanoid!(32);
+        self.logins.insert(
+            token.clone(),
+            Login {
+                username: username.to_string(),
+                expires: now + ttl,
+            },
+        );
+        token
+    }
+
+    fn revoke(&mut self, token: &str) {
+        self.logins.remove(token);
+    }
+
+    /// The username a token was issued to, while it has not expired
+    pub fn authenticate(&self, token: &str) -> Option<String> {
+        self.logins
+            .get(token)
+            .filter(|login| login.expires > Instant::now())
+            .map(|login| login.username.clone())
+    }
+}
+
+/// The reason a username cannot be registered, if any
+fn username_problem(username: &str) -> Option<String> {
+    let length = username.chars().count();
+    if !(MIN_USERNAME_LENGTH..=MAX_USERNAME_LENGTH).contains(&length) {
+        return Some(format!(
+            "usernames must be between {} and {} characters",
+            MIN_USERNAME_LENGTH, MAX_USERNAME_LENGTH
+        ));
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Some(String::from(
+            "usernames may only use ASCII letters, digits, '_' and '-'",
+        ));
+    }
+    None
+}
+
+/// The reason a password cannot be used, if any
+fn password_problem(password: &str) -> Option<String> {
+    let length = password.chars().count();
+    if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&length) {
+        return Some(format!(
+            "passwords must be between {} and {} characters",
+            MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH
+        ));
+    }
+    None
+}
+
+fn hash_password(password: &str) -> std::result::Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+/// The login token of a request, from either its cookie or a bearer token
+pub fn token() -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Clone {
+    warp::cookie::optional::<String>(TOKEN_COOKIE)
+        .and(warp::header::optional::<String>("authorization"))
+        .map(|cookie: Option<String>, authorization: Option<String>| {
+            authorization
+                .and_then(|value| value.strip_prefix("Bearer ").map(str::to_string))
+                .or(cookie)
+        })
+}
+
+/// Check that a connection may use the given client id
+///
+/// Ids claimed by an account need that account's token, while any other id is open to guests
+pub async fn authorize(
+    id: &str,
+    token: Option<&str>,
+    accounts: &data_types::SafeAccounts,
+) -> std::result::Result<(), (StatusCode, &'static str)> {
+    let accounts = metrics::read(accounts, "accounts").await;
+    match token {
+        Some(token) => match accounts.authenticate(token) {
+            Some(username) if username == id => Ok(()),
+            Some(_) => Err((
+                StatusCode::FORBIDDEN,
+                "the connection id must be the username of the account",
+            )),
+            None => Err((StatusCode::UNAUTHORIZED, "the login has expired")),
+        },
+        None if accounts.is_registered(id) => Err((
+            StatusCode::UNAUTHORIZED,
+            "this id belongs to an account, log in to use it",
+        )),
+        None => Ok(()),
+    }
+}
+
+/// A username and password sent to register or log in
+#[derive(Deserialize)]
+pub struct Credentials {
+    username: String,
+    password: String,
+}
+
+/// The reply to a successful login
+#[derive(Serialize)]
+struct LoginView {
+    username: String,
+    token: String,
+}
+
+/// Reply with a new login token, which browsers also keep as a cookie
+fn logged_in(
+    username: String,
+    token: String,
+    status: StatusCode,
+    ttl: Duration,
+) -> warp::reply::Response {
+    let cookie = format!(
+        "{}={}; Max-Age={}; Path=/api; HttpOnly; SameSite=Strict",
+        TOKEN_COOKIE,
+        token,
+        ttl.as_secs()
+    );
+    let reply = warp::reply::with_status(warp::reply::json(&LoginView { username, token }), status);
+    warp::reply::with_header(reply, "set-cookie", cookie).into_response()
+}
+
+fn problem(message: impl Into<String>, status: StatusCode) -> warp::reply::Response {
+    warp::reply::with_status(message.into(), status).into_response()
+}
+
+/// Creates an account and logs into it
+pub async fn register(
+    credentials: Credentials,
+    accounts: data_types::SafeAccounts,
+    config: data_types::SharedConfig,
+) -> Result<warp::reply::Response> {
+    let Credentials { username, password } = credentials;
+    if let Some(message) = username_problem(&username).or_else(|| password_problem(&password)) {
+        return Ok(problem(message, StatusCode::BAD_REQUEST));
+    }
+    if metrics::read(&accounts, "accounts")
+        .await
+        .is_registered(&username)
+    {
+        return Ok(problem("the username is taken", StatusCode::CONFLICT));
+    }
+
+    // hashing is slow on purpose, so it is kept off the async workers
+    let password_hash = tokio::task::spawn_blocking(move || hash_password(&password))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|hash| hash);
+    let password_hash = match password_hash {
+        Ok(password_hash) => password_hash,
+        Err(e) => {
+            warn!(error = %e, "could not hash password");
+            return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    };
+
+    let mut accounts = metrics::write(&accounts, "accounts").await;
+    let account = Account {
+        username: username.clone(),
+        password_hash,
+    };
+    match accounts.insert(account) {
+        Ok(true) => {}
+        Ok(false) => return Ok(problem("the username is taken", StatusCode::CONFLICT)),
+        Err(e) => {
+            warn!(error = %e, "could not save accounts");
+            return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    }
+    info!(username = %username, "registered account");
+    let ttl = config.accounts.token_ttl();
+    let token = accounts.issue_token(&username, ttl);
+    Ok(logged_in(username, token, StatusCode::CREATED, ttl))
+}
+
+/// Logs into an account with its password
+pub async fn login(
+    credentials: Credentials,
+    accounts: data_types::SafeAccounts,
+    config: data_types::SharedConfig,
+) -> Result<warp::reply::Response> {
+    let Credentials { username, password } = credentials;
+    let account = metrics::read(&accounts, "accounts")
+        .await
+        .password_hash(&username);
+    let (username, password_hash) = match account {
+        Some(account) => account,
+        None => {
+            metrics::error("login_failed");
+            return Ok(problem(
+                "wrong username or password",
+                StatusCode::UNAUTHORIZED,
+            ));
+        }
+    };
+
+    let verified = tokio::task::spawn_blocking(move || verify_password(&password, &password_hash))
+        .await
+        .unwrap_or(false);
+    if !verified {
+        info!(username = %username, "rejected login with wrong password");
+        metrics::error("login_failed");
+        return Ok(problem(
+            "wrong username or password",
+            StatusCode::UNAUTHORIZED,
+        ));
+    }
+
+    let ttl = config.accounts.token_ttl();
+    let token = metrics::write(&accounts, "accounts")
+        .await
+        .issue_token(&username, ttl);
+    Ok(logged_in(username, token, StatusCode::OK, ttl))
+}
+
+/// Ends a login, so its token can no longer be used
+pub async fn logout(
+    token: Option<String>,
+    accounts: data_types::SafeAccounts,
+) -> Result<impl Reply> {
+    if let Some(token) = token {
+        metrics::write(&accounts, "accounts").await.revoke(&token);
+    }
+    let cookie = format!(
+        "{}=; Max-Age=0; Path=/api; HttpOnly; SameSite=Strict",
+        TOKEN_COOKIE
+    );
+    Ok(warp::reply::with_header(
+        StatusCode::NO_CONTENT,
+        "set-cookie",
+        cookie,
+    ))
+}
+
+/// Shows which account a request is logged into
+pub async fn me(
+    token: Option<String>,
+    accounts: data_types::SafeAccounts,
+) -> Result<warp::reply::Response> {
+    let username = match token {
+        Some(token) => metrics::read(&accounts, "accounts")
+            .await
+            .authenticate(&token),
+        None => None,
+    };
+    Ok(match username {
+        Some(username) => {
+            warp::reply::json(&serde_json::json!({ "username": username })).into_response()
+        }
+        None => StatusCode::UNAUTHORIZED.into_response(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passwords_only_verify_against_their_own_hash() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("wrong horse", &hash));
+        assert!(!verify_password("correct horse", "not a hash"));
+    }
+
+    #[test]
+    fn usernames_are_unique_ignoring_case() {
+        let mut accounts = Accounts::default();
+        let account = |username: &str| Account {
+            username: username.to_string(),
+            password_hash: String::new(),
+        };
+        assert_eq!(accounts.insert(account("Alice")), Ok(true));
+        assert_eq!(accounts.insert(account("alice")), Ok(false));
+        assert!(accounts.is_registered("ALICE"));
+        assert!(username_problem("al").is_some());
+        assert!(username_problem("alice bob").is_some());
+        assert!(username_problem("alice_bob").is_none());
+    }
+
+    #[test]
+    fn tokens_stop_working_once_revoked_or_expired() {
+        let mut accounts = Accounts::default();
+        let token = accounts.issue_token("alice", Duration::from_secs(60));
+        assert_eq!(accounts.authenticate(&token), Some(String::from("alice")));
+        accounts.revoke(&token);
+        assert_eq!(accounts.authenticate(&token), None);
+
+        let expired = accounts.issue_token("alice", Duration::ZERO);
+        assert_eq!(accounts.authenticate(&expired), None);
+    }
+}
@@ -0,0 +1,240 @@
+//! Operator endpoints to inspect and intervene in the live sessions
+//!
+//! Every route requires the `[admin]` credentials as HTTP Basic auth,
+//! and none of them exist while the credentials are unset
+use crate::shared_types::{EventBuilder, ServerEventCode};
+use crate::{data_types, game_engine, handler, metrics, ws};
+use connect_in_the_dark::types::GameState;
+use serde::{Deserialize, Serialize};
+use sessions::session_types::Session;
+use std::collections::HashMap;
+use tracing::{info, warn};
+use warp::hyper::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+type Result<T> = std::result::Result<T, Rejection>;
+
+/// A request to the admin routes without the right credentials
+#[derive(Debug)]
+struct Unauthorized;
+impl warp::reject::Reject for Unauthorized {}
+
+/// A session as operators see it
+#[derive(Serialize)]
+pub struct SessionView {
+    id: String,
+    owner: String,
+    client_statuses: HashMap<String, bool>,
+    display_names: HashMap<String, String>,
+    game_in_progress: bool,
+    /// Only given when a single session is requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    game: Option<GameView>,
+}
+
+/// The full state of a game, without any of the board masked
+#[derive(Serialize)]
+pub struct GameView {
+    board: Vec<Vec<usize>>,
+    turn_index: usize,
+    player_turn_order: Vec<String>,
+    connect_length: usize,
+}
+
+impl SessionView {
+    fn new(session: Session, game_state: Option<GameState>, with_game: bool) -> Self {
+        SessionView {
+            id: session.id,
+            owner: session.owner,
+            client_statuses: session.client_statuses,
+            display_names: session.display_names,
+            game_in_progress: game_state.is_some(),
+            game: game_state.filter(|_| with_game).map(|game_state| GameView {
+                board: game_state.board,
+                turn_index: game_state.turn_index,
+                player_turn_order: game_state.player_turn_order,
+                connect_length: game_state.connect_length,
+            }),
+        }
+    }
+}
+
+/// A message for every connected client
+#[derive(Deserialize)]
+pub struct Announcement {
+    message: String,
+}
+
+/// Let a request through only when it carries the admin credentials
+pub fn authorized(
+    config: data_types::SharedConfig,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let expected = match (&config.admin.username, &config.admin.password) {
+        (Some(username), Some(password)) => Some(format!(
+            "Basic {}",
+            base64::encode(format!("{}:{}", username, password))
+        )),
+        _ => None,
+    };
+
+    warp::header::optional::<String>("authorization")
+        .and_then(move |given: Option<String>| {
+            let expected = expected.clone();
+            async move {
+                match (expected, given) {
+                    (None, _) => Err(warp::reject::not_found()),
+                    (Some(expected), Some(given))
+                        if constant_time_eq(expected.as_bytes(), given.as_bytes()) =>
+                    {
+                        Ok(())
+                    }
+                    _ => {
+                        warn!("rejected admin request with wrong credentials");
+                        Err(warp::reject::custom(Unauthorized))
+                    }
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Ask for credentials when a request to the admin routes had the wrong ones
+pub async fn recover(rejection: Rejection) -> Result<warp::reply::Response> {
+    if rejection.find::<Unauthorized>().is_some() {
+        metrics::error("admin_unauthorized");
+        let reply = warp::reply::with_status("unauthorized", StatusCode::UNAUTHORIZED);
+        return Ok(
+            warp::reply::with_header(reply, "www-authenticate", "Basic realm=\"admin\"")
+                .into_response(),
+        );
+    }
+    Err(rejection)
+}
+
+/// Compare secrets without leaking how much of them matched through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Lists every live session with its members
+pub async fn list_sessions(sessions: data_types::SafeSessions) -> Result<impl Reply> {
+    let handles = metrics::read(&sessions, "sessions").await.handles();
+    let mut views = Vec::new();
+    // sessions which stopped since the handles were taken are left out
+    for handle in handles {
+        if let Some((session, game_state)) = handle.snapshot().await {
+            views.push(SessionView::new(session, game_state, false));
+        }
+    }
+    Ok(warp::reply::json(&views))
+}
+
+/// Shows a single session along with its unmasked game
+pub async fn get_session(
+    session_id: String,
+    sessions: data_types::SafeSessions,
+) -> Result<warp::reply::Response> {
+    let session_id = match handler::decode_path_id(&session_id) {
+        Ok(session_id) => session_id,
+        Err(status) => return Ok(status.into_response()),
+    };
+    let handle = metrics::read(&sessions, "sessions").await.get(&session_id);
+    let snapshot = match handle {
+        Some(handle) => handle.snapshot().await,
+        None => None,
+    };
+    Ok(match snapshot {
+        Some((session, game_state)) => {
+            warp::reply::json(&SessionView::new(session, game_state, true)).into_response()
+        }
+        None => StatusCode::NOT_FOUND.into_response(),
+    })
+}
+
+/// Ends the game of a session without a winner
+pub async fn end_game(
+    session_id: String,
+    sessions: data_types::SafeSessions,
+) -> Result<impl Reply> {
+    let session_id = match handler::decode_path_id(&session_id) {
+        Ok(session_id) => session_id,
+        Err(status) => return Ok(status),
+    };
+    let handle = metrics::read(&sessions, "sessions").await.get(&session_id);
+    let ended = match handle {
+        Some(handle) => handle.end_game().await,
+        None => None,
+    };
+    Ok(match ended {
+        Some(true) => {
+            info!(session_id = %session_id, "admin ended game");
+            StatusCode::NO_CONTENT
+        }
+        Some(false) => StatusCode::CONFLICT,
+        None => StatusCode::NOT_FOUND,
+    })
+}
+
+/// Removes a client from a session, as though they had left it
+pub async fn kick_client(
+    session_id: String,
+    client_id: String,
+    sessions: data_types::SafeSessions,
+) -> Result<impl Reply> {
+    let session_id = match handler::decode_path_id(&session_id) {
+        Ok(session_id) => session_id,
+        Err(status) => return Ok(status),
+    };
+    let client_id = match handler::decode_path_id(&client_id) {
+        Ok(client_id) => client_id,
+        Err(status) => return Ok(status),
+    };
+    let handle = metrics::read(&sessions, "sessions").await.get(&session_id);
+    let session_empty = match handle {
+        Some(handle) => handle.kick(&client_id).await.flatten(),
+        None => None,
+    };
+    let session_empty = match session_empty {
+        Some(session_empty) => session_empty,
+        None => return Ok(StatusCode::NOT_FOUND),
+    };
+
+    info!(session_id = %session_id, client_id = %client_id, "admin kicked client");
+    metrics::write(&sessions, "sessions")
+        .await
+        .leave(&client_id);
+    if session_empty {
+        ws::cleanup_session(&session_id, &sessions).await;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Sends an announcement to every connected client
+pub async fn broadcast(
+    announcement: Announcement,
+    clients: data_types::SafeClients,
+) -> Result<impl Reply> {
+    info!(message = %announcement.message, "admin broadcast announcement");
+    game_engine::notify_all(
+        &EventBuilder::default()
+            .event_code(ServerEventCode::Announcement)
+            .message(announcement.message)
+            .build()
+            .unwrap(),
+        &clients,
+    )
+    .await;
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_only_match_exactly() {
+        assert!(constant_time_eq(b"Basic abc", b"Basic abc"));
+        assert!(!constant_time_eq(b"Basic abc", b"Basic abd"));
+        assert!(!constant_time_eq(b"Basic abc", b"Basic abcd"));
+    }
+}
@@ -0,0 +1,503 @@
+//! Every finished game, kept for anyone to look back on or to replay move by move (see `replay`)
+//!
+//! Games are appended to a file of one JSON record per line, which is rewritten without
+//! the oldest games once it holds twice as many as `archive.max_games`
+use crate::config::ArchiveConfig;
+use crate::replay::Replay;
+use crate::session_actor::FinishedGame;
+use crate::{data_types, handler, metrics, persistence};
+use connect_in_the_dark::solver;
+use connect_in_the_dark::types::{create_game_board, GameState, RULE_VARIANTS};
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, info, warn};
+use warp::hyper::StatusCode;
+use warp::{Rejection, Reply};
+
+type Result<T> = std::result::Result<T, Rejection>;
+
+/// How many games are listed unless asked for fewer
+const MAX_LISTED_GAMES: usize = 100;
+const DEFAULT_LISTED_GAMES: usize = 50;
+
+/// A piece dropped by a player
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ArchivedMove {
+    pub player: String,
+    pub column: usize,
+    /// Whether the player was given a hint before playing it
+    #[serde(default)]
+    pub hinted: bool,
+}
+
+/// Everything needed to replay a finished game
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ArchivedGame {
+    pub id: String,
+    pub session_id: String,
+    /// The players in their turn order
+    pub players: Vec<String>,
+    /// `None` when the board filled up without anyone winning
+    pub winner: Option<String>,
+    pub rule_variant: String,
+    pub board_width: usize,
+    pub board_height: usize,
+    pub connect_length: usize,
+    /// Oldest first
+    pub moves: Vec<ArchivedMove>,
+    /// Seconds since the Unix epoch
+    pub started_at: u64,
+    pub finished_at: u64,
+}
+
+impl ArchivedGame {
+    fn new(game: FinishedGame) -> Self {
+        ArchivedGame {
+            id: nanoid!(10),
+            moves: game
+                .moves
+                .iter()
+                .map(|played| ArchivedMove {
+                    player: game.players[played.player_index].clone(),
+                    column: played.column,
+                    hinted: played.hinted,
+                })
+                .collect(),
+            session_id: game.session_id,
+            players: game.players,
+            winner: game.winner,
+            rule_variant: RULE_VARIANTS[0].to_string(),
+            board_width: game.board_width,
+            board_height: game.board_height,
+            connect_length: game.connect_length,
+            started_at: game.started_at,
+            finished_at: game.finished_at,
+        }
+    }
+
+    /// The game as it stood before any move was played
+    pub fn initial_state(&self) -> GameState {
+        GameState {
+            board: create_game_board(self.board_width, self.board_height),
+            turn_index: 0,
+            player_turn_order: self.players.clone(),
+            connect_length: self.connect_length,
+            moves: Vec::new(),
+        }
+    }
+
+    /// The game as it stood once this many of its moves were played
+    pub fn state_after(&self, moves: usize) -> std::result::Result<GameState, String> {
+        let mut state = self.initial_state();
+        for (turn, played) in self.moves.iter().take(moves).enumerate() {
+            let player_index = state
+                .get_player_index(&played.player)
+                .ok_or_else(|| format!("move {} was played by a stranger", turn))?;
+            state.play(played.column, player_index)?;
+        }
+        Ok(state)
+    }
+
+    fn summary(&self) -> GameSummary {
+        GameSummary {
+            id: self.id.clone(),
+            players: self.players.clone(),
+            winner: self.winner.clone(),
+            rule_variant: self.rule_variant.clone(),
+            move_count: self.moves.len(),
+            hints_used: self.moves.iter().filter(|played| played.hinted).count(),
+            started_at: self.started_at,
+            finished_at: self.finished_at,
+        }
+    }
+}
+
+/// A row of the list of archived games
+#[derive(Serialize, Debug, PartialEq)]
+pub struct GameSummary {
+    id: String,
+    players: Vec<String>,
+    winner: Option<String>,
+    rule_variant: String,
+    move_count: usize,
+    hints_used: usize,
+    started_at: u64,
+    finished_at: u64,
+}
+
+/// Which archived games to list
+#[derive(Deserialize, Default)]
+pub struct GamesParams {
+    /// Only games this client played in
+    player: Option<String>,
+    /// Only games which finished at or after this many seconds since the Unix epoch
+    since: Option<u64>,
+    /// Only games which finished at or before this many seconds since the Unix epoch
+    until: Option<u64>,
+    limit: Option<usize>,
+}
+
+impl GamesParams {
+    fn matches(&self, game: &ArchivedGame) -> bool {
+        self.player
+            .as_ref()
+            .is_none_or(|player| game.players.contains(player))
+            && self.since.is_none_or(|since| game.finished_at >= since)
+            && self.until.is_none_or(|until| game.finished_at <= until)
+    }
+}
+
+/// The latest finished games, oldest first
+#[derive(Default)]
+pub struct Archive {
+    path: Option<PathBuf>,
+    games: VecDeque<ArchivedGame>,
+    /// How many games the file holds, including those already dropped from memory
+    written: usize,
+}
+
+impl Archive {
+    /// Read the archived games from their file, which may not exist yet
+    pub fn load(
+        path: Option<PathBuf>,
+        config: &ArchiveConfig,
+    ) -> std::result::Result<Archive, String> {
+        let mut archive = Archive {
+            path,
+            ..Archive::default()
+        };
+        let path = match &archive.path {
+            Some(path) if path.exists() => path,
+            _ => return Ok(archive),
+        };
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("could not read archive {}: {}", path.display(), e))?;
+        for (number, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let game: ArchivedGame = serde_json::from_str(line).map_err(|e| {
+                format!(
+                    "invalid archive {} line {}: {}",
+                    path.display(),
+                    number + 1,
+                    e
+                )
+            })?;
+            archive.games.push_back(game);
+            archive.written += 1;
+        }
+        archive.trim(config.max_games);
+        Ok(archive)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&ArchivedGame> {
+        self.games.iter().find(|game| game.id == id)
+    }
+
+    /// The games matching the filters, newest first
+    fn list(&self, params: &GamesParams) -> Vec<GameSummary> {
+        let limit = params
+            .limit
+            .unwrap_or(DEFAULT_LISTED_GAMES)
+            .min(MAX_LISTED_GAMES);
+        self.games
+            .iter()
+            .rev()
+            .filter(|game| params.matches(game))
+            .take(limit)
+            .map(ArchivedGame::summary)
+            .collect()
+    }
+
+    /// Keep a game, appending it to the file
+    fn record(
+        &mut self,
+        game: ArchivedGame,
+        config: &ArchiveConfig,
+    ) -> std::result::Result<(), String> {
+        let line = serde_json::to_string(&game).map_err(|e| e.to_string())?;
+        self.games.push_back(game);
+        self.trim(config.max_games);
+
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if self.written + 1 > config.max_games * 2 {
+            return self.compact();
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", line))
+            .map_err(|e| format!("could not write archive {}: {}", path.display(), e))?;
+        self.written += 1;
+        Ok(())
+    }
+
+    fn trim(&mut self, max_games: usize) {
+        while self.games.len() > max_games {
+            self.games.pop_front();
+        }
+    }
+
+    /// Rewrite the file with only the games still kept
+    fn compact(&mut self) -> std::result::Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut contents = Vec::new();
+        for game in &self.games {
+            serde_json::to_writer(&mut contents, game).map_err(|e| e.to_string())?;
+            contents.push(b'\n');
+        }
+        persistence::write_atomically(path, &contents)
+            .map_err(|e| format!("could not write archive {}: {}", path.display(), e))?;
+        self.written = self.games.len();
+        Ok(())
+    }
+}
+
+/// Archive every finished game for as long as the server runs
+pub async fn record_finished_games(
+    mut finished_games: broadcast::Receiver<FinishedGame>,
+    archive: data_types::SafeArchive,
+    config: data_types::SharedConfig,
+) {
+    loop {
+        let game = match finished_games.recv().await {
+            Ok(game) => game,
+            Err(RecvError::Lagged(missed)) => {
+                warn!(
+                    missed,
+                    "finished games were not archived, too many arrived at once"
+                );
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+
+        let game = ArchivedGame::new(game);
+        let (id, session_id) = (game.id.clone(), game.session_id.clone());
+        let recorded = metrics::write(&archive, "archive")
+            .await
+            .record(game, &config.archive);
+        match recorded {
+            Ok(()) => info!(%session_id, id, "archived game"),
+            Err(e) => error!(error = %e, "could not save archived game"),
+        }
+    }
+}
+
+/// Lists the archived games, newest first
+pub async fn list(params: GamesParams, archive: data_types::SafeArchive) -> Result<impl Reply> {
+    let games = metrics::read(&archive, "archive").await.list(&params);
+    Ok(warp::reply::json(&games))
+}
+
+/// Shows every move of an archived game
+pub async fn get(id: String, archive: data_types::SafeArchive) -> Result<impl Reply> {
+    let id = match handler::decode_path_id(&id) {
+        Ok(id) => id,
+        Err(status) => return Ok(status.into_response()),
+    };
+    Ok(match metrics::read(&archive, "archive").await.get(&id) {
+        Some(game) => warp::reply::json(game).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    })
+}
+
+/// Downloads an archived game with the board of every move, as a whole and as each player saw it
+pub async fn replay(id: String, archive: data_types::SafeArchive) -> Result<impl Reply> {
+    let id = match handler::decode_path_id(&id) {
+        Ok(id) => id,
+        Err(status) => return Ok(status.into_response()),
+    };
+    let game = match metrics::read(&archive, "archive").await.get(&id) {
+        Some(game) => game.clone(),
+        None => return Ok(StatusCode::NOT_FOUND.into_response()),
+    };
+    Ok(match Replay::of(game) {
+        Ok(replay) => warp::reply::with_header(
+            warp::reply::json(&replay),
+            "content-disposition",
+            format!("attachment; filename=\"game-{}.json\"", id),
+        )
+        .into_response(),
+        Err(e) => {
+            error!(id, error = %e, "archived game could not be replayed");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    })
+}
+
+/// Which position of an archived game to analyze
+#[derive(Deserialize)]
+pub struct AnalyzeParams {
+    game: String,
+    /// How many moves of the game were played before the position, counted from 0
+    turn: usize,
+    /// How many moves ahead to search, never more than the configured depth
+    depth: Option<u32>,
+}
+
+/// What the engine makes of a position of an archived game
+#[derive(Serialize)]
+struct AnalysisView {
+    game: String,
+    turn: usize,
+    /// The player to move
+    player: String,
+    /// The column the player went on to play
+    played_column: usize,
+    best_column: Option<usize>,
+    score: i32,
+    win_probability: f64,
+    principal_variation: Vec<usize>,
+    depth: u32,
+}
+
+/// Finds the best move and how likely a win is for a position of a finished game, as though the whole board were in view
+///
+/// Games are only analyzed once they are archived, so that nobody can have a game in progress solved for them
+pub async fn analyze(
+    params: AnalyzeParams,
+    archive: data_types::SafeArchive,
+    config: data_types::SharedConfig,
+) -> Result<warp::reply::Response> {
+    let game = match metrics::read(&archive, "archive").await.get(&params.game) {
+        Some(game) => game.clone(),
+        None => return Ok(StatusCode::NOT_FOUND.into_response()),
+    };
+    let played = match game.moves.get(params.turn) {
+        Some(played) => played.clone(),
+        None => {
+            let message = format!("the game only has {} moves", game.moves.len());
+            return Ok(warp::reply::with_status(message, StatusCode::BAD_REQUEST).into_response());
+        }
+    };
+    let state = match game.state_after(params.turn) {
+        Ok(state) => state,
+        Err(e) => {
+            error!(id = %game.id, error = %e, "archived game could not be replayed");
+            return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+    };
+
+    // the search can take a while, so it is kept off the async workers
+    let max_depth = params.depth.map_or(config.analysis.max_depth, |depth| {
+        depth.clamp(1, config.analysis.max_depth)
+    });
+    let analysis =
+        match tokio::task::spawn_blocking(move || solver::analyze(&state, max_depth)).await {
+            Ok(analysis) => analysis,
+            Err(e) => {
+                error!(id = %game.id, error = %e, "could not analyze game");
+                return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+            }
+        };
+    Ok(warp::reply::json(&AnalysisView {
+        game: game.id,
+        turn: params.turn,
+        player: played.player,
+        played_column: played.column,
+        best_column: analysis.best_column,
+        score: analysis.score,
+        win_probability: analysis.win_probability,
+        principal_variation: analysis.principal_variation,
+        depth: analysis.depth,
+    })
+    .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use connect_in_the_dark::types::Move;
+
+    fn finished_game(winner: &str, finished_at: u64) -> FinishedGame {
+        FinishedGame {
+            session_id: String::from("ABCDE"),
+            players: vec![String::from("alice"), String::from("bob")],
+            winner: Some(winner.to_string()),
+            board_width: 7,
+            board_height: 6,
+            connect_length: 4,
+            moves: [0, 1, 0, 1, 0, 1, 0]
+                .iter()
+                .enumerate()
+                .map(|(turn, column)| Move {
+                    player_index: turn % 2,
+                    column: *column,
+                    hinted: false,
+                })
+                .collect(),
+            started_at: finished_at - 60,
+            finished_at,
+        }
+    }
+
+    #[test]
+    fn games_are_filtered_and_capped() {
+        let config = ArchiveConfig {
+            max_games: 2,
+            ..ArchiveConfig::default()
+        };
+        let mut archive = Archive::default();
+        for at in [100, 200, 300] {
+            archive
+                .record(ArchivedGame::new(finished_game("alice", at)), &config)
+                .unwrap();
+        }
+
+        let listed = archive.list(&GamesParams::default());
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[0].finished_at, 300);
+        assert_eq!(listed[0].move_count, 7);
+
+        let since = GamesParams {
+            since: Some(250),
+            ..GamesParams::default()
+        };
+        assert_eq!(archive.list(&since).len(), 1);
+        let stranger = GamesParams {
+            player: Some(String::from("carol")),
+            ..GamesParams::default()
+        };
+        assert!(archive.list(&stranger).is_empty());
+    }
+
+    #[test]
+    fn file_keeps_the_latest_games() {
+        let path = std::env::temp_dir().join(format!("citd-archive-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let config = ArchiveConfig {
+            path: Some(path.clone()),
+            max_games: 2,
+        };
+        let mut archive = Archive::load(config.path.clone(), &config).unwrap();
+        for at in [100, 200, 300, 400, 500] {
+            archive
+                .record(ArchivedGame::new(finished_game("bob", at)), &config)
+                .unwrap();
+        }
+        // the fifth game went past twice the cap, so the file was rewritten
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        let restored = Archive::load(config.path.clone(), &config).unwrap();
+        assert_eq!(
+            restored.list(&GamesParams::default()),
+            archive.list(&GamesParams::default())
+        );
+        assert_eq!(restored.games.front().unwrap().finished_at, 400);
+        fs::remove_file(&path).unwrap();
+    }
+}
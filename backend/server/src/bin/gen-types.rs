@@ -0,0 +1,17 @@
+//! Writes the TypeScript definitions and JSON Schema of the websocket protocol into the frontend
+use server::codegen::{json_schema, typescript_definitions, JSON_SCHEMA_PATH, TYPESCRIPT_PATH};
+use std::{fs, path::Path};
+
+fn main() {
+    let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+
+    for (relative_path, contents) in [
+        (TYPESCRIPT_PATH, typescript_definitions()),
+        (JSON_SCHEMA_PATH, json_schema()),
+    ] {
+        let path = crate_dir.join(relative_path);
+        fs::write(&path, contents)
+            .unwrap_or_else(|e| panic!("failed to write {}: {}", path.display(), e));
+        println!("[INFO] wrote {}", path.display());
+    }
+}
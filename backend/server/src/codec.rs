@@ -0,0 +1,187 @@
+use crate::shared_types::{ClientEvent, ServerEvent};
+use serde::{de::DeserializeOwned, Serialize};
+use sessions::session_types::Encoding;
+use sessions::sink::Outbound;
+use warp::ws::Message;
+
+/// Find the encoding a client asked for by the name it used in its `Hello`
+pub fn parse_encoding(name: &str) -> Option<Encoding> {
+    match name {
+        "json" => Some(Encoding::Json),
+        "msgpack" => Some(Encoding::MessagePack),
+        _ => None,
+    }
+}
+
+/// The name clients use to ask for an encoding
+pub fn encoding_name(encoding: Encoding) -> &'static str {
+    match encoding {
+        Encoding::Json => "json",
+        Encoding::MessagePack => "msgpack",
+    }
+}
+
+/// Serialize an event into a message of the client's encoding
+///
+/// JSON is sent as text and MessagePack as binary
+pub fn encode(event: &ServerEvent, encoding: Encoding) -> Outbound {
+    let bytes = to_bytes(event, encoding);
+    match encoding {
+        Encoding::Json => Outbound::Text(String::from_utf8(bytes).unwrap()),
+        Encoding::MessagePack => Outbound::Binary(bytes),
+    }
+}
+
+/// Deserialize a client event from a websocket frame
+///
+/// Clients may send either encoding at any time, which is told apart by the frame type
+pub fn decode(message: &Message) -> Result<ClientEvent, String> {
+    if message.is_text() {
+        from_bytes(message.as_bytes(), Encoding::Json)
+    } else if message.is_binary() {
+        from_bytes(message.as_bytes(), Encoding::MessagePack)
+    } else {
+        Err(format!("unsupported websocket frame: {:?}", message))
+    }
+}
+
+fn to_bytes<T: Serialize>(value: &T, encoding: Encoding) -> Vec<u8> {
+    match encoding {
+        Encoding::Json => serde_json::to_vec(value).unwrap(),
+        // fields are written by name so that both encodings share the same shape
+        Encoding::MessagePack => rmp_serde::to_vec_named(value).unwrap(),
+    }
+}
+
+fn from_bytes<T: DeserializeOwned>(bytes: &[u8], encoding: Encoding) -> Result<T, String> {
+    match encoding {
+        Encoding::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+        Encoding::MessagePack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared_types::{
+        ClientEventCode, ClientEventDataBuilder, EventBuilder, GameData, ServerEventCode,
+        ServerEventDataBuilder,
+    };
+    use serde::de::DeserializeOwned;
+    use std::collections::HashMap;
+
+    const ENCODINGS: [Encoding; 2] = [Encoding::Json, Encoding::MessagePack];
+
+    /// Every variant of an event code enum, found by probing its discriminants
+    fn all_codes<Code: DeserializeOwned>() -> Vec<Code> {
+        (0..=u8::MAX)
+            .filter_map(|code| serde_json::from_str(&code.to_string()).ok())
+            .collect()
+    }
+
+    fn round_trip<T: Serialize + DeserializeOwned>(value: &T, encoding: Encoding) -> T {
+        from_bytes(&to_bytes(value, encoding), encoding).unwrap()
+    }
+
+    /// Fails when a field of the event data was left out, so that every field is round tripped
+    fn assert_filled<T: Serialize>(event: &T) {
+        let value = serde_json::to_value(event).unwrap();
+        for (field, data) in value["data"].as_object().unwrap() {
+            assert!(!data.is_null(), "the test event leaves out {}", field);
+        }
+    }
+
+    #[test]
+    fn every_server_event_round_trips() {
+        let codes = all_codes::<ServerEventCode>();
+        assert!(!codes.is_empty());
+
+        for code in codes {
+            let event: ServerEvent = EventBuilder::default()
+                .event_code(code)
+                .request_id("request")
+                .message("message")
+                .data(
+                    ServerEventDataBuilder::default()
+                        .session_id("ABCDE")
+                        .client_id("client")
+                        .session_client_ids(vec![String::from("client")])
+                        .game_data(GameData {
+                            turn_index: 1,
+                            player_order: vec![String::from("client")],
+                            play_indexes: vec![vec![0, usize::MAX]],
+                        })
+                        .protocol_version(1u32)
+                        .rule_variants(vec![String::from("classic")])
+                        .features(vec![String::from("msgpack")])
+                        .encoding("msgpack")
+                        .connection_token("token")
+                        .shutdown_seconds(10u32)
+                        .display_names(HashMap::from([(
+                            String::from("client"),
+                            String::from("Client"),
+                        )]))
+                        .tournament_id("tournament")
+                        .column(3usize)
+                        .hints_left(2u32)
+                        .hints_used(HashMap::from([(String::from("client"), 1u32)]))
+                        .build()
+                        .unwrap(),
+                )
+                .build()
+                .unwrap();
+
+            assert_filled(&event);
+            for encoding in ENCODINGS {
+                assert_eq!(round_trip(&event, encoding), event);
+            }
+        }
+    }
+
+    #[test]
+    fn every_client_event_round_trips() {
+        let codes = all_codes::<ClientEventCode>();
+        assert!(!codes.is_empty());
+
+        for code in codes {
+            let event: ClientEvent = EventBuilder::default()
+                .event_code(code)
+                .request_id("request")
+                .data(
+                    ClientEventDataBuilder::default()
+                        .target_ids(vec![String::from("client")])
+                        .session_id("ABCDE")
+                        .column(3usize)
+                        .protocol_version(1u32)
+                        .encoding("json")
+                        .display_name("Client")
+                        .player_count(2u32)
+                        .rule_variant("classic")
+                        .hint_budget(3u32)
+                        .build()
+                        .unwrap(),
+                )
+                .build()
+                .unwrap();
+
+            assert_filled(&event);
+            for encoding in ENCODINGS {
+                assert_eq!(round_trip(&event, encoding), event);
+            }
+        }
+    }
+
+    #[test]
+    fn frames_match_their_encoding() {
+        let event: ServerEvent = EventBuilder::default()
+            .event_code(ServerEventCode::Acknowledged)
+            .build()
+            .unwrap();
+
+        assert!(matches!(encode(&event, Encoding::Json), Outbound::Text(_)));
+        assert!(matches!(
+            encode(&event, Encoding::MessagePack),
+            Outbound::Binary(_)
+        ));
+    }
+}
@@ -0,0 +1,249 @@
+//! Renders the protocol types in `shared_types` for clients which are not written in Rust
+use crate::shared_types::{
+    ClientEvent, ClientEventCode, ClientEventData, Event, GameData, ServerEvent, ServerEventCode,
+    ServerEventData,
+};
+use schemars::gen::SchemaGenerator;
+use serde_json::json;
+use ts_rs::TS;
+
+/// Location of the generated TypeScript module, relative to the server crate
+pub const TYPESCRIPT_PATH: &str = "../../frontend/utils/shared-types.ts";
+/// Location of the generated JSON Schema, relative to the server crate
+pub const JSON_SCHEMA_PATH: &str = "../../frontend/utils/shared-types.schema.json";
+
+const TYPESCRIPT_HEADER: &str = "/**
+ * This file contains type defintions which are shared between the front and back end applications
+ *
+ * It is generated from backend/server/src/shared_types.rs with `cargo run --bin gen-types`
+ * and should not be edited by hand
+ */";
+
+/// The TypeScript module declaring every type that travels over the websocket
+pub fn typescript_definitions() -> String {
+    let declarations = [
+        format_declaration(&GameData::decl()),
+        format_declaration(&Event::<ServerEventCode, ServerEventData>::decl()),
+        format!("type ServerEvent = {};", ServerEvent::name()),
+        format!("type ClientEvent = {};", ClientEvent::name()),
+        format_declaration(&ServerEventData::decl()),
+        format_declaration(&ClientEventData::decl()),
+        document_variants(
+            &format_declaration(&ServerEventCode::decl()),
+            "ServerEventCode",
+        ),
+        document_variants(
+            &format_declaration(&ClientEventCode::decl()),
+            "ClientEventCode",
+        ),
+    ];
+
+    let mut module = String::from(TYPESCRIPT_HEADER);
+    for declaration in declarations.iter() {
+        module.push_str("\n\nexport ");
+        module.push_str(declaration);
+    }
+    module.push('\n');
+    module
+}
+
+/// A JSON Schema which validates any `ServerEvent` or `ClientEvent`
+pub fn json_schema() -> String {
+    let mut generator = SchemaGenerator::default();
+    let server_event = generator.subschema_for::<ServerEvent>();
+    let client_event = generator.subschema_for::<ClientEvent>();
+
+    let schema = json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "Event",
+        "description": "An event sent over the websocket by either the server or a client",
+        "anyOf": [server_event, client_event],
+        "definitions": generator.take_definitions(),
+    });
+    serde_json::to_string_pretty(&schema).unwrap() + "\n"
+}
+
+/// Spread the members of a declaration onto their own indented lines
+fn format_declaration(declaration: &str) -> String {
+    let mut lines = vec![String::new()];
+    let mut braces = 0;
+    let mut angles = 0;
+    let mut previous = ' ';
+    let mut in_comment = false;
+
+    for c in declaration.trim().chars() {
+        // doc comments are only broken at their own line breaks
+        if in_comment {
+            match c {
+                '\n' if braces == 1 => lines.push(String::new()),
+                _ => lines.last_mut().unwrap().push(c),
+            }
+            in_comment = !(previous == '*' && c == '/');
+            previous = c;
+            continue;
+        }
+        match c {
+            '{' if braces == 0 => {
+                braces += 1;
+                lines.last_mut().unwrap().push(c);
+                lines.push(String::new());
+            }
+            '}' if braces == 1 => {
+                braces -= 1;
+                lines.push(String::from("}"));
+            }
+            ',' | '\n' if braces == 1 && angles == 0 => {
+                lines.last_mut().unwrap().push(c);
+                lines.push(String::new());
+            }
+            _ => {
+                match c {
+                    '{' => braces += 1,
+                    '}' => braces -= 1,
+                    '<' => angles += 1,
+                    '>' if previous != '=' => angles -= 1,
+                    '*' if previous == '/' => in_comment = true,
+                    _ => {}
+                }
+                lines.last_mut().unwrap().push(c);
+            }
+        }
+        previous = c;
+    }
+
+    // declarations without a body such as aliases stay on one line
+    if lines.len() == 1 {
+        return lines.remove(0);
+    }
+
+    let last = lines.len() - 1;
+    let members = lines[1..last]
+        .iter()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(format_member);
+
+    std::iter::once(lines[0].trim_end().to_string())
+        .chain(members)
+        .chain(std::iter::once(lines[last].clone()))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// The source of the protocol types, read for the doc comments ts-rs leaves off enum variants
+const SHARED_TYPES_SOURCE: &str = include_str!("shared_types.rs");
+
+/// The doc comment lines above each variant of an enum in `shared_types`, by variant name
+fn variant_comments(enum_name: &str) -> Vec<(String, Vec<String>)> {
+    let header = format!("pub enum {} {{", enum_name);
+    let mut comments = Vec::new();
+    let mut pending = Vec::new();
+
+    let body = SHARED_TYPES_SOURCE
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| *line != header)
+        .skip(1)
+        .take_while(|line| *line != "}");
+    for line in body {
+        if line.starts_with("/**") || line.starts_with('*') {
+            pending.push(line.to_string());
+        } else if !pending.is_empty() {
+            let name = line
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .next()
+                .unwrap_or_default();
+            comments.push((name.to_string(), std::mem::take(&mut pending)));
+        }
+    }
+    comments
+}
+
+/// Put the doc comments of an enum in `shared_types` back above its formatted variants
+fn document_variants(declaration: &str, enum_name: &str) -> String {
+    let comments = variant_comments(enum_name);
+    let mut lines = Vec::new();
+    for line in declaration.lines() {
+        let name = line
+            .trim()
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .next()
+            .unwrap_or_default();
+        if let Some((_, comment)) = comments.iter().find(|(variant, _)| variant == name) {
+            lines.extend(comment.iter().map(|line| format_member(line)));
+        }
+        lines.push(line.to_string());
+    }
+    lines.join("\n")
+}
+
+/// Indent a single member, unquoting enum member names and terminating it with a comma
+fn format_member(member: &str) -> String {
+    if member.starts_with("/**") {
+        return format!("    {}", member);
+    }
+    if member.starts_with('*') {
+        return format!("     {}", member);
+    }
+
+    let member = member.trim_end_matches(',');
+    let member = match member
+        .strip_prefix('"')
+        .and_then(|rest| rest.split_once('"'))
+    {
+        Some((name, rest)) if name.chars().all(|c| c.is_ascii_alphanumeric()) => {
+            format!("{}{}", name, rest)
+        }
+        _ => member.to_string(),
+    };
+    format!("    {},", member)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, path::Path};
+
+    fn checked_in(relative_path: &str) -> String {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(relative_path);
+        fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("could not read {}: {}", path.display(), e))
+            .replace("\r\n", "\n")
+    }
+
+    #[test]
+    fn typescript_definitions_are_current() {
+        assert!(
+            checked_in(TYPESCRIPT_PATH) == typescript_definitions(),
+            "the TypeScript definitions are stale, run `cargo run --bin gen-types`"
+        );
+    }
+
+    #[test]
+    fn doc_comments_are_kept_whole() {
+        let declaration = "type Data = { \n/**\n * Either `json`, or `msgpack`\n */\nencoding?: string | null, count: number, };";
+        assert_eq!(
+            format_declaration(declaration),
+            "type Data = {\n    /**\n     * Either `json`, or `msgpack`\n     */\n    encoding?: string | null,\n    count: number,\n};"
+        );
+    }
+
+    #[test]
+    fn enum_variants_keep_their_section_comments() {
+        let declaration = document_variants(
+            &format_declaration(&ClientEventCode::decl()),
+            "ClientEventCode",
+        );
+        assert!(declaration.starts_with(
+            "enum ClientEventCode {\n    /**\n     * Session Related Events\n     */\n    JoinSession = 1,"
+        ));
+    }
+
+    #[test]
+    fn json_schema_is_current() {
+        assert!(
+            checked_in(JSON_SCHEMA_PATH) == json_schema(),
+            "the JSON Schema is stale, run `cargo run --bin gen-types`"
+        );
+    }
+}
@@ -0,0 +1,911 @@
+//! Server settings, layered from defaults, an optional TOML file, environment variables and CLI flags
+use clap::Parser;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{fmt, fs};
+
+/// Every setting of the server
+///
+/// Each section can be given in a TOML file, and each setting can be overridden
+/// by an environment variable or CLI flag (see `server --help`)
+#[derive(Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub sessions: SessionConfig,
+    pub rules: RulesConfig,
+    pub grace: GraceConfig,
+    pub limits: LimitsConfig,
+    pub admin: AdminConfig,
+    pub persistence: PersistenceConfig,
+    pub health: HealthConfig,
+    pub accounts: AccountsConfig,
+    pub ratings: RatingsConfig,
+    pub matchmaking: MatchmakingConfig,
+    pub archive: ArchiveConfig,
+    pub analysis: AnalysisConfig,
+    pub hints: HintsConfig,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// The address the server listens on
+    pub bind_address: SocketAddr,
+    /// The directory holding the built frontend
+    pub static_dir: PathBuf,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: SocketAddr::from(([0, 0, 0, 0], 8000)),
+            static_dir: PathBuf::from("dist"),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// The number of characters in a generated session id
+    pub id_length: usize,
+    /// The characters a generated session id is drawn from
+    pub id_alphabet: String,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            id_length: 5,
+            id_alphabet: String::from("ABCDEFGHIJKLMNOPQRSTUVWXYZ"),
+        }
+    }
+}
+
+/// The rules a game is played with unless the session says otherwise
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RulesConfig {
+    /// At most 127, as is the height
+    pub board_width: usize,
+    pub board_height: usize,
+    /// How many pieces in a row win the game
+    pub connect_length: usize,
+    /// How many players a session needs before its game can start
+    pub min_players: usize,
+}
+
+impl Default for RulesConfig {
+    fn default() -> Self {
+        RulesConfig {
+            board_width: 7,
+            board_height: 6,
+            connect_length: 4,
+            min_players: 2,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GraceConfig {
+    /// How long a new connection has to send its `Hello` before it is closed
+    pub handshake_seconds: u64,
+    /// How long a session is kept after its last active client disconnects,
+    /// giving them a chance to reconnect
+    pub empty_session_seconds: u64,
+    /// How long clients are warned before the server closes their connections on shutdown
+    pub shutdown_seconds: u32,
+    /// How often the server pings each websocket
+    pub heartbeat_seconds: u64,
+    /// How long a websocket may stay silent before it is considered dead and closed
+    pub heartbeat_timeout_seconds: u64,
+}
+
+impl Default for GraceConfig {
+    fn default() -> Self {
+        GraceConfig {
+            handshake_seconds: 10,
+            empty_session_seconds: 0,
+            shutdown_seconds: 10,
+            heartbeat_seconds: 15,
+            heartbeat_timeout_seconds: 45,
+        }
+    }
+}
+
+impl GraceConfig {
+    pub fn handshake(&self) -> Duration {
+        Duration::from_secs(self.handshake_seconds)
+    }
+
+    pub fn empty_session(&self) -> Duration {
+        Duration::from_secs(self.empty_session_seconds)
+    }
+
+    pub fn shutdown(&self) -> Duration {
+        Duration::from_secs(self.shutdown_seconds.into())
+    }
+
+    pub fn heartbeat(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_seconds)
+    }
+
+    pub fn heartbeat_timeout(&self) -> Duration {
+        Duration::from_secs(self.heartbeat_timeout_seconds)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// The most clients which may be connected at once
+    pub max_clients: usize,
+    /// The most sessions which may be live at once
+    pub max_sessions: usize,
+    /// The most clients which may be members of a single session, at most 255
+    pub max_session_clients: usize,
+    /// The largest event a client may send, in bytes
+    pub max_message_bytes: usize,
+    /// How many events a client may send at once before being held to `events_per_second`
+    pub event_burst: u32,
+    /// The steady rate of events a client may send
+    pub events_per_second: u32,
+    /// How many sessions a client may create in any minute
+    pub sessions_per_minute: u32,
+    /// How many events in a row may go over the limits before the client is disconnected
+    pub max_rate_violations: u32,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_clients: 1000,
+            max_sessions: 500,
+            max_session_clients: 8,
+            max_message_bytes: 16 * 1024,
+            event_burst: 20,
+            events_per_second: 10,
+            sessions_per_minute: 10,
+            max_rate_violations: 10,
+        }
+    }
+}
+
+/// Credentials for the admin endpoints, which stay disabled while these are unset
+#[derive(Deserialize, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl fmt::Debug for AdminConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdminConfig")
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "********"))
+            .finish()
+    }
+}
+
+/// Where the live sessions are saved on shutdown and restored from on boot
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PersistenceConfig {
+    /// Nothing is saved while this is unset
+    pub snapshot_path: Option<PathBuf>,
+    /// How long a restored session waits for its clients to reconnect
+    pub restore_grace_seconds: u64,
+}
+
+impl Default for PersistenceConfig {
+    fn default() -> Self {
+        PersistenceConfig {
+            snapshot_path: None,
+            restore_grace_seconds: 60,
+        }
+    }
+}
+
+impl PersistenceConfig {
+    pub fn restore_grace(&self) -> Duration {
+        Duration::from_secs(self.restore_grace_seconds)
+    }
+}
+
+/// When the server stops reporting itself as ready
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    /// How long a lock on a shared resource may be held before the server is considered stuck
+    pub max_lock_held_millis: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            max_lock_held_millis: 1000,
+        }
+    }
+}
+
+impl HealthConfig {
+    pub fn max_lock_held(&self) -> Duration {
+        Duration::from_millis(self.max_lock_held_millis)
+    }
+}
+
+/// Where player accounts are kept and how long a login lasts
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountsConfig {
+    /// Accounts are forgotten when the server stops while this is unset
+    pub path: Option<PathBuf>,
+    /// How long a login token stays valid
+    pub token_ttl_hours: u64,
+}
+
+impl Default for AccountsConfig {
+    fn default() -> Self {
+        AccountsConfig {
+            path: None,
+            token_ttl_hours: 24 * 7,
+        }
+    }
+}
+
+impl AccountsConfig {
+    pub fn token_ttl(&self) -> Duration {
+        Duration::from_secs(self.token_ttl_hours * 60 * 60)
+    }
+}
+
+/// How the skill of registered players is rated from their finished games
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RatingsConfig {
+    /// Ratings are forgotten when the server stops while this is unset
+    pub path: Option<PathBuf>,
+    /// The rating of a player before their first game
+    pub initial_rating: u32,
+    /// The most a rating can move in a single game
+    pub k_factor: u32,
+    /// How many of their latest games are kept for each player
+    pub recent_games: usize,
+}
+
+impl Default for RatingsConfig {
+    fn default() -> Self {
+        RatingsConfig {
+            path: None,
+            initial_rating: 1500,
+            k_factor: 32,
+            recent_games: 20,
+        }
+    }
+}
+
+/// How queued clients are grouped into games
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MatchmakingConfig {
+    /// How often the queue is searched for games
+    pub interval_millis: u64,
+    /// How far apart in rating the players of a game may be once queued
+    pub initial_rating_window: u32,
+    /// How much further apart they may be for every second they have waited
+    pub rating_window_growth: u32,
+    /// How far apart they may ever be
+    pub max_rating_window: u32,
+}
+
+impl Default for MatchmakingConfig {
+    fn default() -> Self {
+        MatchmakingConfig {
+            interval_millis: 1000,
+            initial_rating_window: 100,
+            rating_window_growth: 10,
+            max_rating_window: 400,
+        }
+    }
+}
+
+impl MatchmakingConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_millis)
+    }
+
+    /// How far apart in rating a client who has waited this long may be matched
+    pub fn rating_window(&self, waited: Duration) -> f64 {
+        let widened = self.initial_rating_window as f64
+            + self.rating_window_growth as f64 * waited.as_secs_f64();
+        widened.min(self.max_rating_window as f64)
+    }
+}
+
+/// Where finished games are kept for anyone to look back on
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArchiveConfig {
+    /// Finished games are forgotten when the server stops while this is unset
+    pub path: Option<PathBuf>,
+    /// How many games are kept before the oldest ones are dropped
+    pub max_games: usize,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        ArchiveConfig {
+            path: None,
+            max_games: 10000,
+        }
+    }
+}
+
+/// How finished games are analyzed
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnalysisConfig {
+    /// How many moves ahead a position is searched
+    pub max_depth: u32,
+}
+
+impl Default for AnalysisConfig {
+    fn default() -> Self {
+        AnalysisConfig { max_depth: 8 }
+    }
+}
+
+/// How players may ask for a suggested move during a game
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HintsConfig {
+    /// How many hints each player gets in a game unless its session owner chooses otherwise
+    pub default_budget: u32,
+    /// The most hints a session owner can give each player
+    pub max_budget: u32,
+    /// How many boards fitting what the player can see are drawn for each hint
+    pub samples: usize,
+    /// How many moves ahead each of those boards is searched
+    pub depth: u32,
+}
+
+impl Default for HintsConfig {
+    fn default() -> Self {
+        HintsConfig {
+            default_budget: 3,
+            max_budget: 10,
+            samples: 32,
+            depth: 2,
+        }
+    }
+}
+
+/// The smallest message limit which still fits every event a client needs to send
+const MIN_MESSAGE_BYTES: usize = 256;
+
+/// The deepest search the public analysis endpoint may be configured to run
+const MAX_ANALYSIS_DEPTH: u32 = 12;
+
+/// The most boards a single hint may be worked out over
+const MAX_HINT_SAMPLES: usize = 256;
+
+/// The deepest search a hint may run on each of its boards
+const MAX_HINT_DEPTH: u32 = 6;
+
+/// The shortest admin password which is accepted
+const MIN_ADMIN_PASSWORD_LENGTH: usize = 12;
+
+/// The longest side of a board, as the game steps between cells in signed bytes
+const MAX_BOARD_SIDE: usize = i8::MAX as usize;
+
+/// The most members of a session, as the solver stores each player in a byte
+const MAX_SESSION_CLIENTS: usize = u8::MAX as usize;
+
+/// Flags and environment variables, which take precedence over the config file
+#[derive(Parser, Debug, Default)]
+#[command(about = "Connect in the Dark game server")]
+struct Args {
+    /// Path of a TOML config file
+    #[arg(long, env = "CITD_CONFIG")]
+    config: Option<PathBuf>,
+    /// Address to listen on, e.g. 0.0.0.0:8000
+    #[arg(long, env = "CITD_BIND_ADDRESS")]
+    bind_address: Option<SocketAddr>,
+    /// Port to listen on, replacing the port of the bind address
+    #[arg(long, env = "PORT")]
+    port: Option<u16>,
+    /// Directory holding the built frontend
+    #[arg(long, env = "CITD_STATIC_DIR")]
+    static_dir: Option<PathBuf>,
+    /// Number of characters in a generated session id
+    #[arg(long, env = "CITD_SESSION_ID_LENGTH")]
+    session_id_length: Option<usize>,
+    /// Characters a generated session id is drawn from
+    #[arg(long, env = "CITD_SESSION_ID_ALPHABET")]
+    session_id_alphabet: Option<String>,
+    #[arg(long, env = "CITD_BOARD_WIDTH")]
+    board_width: Option<usize>,
+    #[arg(long, env = "CITD_BOARD_HEIGHT")]
+    board_height: Option<usize>,
+    /// Pieces in a row needed to win
+    #[arg(long, env = "CITD_CONNECT_LENGTH")]
+    connect_length: Option<usize>,
+    /// Players needed to start a game
+    #[arg(long, env = "CITD_MIN_PLAYERS")]
+    min_players: Option<usize>,
+    /// Seconds a new connection has to send its Hello
+    #[arg(long, env = "CITD_HANDSHAKE_SECONDS")]
+    handshake_seconds: Option<u64>,
+    /// Seconds a session is kept after its last active client disconnects
+    #[arg(long, env = "CITD_EMPTY_SESSION_SECONDS")]
+    empty_session_seconds: Option<u64>,
+    /// Seconds clients are warned before the server closes their connections on shutdown
+    #[arg(long, env = "CITD_SHUTDOWN_SECONDS")]
+    shutdown_seconds: Option<u32>,
+    /// Seconds between the pings sent to each websocket
+    #[arg(long, env = "CITD_HEARTBEAT_SECONDS")]
+    heartbeat_seconds: Option<u64>,
+    /// Seconds a websocket may stay silent before it is closed
+    #[arg(long, env = "CITD_HEARTBEAT_TIMEOUT_SECONDS")]
+    heartbeat_timeout_seconds: Option<u64>,
+    #[arg(long, env = "CITD_MAX_CLIENTS")]
+    max_clients: Option<usize>,
+    #[arg(long, env = "CITD_MAX_SESSIONS")]
+    max_sessions: Option<usize>,
+    #[arg(long, env = "CITD_MAX_SESSION_CLIENTS")]
+    max_session_clients: Option<usize>,
+    /// Largest event a client may send, in bytes
+    #[arg(long, env = "CITD_MAX_MESSAGE_BYTES")]
+    max_message_bytes: Option<usize>,
+    /// Events a client may send at once
+    #[arg(long, env = "CITD_EVENT_BURST")]
+    event_burst: Option<u32>,
+    /// Steady rate of events a client may send
+    #[arg(long, env = "CITD_EVENTS_PER_SECOND")]
+    events_per_second: Option<u32>,
+    /// Sessions a client may create in any minute
+    #[arg(long, env = "CITD_SESSIONS_PER_MINUTE")]
+    sessions_per_minute: Option<u32>,
+    /// Events in a row over the limits before a client is disconnected
+    #[arg(long, env = "CITD_MAX_RATE_VIOLATIONS")]
+    max_rate_violations: Option<u32>,
+    #[arg(long, env = "CITD_ADMIN_USERNAME")]
+    admin_username: Option<String>,
+    #[arg(long, env = "CITD_ADMIN_PASSWORD", hide_env_values = true)]
+    admin_password: Option<String>,
+    /// File the live sessions are saved to on shutdown and restored from on boot
+    #[arg(long, env = "CITD_SNAPSHOT_PATH")]
+    snapshot_path: Option<PathBuf>,
+    /// Seconds a restored session waits for its clients to reconnect
+    #[arg(long, env = "CITD_RESTORE_GRACE_SECONDS")]
+    restore_grace_seconds: Option<u64>,
+    /// Milliseconds a lock may be held before the server reports itself as not ready
+    #[arg(long, env = "CITD_MAX_LOCK_HELD_MILLIS")]
+    max_lock_held_millis: Option<u64>,
+    /// File the player accounts are kept in
+    #[arg(long, env = "CITD_ACCOUNTS_PATH")]
+    accounts_path: Option<PathBuf>,
+    /// Hours a login token stays valid
+    #[arg(long, env = "CITD_TOKEN_TTL_HOURS")]
+    token_ttl_hours: Option<u64>,
+    /// File the ratings of registered players are kept in
+    #[arg(long, env = "CITD_RATINGS_PATH")]
+    ratings_path: Option<PathBuf>,
+    /// Most a rating can move in a single game
+    #[arg(long, env = "CITD_K_FACTOR")]
+    k_factor: Option<u32>,
+    /// Rating difference past which queued clients are never matched
+    #[arg(long, env = "CITD_MAX_RATING_WINDOW")]
+    max_rating_window: Option<u32>,
+    /// File finished games are archived in
+    #[arg(long, env = "CITD_ARCHIVE_PATH")]
+    archive_path: Option<PathBuf>,
+    /// Moves ahead a finished game is analyzed
+    #[arg(long, env = "CITD_ANALYSIS_DEPTH")]
+    analysis_depth: Option<u32>,
+    /// Hints each player gets in a game unless its owner chooses otherwise
+    #[arg(long, env = "CITD_HINT_BUDGET")]
+    hint_budget: Option<u32>,
+}
+
+impl Config {
+    /// Build the config for this process from its arguments, environment and config file
+    ///
+    /// Every problem found is returned so that they can all be reported at once
+    pub fn load() -> Result<Config, Vec<String>> {
+        Config::from_args(Args::parse())
+    }
+
+    fn from_args(args: Args) -> Result<Config, Vec<String>> {
+        let mut config = match &args.config {
+            Some(path) => Config::from_file(path).map_err(|e| vec![e])?,
+            None => Config::default(),
+        };
+        config.apply(args);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &PathBuf) -> Result<Config, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("could not read config file {}: {}", path.display(), e))?;
+        toml::from_str(&contents)
+            .map_err(|e| format!("invalid config file {}: {}", path.display(), e))
+    }
+
+    /// Override settings with any that were given as flags or environment variables
+    fn apply(&mut self, args: Args) {
+        fn set<T>(setting: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                *setting = value;
+            }
+        }
+
+        set(&mut self.server.bind_address, args.bind_address);
+        if let Some(port) = args.port {
+            self.server.bind_address.set_port(port);
+        }
+        set(&mut self.server.static_dir, args.static_dir);
+        set(&mut self.sessions.id_length, args.session_id_length);
+        set(&mut self.sessions.id_alphabet, args.session_id_alphabet);
+        set(&mut self.rules.board_width, args.board_width);
+        set(&mut self.rules.board_height, args.board_height);
+        set(&mut self.rules.connect_length, args.connect_length);
+        set(&mut self.rules.min_players, args.min_players);
+        set(&mut self.grace.handshake_seconds, args.handshake_seconds);
+        set(
+            &mut self.grace.empty_session_seconds,
+            args.empty_session_seconds,
+        );
+        set(&mut self.grace.shutdown_seconds, args.shutdown_seconds);
+        set(&mut self.grace.heartbeat_seconds, args.heartbeat_seconds);
+        set(
+            &mut self.grace.heartbeat_timeout_seconds,
+            args.heartbeat_timeout_seconds,
+        );
+        set(&mut self.limits.max_clients, args.max_clients);
+        set(&mut self.limits.max_sessions, args.max_sessions);
+        set(
+            &mut self.limits.max_session_clients,
+            args.max_session_clients,
+        );
+        set(&mut self.limits.max_message_bytes, args.max_message_bytes);
+        set(&mut self.limits.event_burst, args.event_burst);
+        set(&mut self.limits.events_per_second, args.events_per_second);
+        set(
+            &mut self.limits.sessions_per_minute,
+            args.sessions_per_minute,
+        );
+        set(
+            &mut self.limits.max_rate_violations,
+            args.max_rate_violations,
+        );
+        if args.admin_username.is_some() {
+            self.admin.username = args.admin_username;
+        }
+        if args.admin_password.is_some() {
+            self.admin.password = args.admin_password;
+        }
+        if args.snapshot_path.is_some() {
+            self.persistence.snapshot_path = args.snapshot_path;
+        }
+        set(
+            &mut self.persistence.restore_grace_seconds,
+            args.restore_grace_seconds,
+        );
+        set(
+            &mut self.health.max_lock_held_millis,
+            args.max_lock_held_millis,
+        );
+        if args.accounts_path.is_some() {
+            self.accounts.path = args.accounts_path;
+        }
+        set(&mut self.accounts.token_ttl_hours, args.token_ttl_hours);
+        if args.ratings_path.is_some() {
+            self.ratings.path = args.ratings_path;
+        }
+        set(&mut self.ratings.k_factor, args.k_factor);
+        set(
+            &mut self.matchmaking.max_rating_window,
+            args.max_rating_window,
+        );
+        if args.archive_path.is_some() {
+            self.archive.path = args.archive_path;
+        }
+        set(&mut self.analysis.max_depth, args.analysis_depth);
+        set(&mut self.hints.default_budget, args.hint_budget);
+    }
+
+    /// Check that the settings make sense together
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        if self.sessions.id_length == 0 {
+            errors.push(String::from("sessions.id_length must be at least 1"));
+        }
+        let alphabet: HashSet<char> = self.sessions.id_alphabet.chars().collect();
+        if alphabet.len() < 2 {
+            errors.push(String::from(
+                "sessions.id_alphabet must have at least 2 distinct characters",
+            ));
+        }
+        if alphabet.len() != self.sessions.id_alphabet.chars().count() {
+            errors.push(String::from(
+                "sessions.id_alphabet must not repeat characters",
+            ));
+        }
+        if alphabet.iter().any(|c| !c.is_ascii_alphanumeric()) {
+            errors.push(String::from(
+                "sessions.id_alphabet must only contain ASCII letters and digits",
+            ));
+        }
+
+        let rules = &self.rules;
+        if !(1..=MAX_BOARD_SIDE).contains(&rules.board_width)
+            || !(1..=MAX_BOARD_SIDE).contains(&rules.board_height)
+        {
+            errors.push(format!(
+                "rules.board_width and rules.board_height must be between 1 and {}",
+                MAX_BOARD_SIDE
+            ));
+        }
+        if rules.connect_length < 2
+            || rules.connect_length > rules.board_width.max(rules.board_height)
+        {
+            errors.push(format!(
+                "rules.connect_length must be between 2 and the longest side of the board ({})",
+                rules.board_width.max(rules.board_height)
+            ));
+        }
+        if rules.min_players < 2 {
+            errors.push(String::from("rules.min_players must be at least 2"));
+        }
+
+        if self.grace.handshake_seconds == 0 {
+            errors.push(String::from("grace.handshake_seconds must be at least 1"));
+        }
+        if self.grace.heartbeat_seconds == 0 {
+            errors.push(String::from("grace.heartbeat_seconds must be at least 1"));
+        }
+        if self.grace.heartbeat_timeout_seconds <= self.grace.heartbeat_seconds {
+            errors.push(String::from(
+                "grace.heartbeat_timeout_seconds must be longer than grace.heartbeat_seconds",
+            ));
+        }
+
+        let limits = &self.limits;
+        if limits.max_clients == 0 {
+            errors.push(String::from("limits.max_clients must be at least 1"));
+        }
+        if limits.max_sessions == 0 {
+            errors.push(String::from("limits.max_sessions must be at least 1"));
+        }
+        if limits.max_session_clients < rules.min_players {
+            errors.push(format!(
+                "limits.max_session_clients must be at least rules.min_players ({})",
+                rules.min_players
+            ));
+        }
+        if limits.max_session_clients > MAX_SESSION_CLIENTS {
+            errors.push(format!(
+                "limits.max_session_clients must be at most {}",
+                MAX_SESSION_CLIENTS
+            ));
+        }
+        if limits.max_message_bytes < MIN_MESSAGE_BYTES {
+            errors.push(format!(
+                "limits.max_message_bytes must be at least {}",
+                MIN_MESSAGE_BYTES
+            ));
+        }
+        if limits.event_burst == 0 || limits.events_per_second == 0 {
+            errors.push(String::from(
+                "limits.event_burst and limits.events_per_second must be at least 1",
+            ));
+        }
+        if limits.sessions_per_minute == 0 {
+            errors.push(String::from(
+                "limits.sessions_per_minute must be at least 1",
+            ));
+        }
+        if limits.max_rate_violations == 0 {
+            errors.push(String::from(
+                "limits.max_rate_violations must be at least 1",
+            ));
+        }
+
+        match (&self.admin.username, &self.admin.password) {
+            (None, None) => {}
+            (Some(username), Some(password)) => {
+                if username.is_empty() {
+                    errors.push(String::from("admin.username must not be empty"));
+                }
+                if password.chars().count() < MIN_ADMIN_PASSWORD_LENGTH {
+                    errors.push(format!(
+                        "admin.password must be at least {} characters",
+                        MIN_ADMIN_PASSWORD_LENGTH
+                    ));
+                }
+            }
+            _ => errors.push(String::from(
+                "admin.username and admin.password must be set together",
+            )),
+        }
+
+        if self.health.max_lock_held_millis == 0 {
+            errors.push(String::from(
+                "health.max_lock_held_millis must be at least 1",
+            ));
+        }
+
+        if self.accounts.token_ttl_hours == 0 {
+            errors.push(String::from("accounts.token_ttl_hours must be at least 1"));
+        }
+
+        if self.ratings.k_factor == 0 {
+            errors.push(String::from("ratings.k_factor must be at least 1"));
+        }
+
+        let matchmaking = &self.matchmaking;
+        if matchmaking.interval_millis == 0 {
+            errors.push(String::from(
+                "matchmaking.interval_millis must be at least 1",
+            ));
+        }
+        if matchmaking.max_rating_window < matchmaking.initial_rating_window {
+            errors.push(format!(
+                "matchmaking.max_rating_window must be at least matchmaking.initial_rating_window ({})",
+                matchmaking.initial_rating_window
+            ));
+        }
+
+        if self.archive.max_games == 0 {
+            errors.push(String::from("archive.max_games must be at least 1"));
+        }
+
+        if !(1..=MAX_ANALYSIS_DEPTH).contains(&self.analysis.max_depth) {
+            errors.push(format!(
+                "analysis.max_depth must be between 1 and {}",
+                MAX_ANALYSIS_DEPTH
+            ));
+        }
+
+        let hints = &self.hints;
+        if hints.default_budget > hints.max_budget {
+            errors.push(format!(
+                "hints.default_budget must be at most hints.max_budget ({})",
+                hints.max_budget
+            ));
+        }
+        if !(1..=MAX_HINT_SAMPLES).contains(&hints.samples) {
+            errors.push(format!(
+                "hints.samples must be between 1 and {}",
+                MAX_HINT_SAMPLES
+            ));
+        }
+        if !(1..=MAX_HINT_DEPTH).contains(&hints.depth) {
+            errors.push(format!(
+                "hints.depth must be between 1 and {}",
+                MAX_HINT_DEPTH
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid() {
+        assert_eq!(Config::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn file_settings_override_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+            [server]
+            bind_address = "127.0.0.1:9000"
+
+            [rules]
+            connect_length = 5
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.server.bind_address,
+            SocketAddr::from(([127, 0, 0, 1], 9000))
+        );
+        assert_eq!(config.rules.connect_length, 5);
+        assert_eq!(config.rules.board_width, RulesConfig::default().board_width);
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        assert!(toml::from_str::<Config>("[rules]\nconect_length = 5").is_err());
+    }
+
+    #[test]
+    fn args_override_file_settings() {
+        let mut config = Config::default();
+        config.apply(Args {
+            bind_address: Some(SocketAddr::from(([127, 0, 0, 1], 9000))),
+            port: Some(9100),
+            connect_length: Some(3),
+            ..Args::default()
+        });
+
+        assert_eq!(
+            config.server.bind_address,
+            SocketAddr::from(([127, 0, 0, 1], 9100))
+        );
+        assert_eq!(config.rules.connect_length, 3);
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let mut config = Config::default();
+        config.sessions.id_alphabet = String::from("AA");
+        config.rules.connect_length = 10;
+        config.admin.username = Some(String::from("admin"));
+
+        assert_eq!(config.validate().unwrap_err().len(), 4);
+    }
+
+    #[test]
+    fn searches_are_bounded() {
+        let mut config = Config::default();
+        config.analysis.max_depth = MAX_ANALYSIS_DEPTH + 1;
+        config.hints.samples = MAX_HINT_SAMPLES + 1;
+        config.hints.depth = MAX_HINT_DEPTH + 1;
+        assert_eq!(config.validate().unwrap_err().len(), 3);
+    }
+
+    #[test]
+    fn boards_are_bounded() {
+        let mut config = Config::default();
+        config.rules.board_width = MAX_BOARD_SIDE + 1;
+        assert_eq!(config.validate().unwrap_err().len(), 1);
+
+        let mut config = Config::default();
+        config.rules.board_height = MAX_BOARD_SIDE + 1;
+        assert_eq!(config.validate().unwrap_err().len(), 1);
+
+        let mut config = Config::default();
+        config.rules.board_width = MAX_BOARD_SIDE;
+        config.rules.board_height = MAX_BOARD_SIDE;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn sessions_are_bounded() {
+        let mut config = Config::default();
+        config.limits.max_session_clients = MAX_SESSION_CLIENTS + 1;
+        assert_eq!(config.validate().unwrap_err().len(), 1);
+
+        config.limits.max_session_clients = MAX_SESSION_CLIENTS;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn heartbeat_timeout_must_outlast_the_interval() {
+        let mut config = Config::default();
+        config.grace.heartbeat_timeout_seconds = config.grace.heartbeat_seconds;
+
+        assert_eq!(config.validate().unwrap_err().len(), 1);
+    }
+}
@@ -1,12 +1,27 @@
-use connect_in_the_dark::types::GameState;
-use sessions::session_types::{Clients, Sessions};
+use crate::accounts::Accounts;
+use crate::archive::Archive;
+use crate::config::Config;
+use crate::ratings::Ratings;
+use crate::session_actor::Registry;
+use crate::sse::SseConnection;
+use crate::tournaments::Tournaments;
+use sessions::session_types::Clients;
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::RwLock;
 
-pub type GameStates = HashMap<String, GameState>;
+/// Each client connected over Server-Sent Events
+pub type SseConnections = HashMap<String, SseConnection>;
 
 pub type SafeResource<T> = Arc<RwLock<T>>;
 
 pub type SafeClients = SafeResource<Clients>;
-pub type SafeSessions = SafeResource<Sessions>;
-pub type SafeGameStates = SafeResource<GameStates>;
+/// Only held long enough to find or replace a session, since each session runs as its own task
+pub type SafeSessions = SafeResource<Registry>;
+pub type SafeSseConnections = SafeResource<SseConnections>;
+pub type SafeAccounts = SafeResource<Accounts>;
+pub type SafeRatings = SafeResource<Ratings>;
+pub type SafeTournaments = SafeResource<Tournaments>;
+pub type SafeArchive = SafeResource<Archive>;
+
+/// Settings are read once at boot and never change
+pub type SharedConfig = Arc<Config>;
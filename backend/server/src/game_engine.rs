@@ -1,20 +1,25 @@
 use crate::{
+    codec,
+    config::SessionConfig,
     data_types::{self, SafeClients},
+    matchmaking::Ticket,
+    metrics, names, protocol,
+    session_actor::{self, JoinOutcome, SessionHandle},
     shared_types::{
         ClientEvent, ClientEventCode, EventBuilder, GameData, ServerEvent, ServerEventCode,
         ServerEventDataBuilder,
     },
     ws::cleanup_session,
 };
-use connect_in_the_dark::types::{create_game_board, GameState};
+use connect_in_the_dark::types::{GameState, RULE_VARIANTS};
 use nanoid::nanoid;
 use nanorand::{Rng, WyRand};
-use serde_json::from_str;
 use sessions::session_types;
-use std::{collections::HashMap, usize::MAX};
-use warp::ws::Message;
+use std::collections::HashMap;
+use std::time::Instant;
+use tracing::{debug, error, info, instrument, warn, Span};
 
-trait ShareableGameData {
+pub trait ShareableGameData {
     fn as_shared_game_data(&self, player_pov: Option<&str>) -> GameData;
 }
 
@@ -33,10 +38,10 @@ impl ShareableGameData for GameState {
                     .map(|col| {
                         col.iter()
                             .map(|ele| match *ele >= self.player_turn_order.len() {
-                                true => MAX,
+                                true => usize::MAX,
                                 false => match self.player_turn_order[*ele] == client_id {
                                     true => player_index,
-                                    false => MAX,
+                                    false => usize::MAX,
                                 },
                             })
                             .collect()
@@ -54,545 +59,601 @@ impl ShareableGameData for GameState {
 }
 
 /// Handle the Client events from a given Session
+#[instrument(
+    name = "event",
+    skip_all,
+    fields(
+        event_code = ?client_event.event_code,
+        request_id = client_event.request_id.as_deref(),
+        session_id = tracing::field::Empty,
+    )
+)]
 pub async fn handle_event(
     client_id: &str,
-    event: &str,
+    client_event: ClientEvent,
     clients: &data_types::SafeClients,
     sessions: &data_types::SafeSessions,
-    game_states: &data_types::SafeGameStates,
+    config: &data_types::SharedConfig,
 ) {
+    if let Some(session_id) = get_client_session_id(client_id, sessions).await {
+        Span::current().record("session_id", session_id.as_str());
+    }
+
+    let request_id = client_event.request_id.clone();
+    let code = format!("{:?}", client_event.event_code);
+    metrics::CLIENT_EVENTS.with_label_values(&[&code]).inc();
+    let timer = metrics::EVENT_HANDLING_SECONDS
+        .with_label_values(&[&code])
+        .start_timer();
+
+    let replied = dispatch_event(client_id, client_event, clients, sessions, config).await;
     //======================================================
-    // Deserialize into Session Event object
+    // Commands which did not send their own reply back to
+    // the client are acknowledged so that every request can
+    // be correlated with a response
     //======================================================
-    let client_event = match from_str::<ClientEvent>(event) {
-        Ok(obj) => obj,
-        Err(_) => {
-            eprintln!(
-                "[ERROR] failed to parse ClientEvent struct from string: {}",
-                event
-            );
-            return;
-        }
-    };
+    if !replied {
+        reply_to_client(
+            client_id,
+            &request_id,
+            EventBuilder::default()
+                .event_code(ServerEventCode::Acknowledged)
+                .build()
+                .unwrap(),
+            clients,
+        )
+        .await;
+    }
+    timer.observe_duration();
+}
 
-    match client_event.event_code {
-        ClientEventCode::SessionRequest => {
-            let session_id: String = match get_client_session_id(client_id, clients).await {
-                Some(session_id) => session_id,
-                None => return, // no session is ok
-            };
+/// Tell a client that an event it sent could not be deserialized
+pub async fn reject_event(client_id: &str, error: &str, clients: &data_types::SafeClients) {
+    warn!(client_id, error, "failed to parse ClientEvent");
+    notify_client_async(
+        client_id,
+        &quick_server_error("malformed_event", "The event could not be parsed."),
+        clients,
+    )
+    .await;
+}
 
-            // create a base server event
-            let mut server_data = ServerEventDataBuilder::default()
-                .session_id(session_id.clone())
-                .build()
-                .unwrap();
+/// Tell a client that an event it sent was dropped without being handled
+pub async fn refuse_event(
+    client_id: &str,
+    request_id: &Option<String>,
+    error_type: &str,
+    msg: &str,
+    clients: &data_types::SafeClients,
+) {
+    reply_to_client(
+        client_id,
+        request_id,
+        quick_server_error(error_type, msg),
+        clients,
+    )
+    .await;
+}
 
-            if let Some(session) = sessions.read().await.get(&session_id) {
-                server_data.session_client_ids = Some(session.get_client_ids());
-            }
-            if let Some(game_state) = game_states.read().await.get(&session_id) {
-                server_data.game_data = Some(game_state.as_shared_game_data(Some(client_id)));
-            }
+/// Tell a client that an event it sent only makes sense from inside a session
+///
+/// Always replies, so the result can be returned straight from `dispatch_event`
+async fn refuse_outside_session(
+    client_id: &str,
+    request_id: &Option<String>,
+    clients: &data_types::SafeClients,
+) -> bool {
+    reply_to_client(
+        client_id,
+        request_id,
+        quick_server_error("not_in_session", "You are not in a session."),
+        clients,
+    )
+    .await;
+    true
+}
 
-            notify_client_async(
-                client_id,
-                &EventBuilder::default()
-                    .event_code(ServerEventCode::SessionResponse)
-                    .data(server_data)
-                    .build()
-                    .unwrap(),
-                &clients,
-            )
-            .await;
+/// Run the logic for a single Client event
+///
+/// Anything touching a session is handed to the task of that session.
+/// Returns whether a direct reply was sent back to the requesting client
+async fn dispatch_event(
+    client_id: &str,
+    client_event: ClientEvent,
+    clients: &data_types::SafeClients,
+    sessions: &data_types::SafeSessions,
+    config: &data_types::SharedConfig,
+) -> bool {
+    let request_id = &client_event.request_id;
+
+    match client_event.event_code {
+        ClientEventCode::SessionRequest => {
+            let session = match client_session(client_id, sessions).await {
+                Some((_, session)) => session,
+                None => return false, // no session is ok
+            };
+            session.describe(client_id, request_id).await.is_some()
         }
         ClientEventCode::CreateSession => {
-            println!("[INFO] request from {} to create new session", client_id);
-            create_session(client_id, None, &sessions, &clients).await;
+            info!("request to create new session");
+            let client = match get_client(client_id, clients).await {
+                Some(client) => client,
+                None => return false,
+            };
+            leave_queue(client_id, sessions).await;
+            remove_client_from_current_session(client_id, sessions).await;
+            create_session(vec![client], None, request_id, sessions, config).await;
+            true
         }
         ClientEventCode::JoinSession => {
-            println!("[INFO] request from {} to join new session", client_id);
+            info!("request to join session");
 
-            let session_id = match client_event.data {
-                Some(data) => match data.session_id {
-                    Some(session_id) => session_id,
-                    None => panic!("no session id found!"),
-                },
+            let session_id = match client_event.data.and_then(|data| data.session_id) {
+                Some(session_id) => session_id,
                 None => {
-                    println!("[ERROR] the session id to join was missing in the request");
-                    return;
+                    warn!("the session id to join was missing in the request");
+                    reply_to_client(
+                        client_id,
+                        request_id,
+                        quick_server_error(
+                            "missing_session_id",
+                            "A session id is required to join a session.",
+                        ),
+                        clients,
+                    )
+                    .await;
+                    return true;
                 } // no session was found on a session join request? ¯\(°_o)/¯
             };
 
-            println!(
-                "[INFO] checking if client {} is already in session {}",
-                client_id, session_id
-            );
-            if let Some(session) = sessions.read().await.get(&session_id) {
-                if session.client_statuses.contains_key(client_id) {
-                    println!(
-                        "[INFO] client {} was already in session {}. (no-op)",
-                        client_id, session.id
-                    );
-                    return;
-                }
+            debug!(session_id = %session_id, "checking if client is already in session");
+            if get_client_session_id(client_id, sessions).await.as_ref() == Some(&session_id) {
+                debug!(session_id = %session_id, "client was already in session (no-op)");
+                return false;
             }
 
             // removing client front session
-            remove_client_from_current_session(client_id, clients, sessions, game_states).await;
+            leave_queue(client_id, sessions).await;
+            remove_client_from_current_session(client_id, sessions).await;
 
+            let client = match get_client(client_id, clients).await {
+                Some(client) => client,
+                None => return false,
+            };
             // Joining Some Session that already exists
-            if let Some(session) = sessions.write().await.get_mut(&session_id) {
-                // do not allow clients to join an active game
-                if game_states.read().await.get(&session_id).is_none() {
-                    println!(
-                        "[INFO] adding client {} into session {}",
-                        client_id, session_id
-                    );
-                    insert_client_into_given_session(client_id, &clients, session).await;
-                }
-                // notify the user that they cannot joing the current session
-                else {
-                    println!(
-                        "[INFO] client {} was not allowed into in-progess session {}",
-                        client_id, session_id
-                    );
-                    notify_client_async(
-                        client_id,
-                        &EventBuilder::default()
-                            .event_code(ServerEventCode::CannotJoinInProgress)
-                            .data(
-                                ServerEventDataBuilder::default()
-                                    .session_id(session_id)
-                                    .build()
-                                    .unwrap(),
-                            )
-                            .build()
-                            .unwrap(),
-                        &clients,
-                    )
-                    .await;
+            let existing = metrics::read(sessions, "sessions").await.get(&session_id);
+            if let Some(session) = existing {
+                match session.join(client.clone(), request_id).await {
+                    Some(JoinOutcome::Joined) => {
+                        Span::current().record("session_id", session_id.as_str());
+                        metrics::write(sessions, "sessions")
+                            .await
+                            .join(client_id, &session_id);
+                        return false;
+                    }
+                    Some(JoinOutcome::Refused) => return true,
+                    // the session closed before the client got in, so it is created again
+                    None => {}
                 }
-                return;
             }
 
             // Attempt to join a Reserved session, which will be created if it doesnt exist
-            println!("[INFO] creating a session from id: {}", session_id);
-            create_session(client_id, Some(&session_id), &sessions, &clients).await;
+            info!(session_id = %session_id, "creating a session from id");
+            create_session(
+                vec![client],
+                Some(&session_id),
+                request_id,
+                sessions,
+                config,
+            )
+            .await;
+            true
         }
         ClientEventCode::LeaveSession => {
-            remove_client_from_current_session(client_id, clients, sessions, game_states).await;
+            remove_client_from_current_session(client_id, sessions).await;
+            false
         }
         ClientEventCode::StartGame => {
-            let session_id = match get_client_session_id(client_id, clients).await {
-                Some(s_id) => s_id,
-                None => return,
+            let session = match client_session(client_id, sessions).await {
+                Some((_, session)) => session,
+                None => return refuse_outside_session(client_id, request_id, clients).await,
             };
-
-            if let Some(session) = sessions.read().await.get(&session_id) {
-                match initialize_game_data(&session.get_client_ids()) {
-                    Ok(player_turn_order) => {
-                        let game_state = GameState {
-                            turn_index: 0,
-                            player_turn_order,
-                            board: create_game_board(7, 6),
-                        };
-
-                        game_states
-                            .write()
-                            .await
-                            .insert(session_id.clone(), game_state.clone());
-
-                        // signal the turn start
-                        notify_session(
-                            &EventBuilder::default()
-                                .event_code(ServerEventCode::GameStarted)
-                                .data(
-                                    ServerEventDataBuilder::default()
-                                        .game_data(game_state.as_shared_game_data(Some(client_id)))
-                                        .build()
-                                        .unwrap(),
-                                )
-                                .build()
-                                .unwrap(),
-                            session,
-                            clients,
-                        )
-                        .await;
-
-                        // signal the turn start
-                        notify_session(
-                            &EventBuilder::default()
-                                .event_code(ServerEventCode::TurnStart)
-                                .data(
-                                    ServerEventDataBuilder::default()
-                                        .client_id(game_state.get_turn_player())
-                                        .game_data(game_state.as_shared_game_data(Some(client_id)))
-                                        .build()
-                                        .unwrap(),
-                                )
-                                .build()
-                                .unwrap(),
-                            session,
-                            clients,
-                        )
-                        .await;
-                    }
-                    Err(msg) => {
-                        eprintln!("[ERROR] {}", msg);
-                        notify_session(
-                            &EventBuilder::default()
-                                .event_code(ServerEventCode::LogicError)
-                                .message(msg.to_string())
-                                .build()
-                                .unwrap(),
-                            &session,
-                            &clients,
-                        )
-                        .await;
-                    }
-                }
-            }
+            let hint_budget = client_event.data.and_then(|data| data.hint_budget);
+            session
+                .start_game(client_id, request_id, hint_budget)
+                .await
+                .unwrap_or(false)
         }
         ClientEventCode::Play => {
-            let session_id: String = match get_client_session_id(client_id, clients).await {
-                Some(s_id) => s_id,
-                None => return,
+            let session = match client_session(client_id, sessions).await {
+                Some((_, session)) => session,
+                None => return refuse_outside_session(client_id, request_id, clients).await,
             };
 
-            let column = match client_event.data {
-                Some(data) => data.column.unwrap(),
-                None => return,
+            let column = match client_event.data.and_then(|data| data.column) {
+                Some(column) => column,
+                None => {
+                    reply_to_client(
+                        client_id,
+                        request_id,
+                        quick_server_error("missing_column", "A column is required to play."),
+                        clients,
+                    )
+                    .await;
+                    return true;
+                }
             };
 
-            if let Some(game_state) = game_states.write().await.get_mut(&session_id) {
-                if game_state.get_turn_player() != client_id {
-                    if let Some(client) = clients.read().await.get(client_id) {
-                        notify_client(
-                            &EventBuilder::default()
-                                .event_code(ServerEventCode::LogicError)
-                                .message("It is not your turn to play.")
-                                .build()
-                                .unwrap(),
-                            &client,
-                        );
-                    }
-                    // exit
-                    return;
+            session
+                .play(client_id, request_id, column)
+                .await
+                .unwrap_or(false)
+        }
+        ClientEventCode::SetDisplayName => {
+            let requested = client_event
+                .data
+                .and_then(|data| data.display_name)
+                .unwrap_or_default();
+            let display_name = match names::validate(&requested) {
+                Ok(display_name) => display_name,
+                Err(msg) => {
+                    reply_to_client(
+                        client_id,
+                        request_id,
+                        quick_server_error("invalid_display_name", msg),
+                        clients,
+                    )
+                    .await;
+                    return true;
                 }
-
-                let player_index = match game_state.get_player_index(client_id) {
-                    Some(index) => index,
-                    None => return,
-                };
-
-                match game_state.play(column, player_index) {
-                    Ok(won) => {
-                        if let Some(session) = sessions.read().await.get(&session_id) {
-                            // if the move was a winning move, then notify everyone that the game is over
-                            if won {
-                                notify_session(
-                                    &EventBuilder::default()
-                                        .event_code(ServerEventCode::GameEnded)
-                                        .data(
-                                            ServerEventDataBuilder::default()
-                                                .client_id(client_id)
-                                                .game_data(game_state.as_shared_game_data(None))
-                                                .build()
-                                                .unwrap(),
-                                        )
-                                        .build()
-                                        .unwrap(),
-                                    session,
-                                    &clients,
-                                )
-                                .await;
-                            }
-                            // else continue emitting the game format
-                            else {
-                                for (client_name, _) in &session.client_statuses {
-                                    if let Some(client) = clients.read().await.get(client_name) {
-                                        notify_client(
-                                            &EventBuilder::default()
-                                                .event_code(ServerEventCode::TurnStart)
-                                                .data(
-                                                    ServerEventDataBuilder::default()
-                                                        .client_id(game_state.get_turn_player())
-                                                        .game_data(
-                                                            game_state.as_shared_game_data(Some(
-                                                                client_name,
-                                                            )),
-                                                        )
-                                                        .build()
-                                                        .unwrap(),
-                                                )
-                                                .build()
-                                                .unwrap(),
-                                            &client,
-                                        );
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        if let Some(client) = clients.read().await.get(client_id) {
-                            notify_client(
-                                &quick_server_error("This column has reached its max."),
-                                &client,
-                            );
-                        }
-                        eprintln!(
-                            "[ERROR] player {} failed to play with err: {}",
-                            client_id, e,
-                        )
-                    }
+            };
+            // the name is used for the next session joined when the client is in none
+            let refused = match client_session(client_id, sessions).await {
+                Some((_, session)) => session
+                    .rename(client_id, &display_name, request_id)
+                    .await
+                    .unwrap_or(false),
+                None => false,
+            };
+            if !refused {
+                if let Some(client) = metrics::write(clients, "clients").await.get_mut(client_id) {
+                    client.display_name = display_name;
                 }
             }
+            refused
+        }
+        ClientEventCode::JoinQueue => {
+            let data = client_event.data;
+            let player_count = data
+                .as_ref()
+                .and_then(|data| data.player_count)
+                .map(|count| count as usize)
+                .unwrap_or(config.rules.min_players);
+            if !(config.rules.min_players..=config.limits.max_session_clients)
+                .contains(&player_count)
+            {
+                reply_to_client(
+                    client_id,
+                    request_id,
+                    quick_server_error(
+                        "invalid_player_count",
+                        &format!(
+                            "Games must have between {} and {} players.",
+                            config.rules.min_players, config.limits.max_session_clients
+                        ),
+                    ),
+                    clients,
+                )
+                .await;
+                return true;
+            }
+            let rule_variant = data
+                .and_then(|data| data.rule_variant)
+                .unwrap_or_else(|| RULE_VARIANTS[0].to_string());
+            if !RULE_VARIANTS.contains(&rule_variant.as_str()) {
+                reply_to_client(
+                    client_id,
+                    request_id,
+                    quick_server_error(
+                        "unknown_rule_variant",
+                        "This rule variant is not played on this server.",
+                    ),
+                    clients,
+                )
+                .await;
+                return true;
+            }
+
+            info!(player_count, rule_variant = %rule_variant, "client joined the matchmaking queue");
+            metrics::write(sessions, "sessions")
+                .await
+                .queue_mut()
+                .join(Ticket {
+                    client_id: client_id.to_string(),
+                    player_count,
+                    rule_variant,
+                    queued_at: Instant::now(),
+                });
+            false
+        }
+        ClientEventCode::LeaveQueue => {
+            leave_queue(client_id, sessions).await;
+            false
+        }
+        ClientEventCode::RequestHint => {
+            let session = match client_session(client_id, sessions).await {
+                Some((_, session)) => session,
+                None => return refuse_outside_session(client_id, request_id, clients).await,
+            };
+            session.hint(client_id, request_id).await.unwrap_or(false)
+        }
+        ClientEventCode::Hello => {
+            // the handshake already happened when the connection opened, so just restate it
+            let client = match get_client(client_id, clients).await {
+                Some(client) => client,
+                None => return false,
+            };
+            reply(
+                &client,
+                request_id,
+                protocol::welcome_event(client.encoding, client_id, &client.display_name),
+            );
+            true
         }
     }
 }
 
-/// Creates a Session with a given Client as its creator / first member
+/// Creates a Session with the given Clients as its members, the first of whom is its creator
 async fn create_session(
-    client_id: &str,
+    members: Vec<session_types::Client>,
     session_id: Option<&str>,
+    request_id: &Option<String>,
     sessions: &data_types::SafeSessions,
-    clients: &data_types::SafeClients,
-) {
-    println!("[INFO] creating session");
+    config: &data_types::SharedConfig,
+) -> Option<(String, SessionHandle)> {
+    debug!("creating session");
     let session = &mut session_types::Session {
         client_statuses: HashMap::new(),
-        owner: client_id.to_string(),
+        display_names: HashMap::new(),
+        owner: members[0].id.clone(),
         id: match session_id {
             Some(id) => id.to_string(),
-            None => get_rand_session_id(),
+            None => get_rand_session_id(&config.sessions),
         },
     };
 
-    // insert the host client into the session
-    session.insert_client(&client_id.to_string(), true);
-
-    println!(
-        "[INFO] writing new session {} to global sessions",
-        session.id
-    );
-    // add a new session into the server
-    sessions
-        .write()
-        .await
-        .insert(session.id.clone(), session.clone());
-
-    println!(
-        "[INFO] attaching session {} to client {}",
-        session.id, client_id
-    );
-    // update the session reference within the client
-    if let Some(client) = clients.write().await.get_mut(client_id) {
-        client.session_id = Some(session.id.clone());
+    // insert the members into the session, the host first
+    for client in &members {
+        let display_name = names::unique(
+            &client.display_name,
+            session.display_names.values().map(String::as_str),
+        );
+        session.insert_client(&client.id, true);
+        session.set_display_name(&client.id, &display_name);
     }
 
-    println!("[INFO] send notification to client {}", client_id);
-    if let Some(client) = clients.read().await.get(client_id) {
-        notify_client(
-            &EventBuilder::default()
-                .event_code(ServerEventCode::ClientJoined)
-                .data(
-                    ServerEventDataBuilder::default()
-                        .session_id(session.id.clone())
-                        .client_id(client_id.to_string())
-                        .session_client_ids(session.get_client_ids())
-                        .build()
-                        .unwrap(),
-                )
+    let created = {
+        let mut registry = metrics::write(sessions, "sessions").await;
+        if registry.len() >= config.limits.max_sessions {
+            info!("session was not created, the server is at its session limit");
+            Err(quick_server_error(
+                "session_limit",
+                "No more sessions can be created right now.",
+            ))
+        } else if registry.contains(&session.id) {
+            info!(session_id = %session.id, "session was not created, the id was taken meanwhile");
+            Err(quick_server_error(
+                "session_taken",
+                "This session was just created, try joining it again.",
+            ))
+        } else {
+            debug!(session_id = %session.id, "starting the task of the new session");
+            let handle = session_actor::spawn(
+                session.clone(),
+                None,
+                members.clone(),
+                registry.finished_games(),
+                config.clone(),
+            );
+            registry.insert(&session.id, handle.clone());
+            for client in &members {
+                registry.join(&client.id, &session.id);
+            }
+            Ok(handle)
+        }
+    };
+    // only the creator made a request, the rest were matched into the session
+    let request_ids = std::iter::once(request_id.clone()).chain(std::iter::repeat(None));
+    let handle = match created {
+        Ok(handle) => handle,
+        Err(refusal) => {
+            for (client, request_id) in members.iter().zip(request_ids) {
+                reply(client, &request_id, refusal.clone());
+            }
+            return None;
+        }
+    };
+
+    Span::current().record("session_id", session.id.as_str());
+    // every member is told that they joined, as a creator would be
+    let joined: ServerEvent = EventBuilder::default()
+        .event_code(ServerEventCode::ClientJoined)
+        .data(
+            ServerEventDataBuilder::default()
+                .session_id(session.id.clone())
+                .session_client_ids(session.get_client_ids())
+                .display_names(session.display_names.clone())
                 .build()
                 .unwrap(),
-            &client,
-        );
+        )
+        .build()
+        .unwrap();
+    for (client, request_id) in members.iter().zip(request_ids) {
+        let mut joined = joined.clone();
+        if let Some(data) = joined.data.as_mut() {
+            data.client_id = Some(client.id.clone());
+        }
+        reply(client, &request_id, joined);
     }
-    println!("[INFO] finished creating session {}", session.id);
-    println!("[INFO] sessions live: {}", sessions.read().await.len());
+    let sessions_live = metrics::read(sessions, "sessions").await.len();
+    info!(session_id = %session.id, sessions_live, "finished creating session");
+    Some((session.id.clone(), handle))
 }
 
-/// Send an update to all clients in the session
+/// Seat the clients of a match in a session of their own and start their game
 ///
-/// Uses a Read lock on clients
-async fn notify_session(
-    game_update: &ServerEvent,
-    session: &session_types::Session,
+/// Clients who disconnected while being matched are dropped, and the rest are queued again
+pub async fn start_match(
+    tickets: Vec<Ticket>,
     clients: &data_types::SafeClients,
+    sessions: &data_types::SafeSessions,
+    config: &data_types::SharedConfig,
 ) {
-    for (client_id, _) in &session.client_statuses {
-        if let Some(client) = clients.read().await.get(client_id) {
-            notify_client(game_update, client);
-        }
+    let members: Vec<session_types::Client> = {
+        let clients = metrics::read(clients, "clients").await;
+        tickets
+            .iter()
+            .filter_map(|ticket| clients.get(&ticket.client_id).cloned())
+            .collect()
+    };
+    if members.len() < tickets.len() {
+        info!("a matched client disconnected, queueing the rest again");
+        let waiting = tickets
+            .into_iter()
+            .filter(|ticket| members.iter().any(|client| client.id == ticket.client_id))
+            .collect();
+        metrics::write(sessions, "sessions")
+            .await
+            .queue_mut()
+            .requeue(waiting);
+        return;
+    }
+
+    if start_game_between(members, sessions, config)
+        .await
+        .is_some()
+    {
+        metrics::MATCHES_MADE.inc();
     }
 }
 
-/// Send and update to a set of clients
-async fn _notify_clients(
-    game_update: &ServerEvent,
-    client_ids: &Vec<String>,
-    clients: &data_types::SafeClients,
-) {
-    for client_id in client_ids {
-        if let Some(client) = clients.read().await.get(client_id) {
-            notify_client(game_update, client);
-        }
+/// Move clients out of their sessions into a new one of their own and start their game,
+/// returning the id of the new session
+pub async fn start_game_between(
+    members: Vec<session_types::Client>,
+    sessions: &data_types::SafeSessions,
+    config: &data_types::SharedConfig,
+) -> Option<String> {
+    for client in &members {
+        leave_queue(&client.id, sessions).await;
+        remove_client_from_current_session(&client.id, sessions).await;
+    }
+    let owner = members[0].id.clone();
+    let (session_id, session) = create_session(members, None, &None, sessions, config).await?;
+    session.start_game(&owner, &None, None).await;
+    Some(session_id)
+}
+
+/// Send an update to every connected client
+pub async fn notify_all(game_update: &ServerEvent, clients: &data_types::SafeClients) {
+    for client in metrics::read(clients, "clients").await.values() {
+        notify_client(game_update, client);
     }
 }
 
 /// Send an update to single clients
 async fn notify_client_async(client_id: &str, game_update: &ServerEvent, clients: &SafeClients) {
-    if let Some(client) = clients.read().await.get(client_id) {
+    if let Some(client) = metrics::read(clients, "clients").await.get(client_id) {
         notify_client(game_update, client);
     } else {
-        eprintln!("[ERROR] could not find client: {}", client_id);
+        error!(client_id, "could not find client");
     }
 }
 
+/// Send a direct reply to the client which made a request, echoing the id of the request
+async fn reply_to_client(
+    client_id: &str,
+    request_id: &Option<String>,
+    mut reply: ServerEvent,
+    clients: &SafeClients,
+) {
+    reply.request_id = request_id.clone();
+    notify_client_async(client_id, &reply, clients).await;
+}
+
+/// Send a direct reply to a client that is already at hand, echoing the id of the request
+fn reply(client: &session_types::Client, request_id: &Option<String>, mut reply: ServerEvent) {
+    reply.request_id = request_id.clone();
+    notify_client(&reply, client);
+}
+
 /// Send an update to single clients
-fn notify_client(game_update: &ServerEvent, client: &session_types::Client) {
+#[instrument(
+    level = "debug",
+    skip_all,
+    fields(client_id = %client.id, event_code = ?game_update.event_code)
+)]
+pub fn notify_client(game_update: &ServerEvent, client: &session_types::Client) {
     let sender = match &client.sender {
         Some(s) => s,
-        None => return eprintln!("[ERROR] sender was lost for client: {}", client.id),
+        None => return error!("sender was lost for client"),
     };
-    if let Err(e) = sender.send(Ok(Message::text(
-        serde_json::to_string(game_update).unwrap(),
-    ))) {
-        eprintln!(
-            "[ERROR] failed to send message to {} with err: {}",
-            client.id, e,
-        );
+    if let Err(e) = sender.send(codec::encode(game_update, client.encoding)) {
+        error!(error = %e, "failed to send message to client");
+    }
+}
+
+/// Stop looking for a game for a client
+async fn leave_queue(client_id: &str, sessions: &data_types::SafeSessions) {
+    if metrics::write(sessions, "sessions")
+        .await
+        .queue_mut()
+        .leave(client_id)
+    {
+        info!(client_id, "client left the matchmaking queue");
     }
 }
 
 /// Removes a client from the session that they currently exist under
-async fn remove_client_from_current_session(
-    client_id: &str,
-    clients: &data_types::SafeClients,
-    sessions: &data_types::SafeSessions,
-    game_states: &data_types::SafeGameStates,
-) {
-    println!(
-        "[INFO] attempting to remove client {} from their current session",
-        client_id
+async fn remove_client_from_current_session(client_id: &str, sessions: &data_types::SafeSessions) {
+    debug!(
+        client_id,
+        "attempting to remove client from their current session"
     );
 
-    let session_id: String = match get_client_session_id(client_id, clients).await {
-        Some(s_id) => s_id,
+    let (session_id, session) = match client_session(client_id, sessions).await {
+        Some(found) => found,
         None => {
-            println!("[SKIP] client {} was not in a session", client_id);
+            debug!(client_id, "client was not in a session");
             return;
         } // client did not exist in any session
     };
 
-    let mut session_empty: bool = false;
-    if let Some(session) = sessions.write().await.get_mut(&session_id) {
-        // notify all clients in the sessions that the client will be leaving
-        notify_session(
-            &EventBuilder::default()
-                .event_code(ServerEventCode::ClientLeft)
-                .data(
-                    ServerEventDataBuilder::default()
-                        .client_id(client_id.to_string())
-                        .build()
-                        .unwrap(),
-                )
-                .build()
-                .unwrap(),
-            &session,
-            &clients,
-        )
-        .await;
-        // remove the client from the session
-        session.remove_client(&client_id.to_string());
-
-        println!(
-            "[INFO] removed client {} from session {}",
-            client_id, session_id
-        );
-
-        // revoke the client's copy of the session_id
-        if let Some(client) = clients.write().await.get_mut(client_id) {
-            client.session_id = None;
-        }
-        // checks the statuses to see if any users are still active
-        session_empty = session.get_clients_with_active_status(true).is_empty();
-        // if the session is not empty, make someone else the owner
-        if !session_empty {
-            set_new_session_owner(session, &clients, &session.get_client_ids()[0]);
-        }
-    }
-    // clean up the session from the map if it is empty
-    // * we cannot do this in the scope above because because we are already holding a mutable reference to a session within the map
+    let session_empty = session.leave(client_id).await.unwrap_or(false);
+    // revoke the client's membership of the session
+    metrics::write(sessions, "sessions").await.leave(client_id);
+    // clean up the session if no one active is left in it
     if session_empty {
-        cleanup_session(&session_id, sessions, game_states).await;
-    }
-}
-
-/// Takes a mutable session reference in order to add a client to a given session
-///
-/// Uses a Read lock for Clients
-async fn insert_client_into_given_session(
-    client_id: &str,
-    clients: &data_types::SafeClients,
-    session: &mut session_types::Session,
-) {
-    // add client to session
-    session.insert_client(client_id, true);
-    // update session_id of client
-    if let Some(client) = clients.write().await.get_mut(client_id) {
-        client.session_id = Some(session.id.clone());
+        cleanup_session(&session_id, sessions).await;
     }
-    // notify all clients in the session that the client has joined
-    notify_session(
-        &EventBuilder::default()
-            .event_code(ServerEventCode::ClientJoined)
-            .data(
-                ServerEventDataBuilder::default()
-                    .session_id(session.id.clone())
-                    .client_id(client_id.to_string())
-                    .session_client_ids(session.get_client_ids())
-                    .build()
-                    .unwrap(),
-            )
-            .build()
-            .unwrap(),
-        &session,
-        &clients,
-    )
-    .await;
-}
-
-fn set_new_session_owner(
-    session: &mut session_types::Session,
-    _clients: &data_types::SafeClients,
-    client_id: &String,
-) {
-    session.owner = client_id.clone();
-    // notify_all_clients(
-    //   &ServerEvent {
-    //     event_code: ServerEventCode::SessionOwnerChange,
-    //     session_id: Some(session.id.clone()),
-    //     client_id: Some(client_id.clone()),
-    //     session_client_ids: None,
-    //   },
-    //   &session,
-    //   &clients,
-    // );
 }
 
 /// Attempt to create a new game if the lobby has enough players
-fn initialize_game_data(client_vec: &Vec<String>) -> Result<Vec<String>, &str> {
-    // Check that the game has at least 2 players
-    match client_vec.len() >= 2 {
-        false => Err("Need at least 2 players to start a game."),
+pub fn initialize_game_data(
+    client_vec: &[String],
+    min_players: usize,
+) -> Result<Vec<String>, String> {
+    // Check that the game has enough players
+    match client_vec.len() >= min_players {
+        false => Err(format!(
+            "Need at least {} players to start a game.",
+            min_players
+        )),
         true => {
             // random gen for shuffling
             let mut rand = WyRand::new();
             // shuffle the order characters
-            let mut playerinfo_vec = client_vec.clone();
+            let mut playerinfo_vec = client_vec.to_vec();
             rand.shuffle(&mut playerinfo_vec);
 
             Ok(playerinfo_vec)
@@ -600,32 +661,100 @@ fn initialize_game_data(client_vec: &Vec<String>) -> Result<Vec<String>, &str> {
     }
 }
 
-/// Gets a random new session 1 that is 5 characters long
+/// Gets a random new session id with the configured length and alphabet
 /// This should almost ensure session uniqueness when dealing with a sizeable number of sessions
-fn get_rand_session_id() -> String {
-    let alphabet: [char; 26] = [
-        'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O', 'P', 'Q', 'R',
-        'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z',
-    ];
-    nanoid!(5, &alphabet)
+fn get_rand_session_id(config: &SessionConfig) -> String {
+    let alphabet: Vec<char> = config.id_alphabet.chars().collect();
+    let length = config.id_length;
+    nanoid!(length, &alphabet)
 }
 
-/// pull the session id off of a client
+/// pull the session id of the session a client belongs to
 async fn get_client_session_id(
     client_id: &str,
-    clients: &data_types::SafeClients,
+    sessions: &data_types::SafeSessions,
 ) -> Option<String> {
-    if let Some(client) = &clients.read().await.get(client_id) {
-        client.session_id.clone()
-    } else {
-        None
-    }
+    metrics::read(sessions, "sessions")
+        .await
+        .session_of(client_id)
+}
+
+/// The id and handle of the session a client belongs to
+pub async fn client_session(
+    client_id: &str,
+    sessions: &data_types::SafeSessions,
+) -> Option<(String, SessionHandle)> {
+    metrics::read(sessions, "sessions")
+        .await
+        .session_of_client(client_id)
+}
+
+/// A copy of a connected client
+async fn get_client(
+    client_id: &str,
+    clients: &data_types::SafeClients,
+) -> Option<session_types::Client> {
+    metrics::read(clients, "clients")
+        .await
+        .get(client_id)
+        .cloned()
 }
 
-fn quick_server_error(msg: &str) -> ServerEvent {
+/// Build a LogicError for the client, recording it under the given error type
+pub fn quick_server_error(error_type: &str, msg: &str) -> ServerEvent {
+    metrics::error(error_type);
     EventBuilder::default()
         .event_code(ServerEventCode::LogicError)
         .message(msg.to_string())
         .build()
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::session_actor::Registry;
+    use sessions::session_types::{Client, Encoding};
+    use sessions::sink::Outbound;
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn session_commands_are_refused_outside_a_session() {
+        let (sender, mut outbound) = crate::transport::channel();
+        let clients: data_types::SafeClients = Arc::new(RwLock::new(HashMap::new()));
+        clients.write().await.insert(
+            String::from("alice"),
+            Client {
+                id: String::from("alice"),
+                display_name: String::from("Alice"),
+                sender: Some(sender),
+                encoding: Encoding::Json,
+            },
+        );
+        let sessions = Arc::new(RwLock::new(Registry::default()));
+        let config = Arc::new(Config::default());
+
+        for (request_id, event) in [
+            ("s1", r#"{"event_code":5,"request_id":"s1"}"#),
+            (
+                "p1",
+                r#"{"event_code":6,"request_id":"p1","data":{"column":0}}"#,
+            ),
+            ("h1", r#"{"event_code":11,"request_id":"h1"}"#),
+        ] {
+            let event: ClientEvent = serde_json::from_str(event).unwrap();
+            handle_event("alice", event, &clients, &sessions, &config).await;
+            let reply = match outbound.next().await {
+                Some(Outbound::Text(text)) => serde_json::from_str::<serde_json::Value>(&text),
+                other => panic!("expected a text reply, got {:?}", other),
+            }
+            .unwrap();
+            assert_eq!(reply["event_code"], ServerEventCode::LogicError as u8);
+            assert_eq!(reply["request_id"], request_id);
+            assert_eq!(reply["message"], "You are not in a session.");
+        }
+    }
+}
@@ -1,4 +1,8 @@
-use crate::{data_types, ws};
+use crate::shared_types::ClientEvent;
+use crate::{accounts, data_types, health, metrics, names, protocol, sse, ws};
+use serde::Deserialize;
+use tracing::{debug, info};
+use urlencoding::decode;
 use warp::hyper::StatusCode;
 use warp::Rejection;
 use warp::Reply;
@@ -10,29 +14,221 @@ struct IDAlreadyTaken;
 impl warp::reject::Reject for IDAlreadyTaken {}
 
 /// Will handle a Client attempting to connect a websocket with the server
-/// A User Requesting to be connected to an already connected ID will be rejected
+/// A User Requesting to be connected to an already connected ID will be rejected,
+/// as will a User without the login of the account owning that ID
+#[allow(clippy::too_many_arguments)]
 pub async fn ws_handler(
     ws: warp::ws::Ws,
     id: String,
+    token: Option<String>,
     clients: data_types::SafeClients,
     sessions: data_types::SafeSessions,
-    game_states: data_types::SafeGameStates,
-) -> Result<impl Reply> {
-    let client = clients.read().await.get(&id).cloned();
+    accounts: data_types::SafeAccounts,
+    config: data_types::SharedConfig,
+) -> Result<warp::reply::Response> {
+    let decoded_id = match decode_path_id(&id) {
+        Ok(decoded_id) => decoded_id,
+        Err(status) => return Ok(status.into_response()),
+    };
+    if let Err(refused) = accounts::authorize(&decoded_id, token.as_deref(), &accounts).await {
+        return Ok(unauthorized(&decoded_id, refused));
+    }
+    let client = metrics::read(&clients, "clients")
+        .await
+        .get(&decoded_id)
+        .cloned();
     match client {
         Some(_) => {
-            println!("[INFO] duplicate connection request for id: {}", id);
+            info!(client_id = %decoded_id, "duplicate connection request");
             Err(warp::reject::custom(IDAlreadyTaken))
         }
-        None => Ok(ws.on_upgrade(move |socket| {
-            println!("[INFO] incoming request for id: {}", id);
-            ws::client_connection(socket, id, clients, sessions, game_states)
-        })),
+        None if is_full(&clients, &config).await => Ok(server_full()),
+        // oversized messages are closed gracefully by the client connection,
+        // so the socket itself only drops those far past the limit
+        None => Ok(ws
+            .max_message_size(config.limits.max_message_bytes * 2)
+            .on_upgrade(move |socket| {
+                info!(client_id = %decoded_id, "incoming websocket request");
+                ws::client_connection(socket, decoded_id, clients, sessions, config)
+            })
+            .into_response()),
+    }
+}
+
+/// Query parameters stating the protocol of an event stream client, in place of a `Hello`
+#[derive(Deserialize)]
+pub struct SseParams {
+    pub protocol_version: u32,
+    pub display_name: Option<String>,
+}
+
+/// Decode an id taken from the request path, so we dont get things like '%20' for spaces
+///
+/// An id which is not UTF-8 once decoded is a bad request
+pub fn decode_path_id(id: &str) -> std::result::Result<String, StatusCode> {
+    decode(id).map(|id| id.into_owned()).map_err(|_| {
+        metrics::error("invalid_path_id");
+        StatusCode::BAD_REQUEST
+    })
+}
+
+/// Will handle a Client attempting to open a Server-Sent Events stream with the server
+/// A User Requesting to be connected to an already connected ID will be rejected,
+/// as will a User without the login of the account owning that ID
+#[allow(clippy::too_many_arguments)]
+pub async fn sse_handler(
+    id: String,
+    params: SseParams,
+    token: Option<String>,
+    clients: data_types::SafeClients,
+    sessions: data_types::SafeSessions,
+    sse_connections: data_types::SafeSseConnections,
+    accounts: data_types::SafeAccounts,
+    config: data_types::SharedConfig,
+) -> Result<warp::reply::Response> {
+    let id = match decode_path_id(&id) {
+        Ok(id) => id,
+        Err(status) => return Ok(status.into_response()),
+    };
+    if let Err(refused) = accounts::authorize(&id, token.as_deref(), &accounts).await {
+        return Ok(unauthorized(&id, refused));
+    }
+    if metrics::read(&clients, "clients").await.contains_key(&id) {
+        info!(client_id = %id, "duplicate connection request");
+        return Err(warp::reject::custom(IDAlreadyTaken));
+    }
+    if is_full(&clients, &config).await {
+        return Ok(server_full());
+    }
+    if !protocol::is_supported_version(params.protocol_version) {
+        return Ok(warp::reply::with_status(
+            "unsupported protocol version",
+            StatusCode::BAD_REQUEST,
+        )
+        .into_response());
+    }
+
+    let display_name = match params.display_name.as_deref().map(names::validate) {
+        None => names::default_for(&id),
+        Some(Ok(display_name)) => display_name,
+        Some(Err(msg)) => {
+            return Ok(warp::reply::with_status(msg, StatusCode::BAD_REQUEST).into_response())
+        }
+    };
+
+    info!(client_id = %id, "incoming event stream request");
+    let stream =
+        sse::client_connection(id, display_name, clients, sessions, sse_connections, config).await;
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(stream)).into_response())
+}
+
+/// Will handle a Client event sent over HTTP by a client holding an event stream
+#[allow(clippy::too_many_arguments)]
+pub async fn sse_event_handler(
+    id: String,
+    token: String,
+    client_event: ClientEvent,
+    clients: data_types::SafeClients,
+    sessions: data_types::SafeSessions,
+    sse_connections: data_types::SafeSseConnections,
+    config: data_types::SharedConfig,
+) -> Result<warp::reply::Response> {
+    let id = match decode_path_id(&id) {
+        Ok(id) => id,
+        Err(status) => return Ok(status.into_response()),
+    };
+    let status = sse::handle_client_event(
+        &id,
+        &token,
+        client_event,
+        &clients,
+        &sessions,
+        &sse_connections,
+        &config,
+    )
+    .await;
+    Ok(warp::reply::with_status(warp::reply(), status).into_response())
+}
+
+/// Whether the server already holds as many clients as it is allowed
+async fn is_full(clients: &data_types::SafeClients, config: &data_types::SharedConfig) -> bool {
+    metrics::read(clients, "clients").await.len() >= config.limits.max_clients
+}
+
+/// The reply to a client connecting with an id it may not use
+fn unauthorized(id: &str, (status, reason): (StatusCode, &'static str)) -> warp::reply::Response {
+    info!(client_id = %id, reason, "rejecting connection without the account's login");
+    metrics::error("connection_unauthorized");
+    warp::reply::with_status(reason, status).into_response()
+}
+
+/// The reply to a client connecting while the server is full
+fn server_full() -> warp::reply::Response {
+    info!("rejecting connection, the server is full");
+    metrics::error("server_full");
+    warp::reply::with_status("the server is full", StatusCode::SERVICE_UNAVAILABLE).into_response()
+}
+
+/// Metrics Endpoint exposing the server's state in the Prometheus text format
+pub async fn metrics_handler(
+    clients: data_types::SafeClients,
+    sessions: data_types::SafeSessions,
+) -> Result<impl Reply> {
+    // gauges are sampled from the shared maps on every scrape, except for the games
+    // in progress which every session counts itself
+    metrics::CONNECTED_CLIENTS.set(metrics::read(&clients, "clients").await.len() as i64);
+    {
+        let registry = metrics::read(&sessions, "sessions").await;
+        metrics::SESSIONS.set(registry.len() as i64);
+        metrics::QUEUED_CLIENTS.set(registry.queue().len() as i64);
     }
+
+    Ok(warp::reply::with_header(
+        metrics::render(),
+        "content-type",
+        "text/plain; version=0.0.4",
+    ))
 }
 
 /// Health Check Endpoint used to verify the service is live
 pub async fn health_handler() -> Result<impl Reply> {
-    println!("[INFO] HEALTH_CHECK ✓");
+    debug!("HEALTH_CHECK ✓");
     Ok(warp::reply::with_status("health check ✓", StatusCode::OK))
 }
+
+/// Liveness Endpoint telling an orchestrator the process is still serving requests
+pub async fn live_handler() -> Result<impl Reply> {
+    Ok(warp::reply::json(&health::liveness()))
+}
+
+/// Readiness Endpoint telling an orchestrator whether the server should be sent new clients
+pub async fn ready_handler(
+    clients: data_types::SafeClients,
+    sessions: data_types::SafeSessions,
+    sse_connections: data_types::SafeSseConnections,
+    config: data_types::SharedConfig,
+) -> Result<impl Reply> {
+    let readiness = health::readiness(&clients, &sessions, &sse_connections, &config).await;
+    let status = match readiness.is_ready() {
+        true => StatusCode::OK,
+        false => {
+            debug!(failing = ?readiness.failing(), "not ready");
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::json(&readiness),
+        status,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_ids_are_decoded_or_refused() {
+        assert_eq!(decode_path_id("Jane%20Doe").unwrap(), "Jane Doe");
+        assert_eq!(decode_path_id("%FF"), Err(StatusCode::BAD_REQUEST));
+    }
+}
@@ -0,0 +1,163 @@
+//! Reports on whether the server is alive and ready for traffic, for orchestrators to act on
+use crate::{data_types, metrics};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+use tokio::time::timeout;
+
+/// Version reported by the health endpoints, which builds can stamp with `CITD_BUILD_VERSION`
+const VERSION: &str = match option_env!("CITD_BUILD_VERSION") {
+    Some(version) => version,
+    None => env!("CARGO_PKG_VERSION"),
+};
+
+/// How long counting the connections may wait on the shared locks
+const COUNT_TIMEOUT: Duration = Duration::from_millis(100);
+
+static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+/// Start counting the uptime from now
+pub fn start_clock() {
+    LazyLock::force(&STARTED);
+}
+
+/// Stop reporting as ready, since the server is shutting down
+pub fn mark_draining() {
+    DRAINING.store(true, Ordering::Relaxed);
+}
+
+#[derive(Serialize)]
+pub struct Liveness {
+    status: &'static str,
+    version: &'static str,
+    uptime_seconds: u64,
+}
+
+#[derive(Serialize)]
+pub struct Readiness {
+    status: &'static str,
+    version: &'static str,
+    uptime_seconds: u64,
+    connections: Connections,
+    /// `disabled` while no snapshot path is set, else whether it can be written
+    persistence: &'static str,
+    longest_lock_held_millis: u64,
+    draining: bool,
+    /// The checks which stop the server from being ready
+    failing: Vec<&'static str>,
+}
+
+/// Each count is left out when its lock could not be taken in time
+#[derive(Serialize)]
+pub struct Connections {
+    clients: Option<usize>,
+    event_streams: Option<usize>,
+    sessions: Option<usize>,
+}
+
+impl Readiness {
+    pub fn is_ready(&self) -> bool {
+        self.failing.is_empty()
+    }
+    pub fn failing(&self) -> &[&'static str] {
+        &self.failing
+    }
+}
+
+/// The process is running and serving requests
+pub fn liveness() -> Liveness {
+    Liveness {
+        status: "live",
+        version: VERSION,
+        uptime_seconds: STARTED.elapsed().as_secs(),
+    }
+}
+
+/// Whether the server should be sent new clients
+pub async fn readiness(
+    clients: &data_types::SafeClients,
+    sessions: &data_types::SafeSessions,
+    sse_connections: &data_types::SafeSseConnections,
+    config: &data_types::SharedConfig,
+) -> Readiness {
+    let connections = Connections {
+        clients: timeout(COUNT_TIMEOUT, metrics::read(clients, "clients"))
+            .await
+            .ok()
+            .map(|clients| clients.len()),
+        event_streams: timeout(
+            COUNT_TIMEOUT,
+            metrics::read(sse_connections, "sse_connections"),
+        )
+        .await
+        .ok()
+        .map(|sse_connections| sse_connections.len()),
+        sessions: timeout(COUNT_TIMEOUT, metrics::read(sessions, "sessions"))
+            .await
+            .ok()
+            .map(|sessions| sessions.len()),
+    };
+    let persistence = match &config.persistence.snapshot_path {
+        None => "disabled",
+        Some(path) if is_writable(path) => "reachable",
+        Some(_) => "unreachable",
+    };
+    let longest_lock_held = metrics::longest_lock_held();
+    let draining = DRAINING.load(Ordering::Relaxed);
+
+    let mut failing = Vec::new();
+    if draining {
+        failing.push("draining");
+    }
+    if persistence == "unreachable" {
+        failing.push("persistence");
+    }
+    if longest_lock_held > config.health.max_lock_held() {
+        failing.push("lock_held");
+    }
+    if connections.clients.is_none()
+        || connections.event_streams.is_none()
+        || connections.sessions.is_none()
+    {
+        failing.push("connections");
+    }
+
+    Readiness {
+        status: if failing.is_empty() {
+            "ready"
+        } else {
+            "not_ready"
+        },
+        version: VERSION,
+        uptime_seconds: STARTED.elapsed().as_secs(),
+        connections,
+        persistence,
+        longest_lock_held_millis: longest_lock_held.as_millis() as u64,
+        draining,
+        failing,
+    }
+}
+
+/// Whether a snapshot could be written beside the given path right now
+fn is_writable(path: &Path) -> bool {
+    let probe = path.with_extension("probe");
+    let writable = fs::write(&probe, b"").is_ok();
+    let _ = fs::remove_file(&probe);
+    writable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots_are_unreachable_in_missing_directories() {
+        let missing = Path::new("this-directory-does-not-exist/snapshot.json");
+        assert!(!is_writable(missing));
+        assert!(is_writable(&std::env::temp_dir().join("citd-health.json")));
+    }
+}
@@ -1,41 +1,446 @@
+use accounts::Accounts;
+use archive::Archive;
+use config::Config;
+use ratings::Ratings;
+use session_actor::Registry;
 use std::collections::HashMap;
+use std::convert::Infallible;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{oneshot, RwLock};
+use tokio::time::timeout;
+use tracing::{error, info};
 use warp::filters::BoxedFilter;
 use warp::{Filter, Reply};
 
+mod accounts;
+mod admin;
+mod archive;
+mod codec;
+pub mod codegen;
+pub mod config;
 mod data_types;
 mod game_engine;
 mod handler;
+mod health;
+pub mod logging;
+mod matchmaking;
+mod metrics;
+mod names;
+mod persistence;
+mod protocol;
+mod rate_limit;
+mod ratings;
+mod replay;
+mod session_actor;
+// the TS derive parses enum discriminants with `from_str_radix`
+#[allow(clippy::from_str_radix_10)]
 mod shared_types;
+mod shutdown;
+mod sse;
+mod tournaments;
+mod transport;
 mod ws;
 
-/// Composite backend and frontend routes for the entire server
-pub fn server() -> BoxedFilter<(impl Reply,)> {
-    warp::path("api").and(backend()).or(frontend()).boxed()
+/// Serve the entire server until the process is asked to stop,
+/// then warn the clients and drain their sessions before returning
+pub async fn run(config: Config) {
+    health::start_clock();
+    let config: data_types::SharedConfig = Arc::new(config);
+    let clients: data_types::SafeClients = Arc::new(RwLock::new(HashMap::new()));
+    let sessions: data_types::SafeSessions = Arc::new(RwLock::new(Registry::default()));
+    // a file which cannot be read is never replaced, since that would lose every account in it
+    let accounts: data_types::SafeAccounts = match Accounts::load(config.accounts.path.clone()) {
+        Ok(accounts) => Arc::new(RwLock::new(accounts)),
+        Err(e) => {
+            error!(error = %e, "the server could not start without its accounts");
+            return;
+        }
+    };
+    let ratings: data_types::SafeRatings = match Ratings::load(config.ratings.path.clone()) {
+        Ok(ratings) => Arc::new(RwLock::new(ratings)),
+        Err(e) => {
+            error!(error = %e, "the server could not start without its ratings");
+            return;
+        }
+    };
+    let finished_games = metrics::read(&sessions, "sessions")
+        .await
+        .subscribe_finished_games();
+    tokio::spawn(ratings::record_finished_games(
+        finished_games,
+        ratings.clone(),
+        accounts.clone(),
+        config.clone(),
+    ));
+    let archive: data_types::SafeArchive =
+        match Archive::load(config.archive.path.clone(), &config.archive) {
+            Ok(archive) => Arc::new(RwLock::new(archive)),
+            Err(e) => {
+                error!(error = %e, "the server could not start without its archive");
+                return;
+            }
+        };
+    let finished_games = metrics::read(&sessions, "sessions")
+        .await
+        .subscribe_finished_games();
+    tokio::spawn(archive::record_finished_games(
+        finished_games,
+        archive.clone(),
+        config.clone(),
+    ));
+    let tournaments: data_types::SafeTournaments = Arc::new(RwLock::new(HashMap::new()));
+    let finished_games = metrics::read(&sessions, "sessions")
+        .await
+        .subscribe_finished_games();
+    tokio::spawn(tournaments::record_finished_games(
+        finished_games,
+        tournaments.clone(),
+        clients.clone(),
+        sessions.clone(),
+        config.clone(),
+    ));
+    tokio::spawn(matchmaking::run(
+        clients.clone(),
+        sessions.clone(),
+        ratings.clone(),
+        config.clone(),
+    ));
+
+    if let Some(path) = &config.persistence.snapshot_path {
+        match persistence::restore(path, &sessions, &config).await {
+            Ok(restored) => {
+                info!(restored = restored.len(), path = %path.display(), "restored sessions");
+                for session_id in restored {
+                    let grace = config.persistence.restore_grace();
+                    ws::schedule_cleanup(session_id, &sessions, grace);
+                }
+            }
+            Err(e) => error!(error = %e, "could not restore sessions"),
+        }
+    }
+
+    let routes = warp::path("api")
+        .and(backend(
+            clients.clone(),
+            sessions.clone(),
+            accounts,
+            ratings,
+            tournaments,
+            archive,
+            config.clone(),
+        ))
+        .or(frontend(config.server.static_dir.clone()));
+
+    // new connections stop being accepted as soon as the shutdown begins
+    let (stop_accepting, stopped) = oneshot::channel::<()>();
+    let (address, serving) =
+        warp::serve(routes).bind_with_graceful_shutdown(config.server.bind_address, async {
+            let _ = stopped.await;
+        });
+    info!(%address, "server listening");
+    let serving = tokio::spawn(serving);
+
+    shutdown::signal().await;
+    info!("shutting down");
+    health::mark_draining();
+    let _ = stop_accepting.send(());
+    shutdown::drain(&clients, &sessions, &config).await;
+
+    if timeout(shutdown::CLOSE_TIMEOUT, serving).await.is_err() {
+        error!("connections were still open when the server stopped");
+    }
+    info!("server stopped");
 }
 
 /// Routes handling server requests and connections
-fn backend() -> BoxedFilter<(impl Reply,)> {
-    let clients: data_types::SafeClients = Arc::new(RwLock::new(HashMap::new()));
-    let sessions: data_types::SafeSessions = Arc::new(RwLock::new(HashMap::new()));
-    let game_states: data_types::SafeGameStates = Arc::new(RwLock::new(HashMap::new()));
+fn backend(
+    clients: data_types::SafeClients,
+    sessions: data_types::SafeSessions,
+    accounts: data_types::SafeAccounts,
+    ratings: data_types::SafeRatings,
+    tournaments: data_types::SafeTournaments,
+    archive: data_types::SafeArchive,
+    config: data_types::SharedConfig,
+) -> BoxedFilter<(impl Reply,)> {
+    let sse_connections: data_types::SafeSseConnections = Arc::new(RwLock::new(HashMap::new()));
 
     let health = warp::path!("health").and_then(handler::health_handler);
 
+    let live = warp::path!("health" / "live")
+        .and(warp::get())
+        .and_then(handler::live_handler);
+
+    let ready = warp::path!("health" / "ready")
+        .and(warp::get())
+        .and(with_resource(clients.clone()))
+        .and(with_resource(sessions.clone()))
+        .and(with_resource(sse_connections.clone()))
+        .and(with_resource(config.clone()))
+        .and_then(handler::ready_handler);
+
+    let metrics = warp::path!("metrics")
+        .and(warp::get())
+        .and(with_resource(clients.clone()))
+        .and(with_resource(sessions.clone()))
+        .and_then(handler::metrics_handler);
+
     let socket = warp::path("ws")
         .and(warp::ws())
         .and(warp::path::param())
+        .and(accounts::token())
         // pass copies of our references for the client and sessions maps to our handler
-        .and(warp::any().map(move || clients.clone()))
-        .and(warp::any().map(move || sessions.clone()))
-        .and(warp::any().map(move || game_states.clone()))
+        .and(with_resource(clients.clone()))
+        .and(with_resource(sessions.clone()))
+        .and(with_resource(accounts.clone()))
+        .and(with_resource(config.clone()))
         .and_then(handler::ws_handler);
 
-    health.or(socket).boxed()
+    // fallback transport for clients whose proxies do not allow websockets
+    let event_stream = warp::path!("sse" / String)
+        .and(warp::get())
+        .and(warp::query::<handler::SseParams>())
+        .and(accounts::token())
+        .and(with_resource(clients.clone()))
+        .and(with_resource(sessions.clone()))
+        .and(with_resource(sse_connections.clone()))
+        .and(with_resource(accounts.clone()))
+        .and(with_resource(config.clone()))
+        .and_then(handler::sse_handler);
+
+    let event_post = warp::path!("sse" / String)
+        .and(warp::post())
+        .and(warp::header::<String>("x-connection-token"))
+        .and(warp::body::content_length_limit(
+            config.limits.max_message_bytes as u64,
+        ))
+        .and(warp::body::json())
+        .and(with_resource(clients.clone()))
+        .and(with_resource(sessions.clone()))
+        .and(with_resource(sse_connections))
+        .and(with_resource(config.clone()))
+        .and_then(handler::sse_event_handler);
+
+    health
+        .or(live)
+        .or(ready)
+        .or(metrics)
+        .or(socket)
+        .or(event_stream)
+        .or(event_post)
+        .or(account_routes(accounts.clone(), config.clone()))
+        .or(rating_routes(ratings.clone()))
+        .or(tournament_routes(tournaments.clone(), accounts))
+        .or(archive_routes(archive, config.clone()))
+        .or(admin(clients, sessions, ratings, tournaments, config))
+        .boxed()
+}
+
+/// Routes for players to register and log into their accounts
+fn account_routes(
+    accounts: data_types::SafeAccounts,
+    config: data_types::SharedConfig,
+) -> BoxedFilter<(impl Reply,)> {
+    let register = warp::path!("register")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(
+            config.limits.max_message_bytes as u64,
+        ))
+        .and(warp::body::json())
+        .and(with_resource(accounts.clone()))
+        .and(with_resource(config.clone()))
+        .and_then(accounts::register);
+
+    let login = warp::path!("login")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(
+            config.limits.max_message_bytes as u64,
+        ))
+        .and(warp::body::json())
+        .and(with_resource(accounts.clone()))
+        .and(with_resource(config))
+        .and_then(accounts::login);
+
+    let logout = warp::path!("logout")
+        .and(warp::post())
+        .and(accounts::token())
+        .and(with_resource(accounts.clone()))
+        .and_then(accounts::logout);
+
+    let me = warp::path!("me")
+        .and(warp::get())
+        .and(accounts::token())
+        .and(with_resource(accounts))
+        .and_then(accounts::me);
+
+    warp::path("accounts")
+        .and(register.or(login).or(logout).or(me))
+        .boxed()
+}
+
+/// Routes for anyone to look up how the registered players rank
+fn rating_routes(ratings: data_types::SafeRatings) -> BoxedFilter<(impl Reply,)> {
+    let leaderboard = warp::path!("leaderboard")
+        .and(warp::get())
+        .and(warp::query::<ratings::LeaderboardParams>())
+        .and(with_resource(ratings.clone()))
+        .and_then(ratings::leaderboard);
+
+    let player = warp::path!("players" / String)
+        .and(warp::get())
+        .and(with_resource(ratings))
+        .and_then(ratings::player);
+
+    leaderboard.or(player).boxed()
+}
+
+/// Routes for anyone to follow tournaments, and for players to register for them
+fn tournament_routes(
+    tournaments: data_types::SafeTournaments,
+    accounts: data_types::SafeAccounts,
+) -> BoxedFilter<(impl Reply,)> {
+    let list = warp::path!("tournaments")
+        .and(warp::get())
+        .and(with_resource(tournaments.clone()))
+        .and_then(tournaments::list);
+
+    let get = warp::path!("tournaments" / String)
+        .and(warp::get())
+        .and(with_resource(tournaments.clone()))
+        .and_then(tournaments::get);
+
+    let register = warp::path!("tournaments" / String / "participants")
+        .and(warp::post())
+        .and(accounts::token())
+        .and(with_resource(tournaments))
+        .and(with_resource(accounts))
+        .and_then(tournaments::register);
+
+    list.or(get).or(register).boxed()
+}
+
+/// Routes for anyone to look back on finished games, download their replays and analyze them
+fn archive_routes(
+    archive: data_types::SafeArchive,
+    config: data_types::SharedConfig,
+) -> BoxedFilter<(impl Reply,)> {
+    let list = warp::path!("games")
+        .and(warp::get())
+        .and(warp::query::<archive::GamesParams>())
+        .and(with_resource(archive.clone()))
+        .and_then(archive::list);
+
+    let get = warp::path!("games" / String)
+        .and(warp::get())
+        .and(with_resource(archive.clone()))
+        .and_then(archive::get);
+
+    let replay = warp::path!("games" / String / "replay")
+        .and(warp::get())
+        .and(with_resource(archive.clone()))
+        .and_then(archive::replay);
+
+    let analyze = warp::path!("analyze")
+        .and(warp::get())
+        .and(warp::query::<archive::AnalyzeParams>())
+        .and(with_resource(archive))
+        .and(with_resource(config))
+        .and_then(archive::analyze);
+
+    list.or(get).or(replay).or(analyze).boxed()
+}
+
+/// Routes for operators to manage the live sessions and run tournaments
+fn admin(
+    clients: data_types::SafeClients,
+    sessions: data_types::SafeSessions,
+    ratings: data_types::SafeRatings,
+    tournaments: data_types::SafeTournaments,
+    config: data_types::SharedConfig,
+) -> BoxedFilter<(impl Reply,)> {
+    let list_sessions = warp::path!("sessions")
+        .and(warp::get())
+        .and(with_resource(sessions.clone()))
+        .and_then(admin::list_sessions);
+
+    let get_session = warp::path!("sessions" / String)
+        .and(warp::get())
+        .and(with_resource(sessions.clone()))
+        .and_then(admin::get_session);
+
+    let end_game = warp::path!("sessions" / String / "end")
+        .and(warp::post())
+        .and(with_resource(sessions.clone()))
+        .and_then(admin::end_game);
+
+    let kick_client = warp::path!("sessions" / String / "clients" / String)
+        .and(warp::delete())
+        .and(with_resource(sessions.clone()))
+        .and_then(admin::kick_client);
+
+    let broadcast = warp::path!("broadcast")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(
+            config.limits.max_message_bytes as u64,
+        ))
+        .and(warp::body::json())
+        .and(with_resource(clients.clone()))
+        .and_then(admin::broadcast);
+
+    let create_tournament = warp::path!("tournaments")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(
+            config.limits.max_message_bytes as u64,
+        ))
+        .and(warp::body::json())
+        .and(with_resource(tournaments.clone()))
+        .and_then(tournaments::create);
+
+    let start_tournament = warp::path!("tournaments" / String / "start")
+        .and(warp::post())
+        .and(with_resource(tournaments.clone()))
+        .and(with_resource(ratings))
+        .and(with_resource(clients.clone()))
+        .and(with_resource(sessions.clone()))
+        .and(with_resource(config.clone()))
+        .and_then(tournaments::start);
+
+    let report_result = warp::path!("tournaments" / String / "results")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(
+            config.limits.max_message_bytes as u64,
+        ))
+        .and(warp::body::json())
+        .and(with_resource(tournaments))
+        .and(with_resource(clients))
+        .and(with_resource(sessions))
+        .and(with_resource(config.clone()))
+        .and_then(tournaments::report);
+
+    warp::path("admin")
+        .and(admin::authorized(config))
+        .and(
+            list_sessions
+                .or(get_session)
+                .or(end_game)
+                .or(kick_client)
+                .or(broadcast)
+                .or(create_tournament)
+                .or(start_tournament)
+                .or(report_result),
+        )
+        .recover(admin::recover)
+        .boxed()
+}
+
+/// Pass a copy of a shared resource to a handler
+fn with_resource<T: Clone + Send>(
+    resource: T,
+) -> impl Filter<Extract = (T,), Error = Infallible> + Clone {
+    warp::any().map(move || resource.clone())
 }
 
 /// Routes for serving static website files
-fn frontend() -> BoxedFilter<(impl Reply,)> {
-    warp::fs::dir("dist").boxed()
+fn frontend(static_dir: PathBuf) -> BoxedFilter<(impl Reply,)> {
+    warp::fs::dir(static_dir).boxed()
 }
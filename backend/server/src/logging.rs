@@ -0,0 +1,27 @@
+use std::env;
+use tracing_subscriber::{fmt, EnvFilter};
+
+/// Filter directives used when `LOG_LEVEL` is not set
+const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// Install the global subscriber which every log line and span is written through
+///
+/// - `LOG_LEVEL` takes filter directives such as `debug` or `info,server::game_engine=trace`
+/// - `LOG_FORMAT` is either `pretty` (the default) or `json`
+pub fn init() {
+    let filter =
+        EnvFilter::try_from_env("LOG_LEVEL").unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_LEVEL));
+
+    let format = env::var("LOG_FORMAT").unwrap_or_else(|_| String::from("pretty"));
+    match format.as_str() {
+        "pretty" => fmt().with_env_filter(filter).init(),
+        "json" => fmt()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_env_filter(filter)
+            .init(),
+        other => panic!("LOG_FORMAT must be `pretty` or `json`, found `{}`", other),
+    }
+}
@@ -1,13 +1,20 @@
-use server::server;
-use std::env;
+use server::{config::Config, logging};
+use std::process;
 
 #[tokio::main]
 async fn main() {
-    let port: u16 = env::var("PORT")
-        .unwrap_or_else(|_| String::from("8000"))
-        .parse()
-        .expect("PORT must be a number");
+    logging::init();
 
-    println!("[BOOT] server starting on port::{}", port);
-    warp::serve(server()).run(([0, 0, 0, 0], port)).await;
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(errors) => {
+            for error in errors {
+                tracing::error!("{}", error);
+            }
+            tracing::error!("the server could not start because of an invalid config");
+            process::exit(1);
+        }
+    };
+
+    server::run(config).await;
 }
@@ -0,0 +1,254 @@
+//! Clients waiting to be matched into a game with others of a similar rating
+//!
+//! The queue is kept in the session registry, and a background task searches it for games
+//! every `matchmaking.interval_millis`
+use crate::config::MatchmakingConfig;
+use crate::{data_types, game_engine, metrics};
+use std::collections::HashMap;
+use std::time::Instant;
+use tokio::time::interval;
+use tracing::info;
+
+/// A client waiting for a game
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ticket {
+    pub client_id: String,
+    /// How many players the game must have
+    pub player_count: usize,
+    pub rule_variant: String,
+    pub queued_at: Instant,
+}
+
+impl Ticket {
+    fn plays_with(&self, other: &Ticket) -> bool {
+        self.player_count == other.player_count && self.rule_variant == other.rule_variant
+    }
+}
+
+/// The clients waiting for a game, longest waiting first
+#[derive(Default)]
+pub struct Queue {
+    tickets: Vec<Ticket>,
+}
+
+impl Queue {
+    /// Queue a client, replacing any ticket it already had
+    pub fn join(&mut self, ticket: Ticket) {
+        self.leave(&ticket.client_id);
+        self.tickets.push(ticket);
+    }
+    /// Returns whether the client was queued
+    pub fn leave(&mut self, client_id: &str) -> bool {
+        let queued = self.tickets.len();
+        self.tickets.retain(|ticket| ticket.client_id != client_id);
+        self.tickets.len() < queued
+    }
+    pub fn len(&self) -> usize {
+        self.tickets.len()
+    }
+    pub fn client_ids(&self) -> Vec<String> {
+        self.tickets
+            .iter()
+            .map(|ticket| ticket.client_id.clone())
+            .collect()
+    }
+    /// Put tickets which could not be seated back where they were in the queue
+    pub fn requeue(&mut self, tickets: Vec<Ticket>) {
+        self.tickets.extend(tickets);
+        self.tickets.sort_by_key(|ticket| ticket.queued_at);
+    }
+
+    /// Take every group of tickets out of the queue which can be played as a game
+    ///
+    /// Whoever has waited longest is matched first, with the closest rated clients
+    /// wanting the same game who are within their rating window
+    pub fn take_matches(
+        &mut self,
+        rating: impl Fn(&str) -> f64,
+        now: Instant,
+        config: &MatchmakingConfig,
+    ) -> Vec<Vec<Ticket>> {
+        let mut matched = vec![false; self.tickets.len()];
+        let mut groups = Vec::new();
+
+        for (anchor, ticket) in self.tickets.iter().enumerate() {
+            if matched[anchor] {
+                continue;
+            }
+            let window = config.rating_window(now.saturating_duration_since(ticket.queued_at));
+            let anchor_rating = rating(&ticket.client_id);
+            let mut candidates: Vec<(usize, f64)> = self
+                .tickets
+                .iter()
+                .enumerate()
+                .skip(anchor + 1)
+                .filter(|(index, other)| !matched[*index] && ticket.plays_with(other))
+                .map(|(index, other)| (index, (rating(&other.client_id) - anchor_rating).abs()))
+                .filter(|(_, gap)| *gap <= window)
+                .collect();
+            if candidates.len() + 1 < ticket.player_count {
+                continue;
+            }
+            candidates.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+            let group: Vec<usize> = std::iter::once(anchor)
+                .chain(
+                    candidates
+                        .into_iter()
+                        .take(ticket.player_count - 1)
+                        .map(|(index, _)| index),
+                )
+                .collect();
+            for index in &group {
+                matched[*index] = true;
+            }
+            groups.push(group);
+        }
+
+        let found = groups
+            .into_iter()
+            .map(|group| {
+                group
+                    .into_iter()
+                    .map(|index| self.tickets[index].clone())
+                    .collect()
+            })
+            .collect();
+        let mut index = 0;
+        self.tickets.retain(|_| {
+            index += 1;
+            !matched[index - 1]
+        });
+        found
+    }
+}
+
+/// Search the queue for games for as long as the server runs, starting each one found
+pub async fn run(
+    clients: data_types::SafeClients,
+    sessions: data_types::SafeSessions,
+    ratings: data_types::SafeRatings,
+    config: data_types::SharedConfig,
+) {
+    let mut ticks = interval(config.matchmaking.interval());
+    loop {
+        ticks.tick().await;
+        let queued = metrics::read(&sessions, "sessions")
+            .await
+            .queue()
+            .client_ids();
+        if queued.len() < config.rules.min_players {
+            continue;
+        }
+
+        // ratings are looked up first so that both locks are never held at once
+        let known: HashMap<String, f64> = {
+            let ratings = metrics::read(&ratings, "ratings").await;
+            queued
+                .into_iter()
+                .map(|id| {
+                    let rating = ratings.rating(&id, &config.ratings);
+                    (id, rating)
+                })
+                .collect()
+        };
+        let initial_rating = config.ratings.initial_rating as f64;
+        let matches = metrics::write(&sessions, "sessions")
+            .await
+            .queue_mut()
+            .take_matches(
+                |id| known.get(id).copied().unwrap_or(initial_rating),
+                Instant::now(),
+                &config.matchmaking,
+            );
+
+        for tickets in matches {
+            info!(players = tickets.len(), "matched queued clients");
+            game_engine::start_match(tickets, &clients, &sessions, &config).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn ticket(client_id: &str, player_count: usize, queued_at: Instant) -> Ticket {
+        Ticket {
+            client_id: client_id.to_string(),
+            player_count,
+            rule_variant: String::from("classic"),
+            queued_at,
+        }
+    }
+
+    fn rating(id: &str) -> f64 {
+        match id {
+            "strong" => 1900.0,
+            "weak" => 1300.0,
+            _ => 1500.0,
+        }
+    }
+
+    #[test]
+    fn clients_are_matched_by_player_count() {
+        let now = Instant::now();
+        let mut queue = Queue::default();
+        queue.join(ticket("a", 2, now));
+        queue.join(ticket("b", 3, now));
+        queue.join(ticket("c", 3, now));
+        queue.join(ticket("d", 2, now));
+
+        let matches = queue.take_matches(rating, now, &MatchmakingConfig::default());
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0][0].client_id, "a");
+        assert_eq!(matches[0][1].client_id, "d");
+        assert_eq!(queue.client_ids(), ["b", "c"]);
+
+        queue.join(ticket("e", 3, now));
+        assert_eq!(
+            queue
+                .take_matches(rating, now, &MatchmakingConfig::default())
+                .len(),
+            1
+        );
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn rating_windows_widen_while_waiting() {
+        let config = MatchmakingConfig::default();
+        let now = Instant::now();
+        let mut queue = Queue::default();
+        queue.join(ticket("strong", 2, now));
+        queue.join(ticket("a", 2, now));
+        assert!(queue.take_matches(rating, now, &config).is_empty());
+
+        // 400 points apart is only within the widest window
+        let later = now + Duration::from_secs(60);
+        assert_eq!(queue.take_matches(rating, later, &config).len(), 1);
+
+        queue.join(ticket("strong", 2, now));
+        queue.join(ticket("weak", 2, now));
+        assert!(queue.take_matches(rating, later, &config).is_empty());
+    }
+
+    #[test]
+    fn closest_ratings_are_matched_first() {
+        let config = MatchmakingConfig {
+            initial_rating_window: 1000,
+            max_rating_window: 1000,
+            ..MatchmakingConfig::default()
+        };
+        let now = Instant::now();
+        let mut queue = Queue::default();
+        queue.join(ticket("a", 2, now));
+        queue.join(ticket("strong", 2, now));
+        queue.join(ticket("b", 2, now));
+
+        let matches = queue.take_matches(rating, now, &config);
+        assert_eq!(matches[0][1].client_id, "b");
+        assert_eq!(queue.client_ids(), ["strong"]);
+    }
+}
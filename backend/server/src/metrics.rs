@@ -0,0 +1,217 @@
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge, TextEncoder,
+};
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+pub static CONNECTED_CLIENTS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!("citd_connected_clients", "Clients currently connected").unwrap()
+});
+
+pub static SESSIONS: LazyLock<IntGauge> =
+    LazyLock::new(|| register_int_gauge!("citd_sessions", "Sessions currently live").unwrap());
+
+pub static QUEUED_CLIENTS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "citd_queued_clients",
+        "Clients waiting in the matchmaking queue"
+    )
+    .unwrap()
+});
+
+pub static GAMES_IN_PROGRESS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "citd_games_in_progress",
+        "Sessions with a game being played"
+    )
+    .unwrap()
+});
+
+/// Held by tests which start or restore games, so that they can count the games in progress
+#[cfg(test)]
+pub static GAMES_IN_PROGRESS_TESTS: LazyLock<tokio::sync::Mutex<()>> =
+    LazyLock::new(Default::default);
+
+pub static CLIENT_EVENTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "citd_client_events_total",
+        "Events received from clients by ClientEventCode",
+        &["code"]
+    )
+    .unwrap()
+});
+
+pub static ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!("citd_errors_total", "Errors by type", &["type"]).unwrap()
+});
+
+pub static GAMES_FINISHED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "citd_games_finished_total",
+        "Games finished by outcome",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+pub static MATCHES_MADE: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "citd_matches_total",
+        "Games started for clients matched from the queue"
+    )
+    .unwrap()
+});
+
+pub static RATE_LIMITED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "citd_rate_limited_total",
+        "Client events dropped for going over a limit",
+        &["limit"]
+    )
+    .unwrap()
+});
+
+pub static ABUSE_DISCONNECTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "citd_abuse_disconnects_total",
+        "Clients disconnected for going over their limits by reason",
+        &["reason"]
+    )
+    .unwrap()
+});
+
+pub static EVENT_HANDLING_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "citd_event_handling_seconds",
+        "Time taken to handle a client event by ClientEventCode",
+        &["code"]
+    )
+    .unwrap()
+});
+
+pub static LOCK_WAIT_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "citd_lock_wait_seconds",
+        "Time spent waiting to acquire a lock on a shared resource",
+        &["resource", "access"],
+        vec![0.00001, 0.0001, 0.001, 0.01, 0.1, 1.0]
+    )
+    .unwrap()
+});
+
+pub static LOCK_HELD_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "citd_lock_held_seconds",
+        "Time a lock on a shared resource was held for",
+        &["resource", "access"],
+        vec![0.00001, 0.0001, 0.001, 0.01, 0.1, 1.0]
+    )
+    .unwrap()
+});
+
+/// When each lock which is currently held was acquired, by the id of its guard
+static HELD_SINCE: LazyLock<Mutex<HashMap<u64, Instant>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+static NEXT_GUARD_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Record an error of the given type
+pub fn error(error_type: &str) {
+    ERRORS.with_label_values(&[error_type]).inc();
+}
+
+/// Acquire a read lock on a shared resource, recording how long it took and how long it is held
+pub async fn read<'a, T>(
+    lock: &'a RwLock<T>,
+    resource: &'static str,
+) -> Held<RwLockReadGuard<'a, T>> {
+    let start = Instant::now();
+    let guard = lock.read().await;
+    LOCK_WAIT_SECONDS
+        .with_label_values(&[resource, "read"])
+        .observe(start.elapsed().as_secs_f64());
+    Held::new(guard, resource, "read")
+}
+
+/// Acquire a write lock on a shared resource, recording how long it took and how long it is held
+pub async fn write<'a, T>(
+    lock: &'a RwLock<T>,
+    resource: &'static str,
+) -> Held<RwLockWriteGuard<'a, T>> {
+    let start = Instant::now();
+    let guard = lock.write().await;
+    LOCK_WAIT_SECONDS
+        .with_label_values(&[resource, "write"])
+        .observe(start.elapsed().as_secs_f64());
+    Held::new(guard, resource, "write")
+}
+
+/// A lock guard which records how long it was held once dropped
+pub struct Held<G> {
+    guard: G,
+    id: u64,
+    resource: &'static str,
+    access: &'static str,
+    since: Instant,
+}
+
+impl<G> Held<G> {
+    fn new(guard: G, resource: &'static str, access: &'static str) -> Self {
+        let id = NEXT_GUARD_ID.fetch_add(1, Ordering::Relaxed);
+        let since = Instant::now();
+        HELD_SINCE.lock().unwrap().insert(id, since);
+        Held {
+            guard,
+            id,
+            resource,
+            access,
+            since,
+        }
+    }
+}
+
+impl<G> Deref for Held<G> {
+    type Target = G;
+    fn deref(&self) -> &G {
+        &self.guard
+    }
+}
+
+impl<G> DerefMut for Held<G> {
+    fn deref_mut(&mut self) -> &mut G {
+        &mut self.guard
+    }
+}
+
+impl<G> Drop for Held<G> {
+    fn drop(&mut self) {
+        HELD_SINCE.lock().unwrap().remove(&self.id);
+        LOCK_HELD_SECONDS
+            .with_label_values(&[self.resource, self.access])
+            .observe(self.since.elapsed().as_secs_f64());
+    }
+}
+
+/// How long the lock which has been held the longest has been held for, if any is held
+pub fn longest_lock_held() -> Duration {
+    HELD_SINCE
+        .lock()
+        .unwrap()
+        .values()
+        .map(|since| since.elapsed())
+        .max()
+        .unwrap_or_default()
+}
+
+/// Render every registered metric in the Prometheus text format
+pub fn render() -> String {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .unwrap();
+    String::from_utf8(buffer).unwrap()
+}
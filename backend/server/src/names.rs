@@ -0,0 +1,194 @@
+//! Display names shown for players, kept apart from the ids which identify them
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// The longest display name, in characters
+pub const MAX_DISPLAY_NAME_LENGTH: usize = 20;
+
+/// The name given to clients which did not choose a valid one
+const DEFAULT_DISPLAY_NAME: &str = "Guest";
+
+/// Punctuation allowed in display names besides letters, digits and spaces
+const ALLOWED_PUNCTUATION: [char; 4] = ['_', '-', '.', '\''];
+
+/// Check a requested display name, returning it in its canonical form
+///
+/// Whitespace is trimmed and collapsed, and the name is composed into NFC
+pub fn validate(requested: &str) -> Result<String, &'static str> {
+    let name = requested
+        .nfc()
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if name.is_empty() {
+        return Err("Display names must not be empty.");
+    }
+    if name.chars().count() > MAX_DISPLAY_NAME_LENGTH {
+        return Err("Display names must be at most 20 characters.");
+    }
+    if name.chars().any(is_invisible) {
+        return Err("Display names must not contain control or invisible characters.");
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == ' ' || ALLOWED_PUNCTUATION.contains(&c))
+    {
+        return Err("Display names may only use letters, digits, spaces and _ - . '");
+    }
+    if mixes_scripts(&name) {
+        return Err("Display names must not mix Latin, Greek and Cyrillic letters.");
+    }
+    Ok(name)
+}
+
+/// The display name a client gets when it did not ask for one
+pub fn default_for(client_id: &str) -> String {
+    validate(client_id).unwrap_or_else(|_| DEFAULT_DISPLAY_NAME.to_string())
+}
+
+/// Whether two names would be mistaken for one another
+pub fn is_confusable(a: &str, b: &str) -> bool {
+    skeleton(a) == skeleton(b)
+}
+
+/// Make a name unique among those already taken, numbering it when it would be confused with one
+pub fn unique<'a>(name: &str, taken: impl Iterator<Item = &'a str> + Clone) -> String {
+    let is_taken = |candidate: &str| taken.clone().any(|other| is_confusable(candidate, other));
+    if !is_taken(name) {
+        return name.to_string();
+    }
+    (2..)
+        .map(|n| {
+            let suffix = format!(" {}", n);
+            let room = MAX_DISPLAY_NAME_LENGTH - suffix.len();
+            let base: String = name.chars().take(room).collect();
+            format!("{}{}", base.trim_end(), suffix)
+        })
+        .find(|candidate| !is_taken(candidate))
+        .unwrap()
+}
+
+/// Characters which render as nothing, or which change how the text around them renders
+fn is_invisible(c: char) -> bool {
+    c.is_control()
+        || matches!(
+            c,
+            '\u{00AD}'
+                | '\u{034F}'
+                | '\u{115F}'
+                | '\u{1160}'
+                | '\u{17B4}'
+                | '\u{17B5}'
+                | '\u{180E}'
+                | '\u{200B}'..='\u{200F}'
+                | '\u{202A}'..='\u{202E}'
+                | '\u{2060}'..='\u{206F}'
+                | '\u{3164}'
+                | '\u{FE00}'..='\u{FE0F}'
+                | '\u{FEFF}'
+                | '\u{FFA0}'
+        )
+}
+
+#[derive(PartialEq)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+}
+
+fn script(c: char) -> Option<Script> {
+    match c {
+        'a'..='z' | 'A'..='Z' | '\u{00C0}'..='\u{024F}' => Some(Script::Latin),
+        '\u{0370}'..='\u{03FF}' => Some(Script::Greek),
+        '\u{0400}'..='\u{052F}' => Some(Script::Cyrillic),
+        _ => None,
+    }
+}
+
+/// Whether a name uses letters from more than one of the scripts which share lookalikes
+fn mixes_scripts(name: &str) -> bool {
+    let mut scripts = name.chars().filter_map(script);
+    match scripts.next() {
+        Some(first) => scripts.any(|other| other != first),
+        None => false,
+    }
+}
+
+/// A folded form of a name, equal for names which look alike
+///
+/// Case, accents, spacing and punctuation are dropped, and letters and digits
+/// which are commonly mistaken for one another are mapped onto a single one
+fn skeleton(name: &str) -> String {
+    let folded: String = name
+        .nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .filter(|c| c.is_alphanumeric())
+        .map(|c| match c {
+            '0' | 'о' | 'ο' => 'o',
+            '1' | 'i' | 'і' | 'ι' | 'ӏ' => 'l',
+            '5' | 'ѕ' => 's',
+            'а' | 'α' => 'a',
+            'е' | 'ε' => 'e',
+            'р' | 'ρ' => 'p',
+            'с' | 'ϲ' => 'c',
+            'у' | 'γ' => 'y',
+            'х' | 'χ' => 'x',
+            'ј' => 'j',
+            'к' | 'κ' => 'k',
+            'ν' => 'v',
+            'т' | 'τ' => 't',
+            'в' | 'β' => 'b',
+            'н' | 'η' => 'n',
+            'м' | 'μ' => 'm',
+            c => c,
+        })
+        .collect();
+    folded.replace("rn", "m").replace("vv", "w")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_trimmed_and_checked() {
+        assert_eq!(
+            validate("  Ada   Lovelace "),
+            Ok(String::from("Ada Lovelace"))
+        );
+        assert!(validate("   ").is_err());
+        assert!(validate(&"a".repeat(MAX_DISPLAY_NAME_LENGTH + 1)).is_err());
+        assert!(validate("ad\u{200B}a").is_err());
+        assert!(validate("ada\u{202E}").is_err());
+        assert!(validate("<ada>").is_err());
+        // a Cyrillic 'а' among Latin letters
+        assert!(validate("\u{0430}da").is_err());
+        assert_eq!(validate("Zoë"), Ok(String::from("Zoë")));
+    }
+
+    #[test]
+    fn lookalikes_are_confused() {
+        assert!(is_confusable("Alice", "alice"));
+        assert!(is_confusable("AIice", "alice"));
+        assert!(is_confusable("b0b", "Bob"));
+        assert!(is_confusable("Zoë", "zoe"));
+        assert!(is_confusable("mia", "rnia"));
+        assert!(!is_confusable("alice", "bob"));
+    }
+
+    #[test]
+    fn taken_names_are_numbered() {
+        let taken = ["alice", "Alice 2"];
+        assert_eq!(unique("bob", taken.iter().copied()), "bob");
+        assert_eq!(unique("ALICE", taken.iter().copied()), "ALICE 3");
+
+        let long = "a".repeat(MAX_DISPLAY_NAME_LENGTH);
+        let numbered = unique(&long, [long.as_str()].iter().copied());
+        assert_eq!(numbered.chars().count(), MAX_DISPLAY_NAME_LENGTH);
+        assert!(numbered.ends_with(" 2"));
+    }
+}
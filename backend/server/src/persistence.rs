@@ -0,0 +1,241 @@
+//! Snapshots of the live sessions and their games, so that they survive a restart
+use crate::{data_types, metrics, session_actor};
+use connect_in_the_dark::types::{GameState, Move};
+use serde::{Deserialize, Serialize};
+use sessions::session_types::Session;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Snapshot {
+    sessions: Vec<SessionSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct SessionSnapshot {
+    id: String,
+    owner: String,
+    client_ids: Vec<String>,
+    /// Missing from snapshots taken before display names existed
+    #[serde(default)]
+    display_names: HashMap<String, String>,
+    game: Option<GameSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct GameSnapshot {
+    board: Vec<Vec<usize>>,
+    turn_index: usize,
+    player_turn_order: Vec<String>,
+    connect_length: usize,
+    /// Missing from snapshots taken before moves were recorded
+    #[serde(default)]
+    moves: Vec<MoveSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct MoveSnapshot {
+    player_index: usize,
+    column: usize,
+    #[serde(default)]
+    hinted: bool,
+}
+
+/// Write every live session to the snapshot file
+///
+/// Returns the number of sessions that were saved
+pub async fn save(path: &Path, sessions: &data_types::SafeSessions) -> Result<usize, String> {
+    let handles = metrics::read(sessions, "sessions").await.handles();
+    let mut snapshot = Snapshot {
+        sessions: Vec::new(),
+    };
+    // sessions which stopped since the handles were taken are left out
+    for handle in handles {
+        if let Some((session, game_state)) = handle.snapshot().await {
+            snapshot.sessions.push(SessionSnapshot {
+                id: session.id.clone(),
+                owner: session.owner.clone(),
+                client_ids: session.get_client_ids(),
+                display_names: session.display_names.clone(),
+                game: game_state.map(|game_state| GameSnapshot {
+                    board: game_state.board,
+                    turn_index: game_state.turn_index,
+                    player_turn_order: game_state.player_turn_order,
+                    connect_length: game_state.connect_length,
+                    moves: game_state
+                        .moves
+                        .iter()
+                        .map(|played| MoveSnapshot {
+                            player_index: played.player_index,
+                            column: played.column,
+                            hinted: played.hinted,
+                        })
+                        .collect(),
+                }),
+            });
+        }
+    }
+
+    let contents = serde_json::to_vec(&snapshot).map_err(|e| e.to_string())?;
+    write_atomically(path, &contents)
+        .map_err(|e| format!("could not write snapshot {}: {}", path.display(), e))?;
+    Ok(snapshot.sessions.len())
+}
+
+/// Replace a file, writing beside it first so that a failed write never leaves half a file behind
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let partial = path.with_extension("partial");
+    fs::write(&partial, contents).and_then(|_| fs::rename(&partial, path))
+}
+
+/// Start the sessions saved in the snapshot file, with every client marked inactive until they reconnect
+///
+/// Returns the ids of the restored sessions, which is empty when there is no snapshot
+pub async fn restore(
+    path: &Path,
+    sessions: &data_types::SafeSessions,
+    config: &data_types::SharedConfig,
+) -> Result<Vec<String>, String> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents =
+        fs::read(path).map_err(|e| format!("could not read snapshot {}: {}", path.display(), e))?;
+    let snapshot: Snapshot = serde_json::from_slice(&contents)
+        .map_err(|e| format!("invalid snapshot {}: {}", path.display(), e))?;
+
+    let mut registry = metrics::write(sessions, "sessions").await;
+    let mut restored = Vec::new();
+    for saved in snapshot.sessions {
+        for client_id in &saved.client_ids {
+            registry.join(client_id, &saved.id);
+        }
+        let session = Session {
+            id: saved.id.clone(),
+            owner: saved.owner,
+            client_statuses: saved
+                .client_ids
+                .into_iter()
+                .map(|id| (id, false))
+                .collect::<HashMap<String, bool>>(),
+            display_names: saved.display_names,
+        };
+        let game_state = saved.game.map(|game| GameState {
+            board: game.board,
+            turn_index: game.turn_index,
+            player_turn_order: game.player_turn_order,
+            connect_length: game.connect_length,
+            moves: game
+                .moves
+                .into_iter()
+                .map(|played| Move {
+                    player_index: played.player_index,
+                    column: played.column,
+                    hinted: played.hinted,
+                })
+                .collect(),
+        });
+        let handle = session_actor::spawn(
+            session,
+            game_state,
+            Vec::new(),
+            registry.finished_games(),
+            config.clone(),
+        );
+        registry.insert(&saved.id, handle);
+        restored.push(saved.id);
+    }
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::session_actor::Registry;
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn restored_sessions_match_the_saved_ones() {
+        let _games = crate::metrics::GAMES_IN_PROGRESS_TESTS.lock().await;
+        let path = std::env::temp_dir().join(format!("citd-snapshot-{}.json", std::process::id()));
+
+        let mut client_statuses = HashMap::new();
+        client_statuses.insert(String::from("alice"), true);
+        client_statuses.insert(String::from("bob"), false);
+        let session = Session {
+            id: String::from("ABCDE"),
+            owner: String::from("alice"),
+            client_statuses,
+            display_names: HashMap::from([(String::from("bob"), String::from("Bob"))]),
+        };
+        let game_state = GameState {
+            board: vec![vec![0, usize::MAX], vec![1, usize::MAX]],
+            turn_index: 0,
+            player_turn_order: vec![String::from("alice"), String::from("bob")],
+            connect_length: 4,
+            moves: vec![
+                Move {
+                    player_index: 0,
+                    column: 0,
+                    hinted: false,
+                },
+                Move {
+                    player_index: 1,
+                    column: 1,
+                    hinted: true,
+                },
+            ],
+        };
+        let config: data_types::SharedConfig = Arc::new(Config::default());
+        let sessions: data_types::SafeSessions = Arc::new(RwLock::new(Registry::default()));
+        let handle = session_actor::spawn(
+            session,
+            Some(game_state.clone()),
+            Vec::new(),
+            Registry::default().finished_games(),
+            config.clone(),
+        );
+        sessions.write().await.insert("ABCDE", handle);
+
+        assert_eq!(save(&path, &sessions).await, Ok(1));
+
+        let restored_sessions: data_types::SafeSessions =
+            Arc::new(RwLock::new(Registry::default()));
+        let restored = restore(&path, &restored_sessions, &config).await;
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(restored, Ok(vec![String::from("ABCDE")]));
+        let registry = restored_sessions.read().await;
+        assert_eq!(registry.session_of("bob"), Some(String::from("ABCDE")));
+        let (restored_session, restored_game) =
+            registry.get("ABCDE").unwrap().snapshot().await.unwrap();
+        assert_eq!(restored_session.owner, "alice");
+        assert!(restored_session
+            .get_clients_with_active_status(true)
+            .is_empty());
+        assert_eq!(restored_session.get_num_clients(), 2);
+        assert_eq!(restored_session.get_display_name("bob"), "Bob");
+        assert_eq!(restored_session.get_display_name("alice"), "alice");
+
+        let restored_game = restored_game.unwrap();
+        assert_eq!(restored_game.board, game_state.board);
+        assert_eq!(
+            restored_game.player_turn_order,
+            game_state.player_turn_order
+        );
+        assert_eq!(restored_game.moves, game_state.moves);
+    }
+
+    #[tokio::test]
+    async fn missing_snapshots_restore_nothing() {
+        let config: data_types::SharedConfig = Arc::new(Config::default());
+        let sessions: data_types::SafeSessions = Arc::new(RwLock::new(Registry::default()));
+        let path = Path::new("this-snapshot-does-not-exist.json");
+
+        assert_eq!(restore(path, &sessions, &config).await, Ok(vec![]));
+    }
+}
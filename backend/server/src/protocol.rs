@@ -0,0 +1,70 @@
+use crate::codec;
+use crate::shared_types::{EventBuilder, ServerEvent, ServerEventCode, ServerEventDataBuilder};
+use connect_in_the_dark::types::RULE_VARIANTS;
+use sessions::session_types::Encoding;
+use std::collections::HashMap;
+
+/// The version of the event protocol spoken by this server.
+///
+/// This must be bumped whenever an event code is reordered or removed,
+/// or when the meaning of an existing payload field changes
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The oldest client protocol version which the server still understands
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Optional protocol features that clients may rely on
+pub const FEATURES: [&str; 2] = ["request_ids", "msgpack"];
+
+/// Close code for a client which did not open with a `Hello` event
+pub const CLOSE_HANDSHAKE_REQUIRED: u16 = 4000;
+/// Close code for a client speaking a protocol version the server does not support
+pub const CLOSE_UNSUPPORTED_PROTOCOL: u16 = 4001;
+/// Close code for a client asking for an encoding the server does not support
+pub const CLOSE_UNSUPPORTED_ENCODING: u16 = 4002;
+/// Close code for a client which kept sending events over its rate limits
+pub const CLOSE_RATE_LIMITED: u16 = 4003;
+/// Close code for a client which stayed silent for longer than the heartbeat timeout
+pub const CLOSE_HEARTBEAT_TIMEOUT: u16 = 4004;
+/// Close code for a client asking for a display name which is not allowed
+pub const CLOSE_INVALID_DISPLAY_NAME: u16 = 4005;
+/// Close code for a client which sent a message larger than the server accepts
+pub const CLOSE_MESSAGE_TOO_BIG: u16 = 1009;
+
+/// Whether a client stating the given protocol version can be served
+pub fn is_supported_version(version: u32) -> bool {
+    (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version)
+}
+
+/// The reply to a successful `Hello`, describing what the server supports,
+/// the encoding it will send events to the client in and the name the client is shown with
+pub fn welcome_event(encoding: Encoding, client_id: &str, display_name: &str) -> ServerEvent {
+    EventBuilder::default()
+        .event_code(ServerEventCode::Welcome)
+        .data(
+            ServerEventDataBuilder::default()
+                .protocol_version(PROTOCOL_VERSION)
+                .rule_variants(
+                    RULE_VARIANTS
+                        .iter()
+                        .map(|variant| variant.to_string())
+                        .collect::<Vec<String>>(),
+                )
+                .features(
+                    FEATURES
+                        .iter()
+                        .map(|feature| feature.to_string())
+                        .collect::<Vec<String>>(),
+                )
+                .encoding(codec::encoding_name(encoding))
+                .client_id(client_id)
+                .display_names(HashMap::from([(
+                    client_id.to_string(),
+                    display_name.to_string(),
+                )]))
+                .build()
+                .unwrap(),
+        )
+        .build()
+        .unwrap()
+}
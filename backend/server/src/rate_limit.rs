@@ -0,0 +1,197 @@
+use crate::config::LimitsConfig;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// What should happen to an event a client sent
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Allow,
+    /// Drop the event, naming the limit it went over
+    Reject(&'static str),
+    /// The client kept going over its limits and should be disconnected
+    Disconnect,
+}
+
+/// The limits a single connection is held to
+pub struct ClientLimiter {
+    events: TokenBucket,
+    sessions: SlidingWindow,
+    /// Rejections since the last event which was allowed
+    violations: u32,
+    max_violations: u32,
+}
+
+impl ClientLimiter {
+    pub fn new(limits: &LimitsConfig) -> Self {
+        ClientLimiter {
+            events: TokenBucket::new(limits.event_burst, limits.events_per_second),
+            sessions: SlidingWindow::new(limits.sessions_per_minute, Duration::from_secs(60)),
+            violations: 0,
+            max_violations: limits.max_rate_violations,
+        }
+    }
+
+    /// Decide whether an event can be handled, given whether it would create a session
+    pub fn check(&mut self, creates_session: bool) -> Verdict {
+        self.check_at(creates_session, Instant::now())
+    }
+
+    fn check_at(&mut self, creates_session: bool, now: Instant) -> Verdict {
+        let limit = if !self.events.try_take(now) {
+            Some("events")
+        } else if creates_session && !self.sessions.try_record(now) {
+            Some("sessions")
+        } else {
+            None
+        };
+
+        match limit {
+            None => {
+                self.violations = 0;
+                Verdict::Allow
+            }
+            Some(limit) => {
+                self.violations += 1;
+                if self.violations >= self.max_violations {
+                    Verdict::Disconnect
+                } else {
+                    Verdict::Reject(limit)
+                }
+            }
+        }
+    }
+}
+
+/// Allows bursts of up to `capacity` events, refilled at a steady rate
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_second: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: u32, refill_per_second: u32) -> Self {
+        TokenBucket {
+            capacity: capacity.into(),
+            tokens: capacity.into(),
+            refill_per_second: refill_per_second.into(),
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Allows at most `limit` events within any span of `window`
+struct SlidingWindow {
+    limit: usize,
+    window: Duration,
+    events: VecDeque<Instant>,
+}
+
+impl SlidingWindow {
+    fn new(limit: u32, window: Duration) -> Self {
+        SlidingWindow {
+            limit: limit as usize,
+            window,
+            events: VecDeque::new(),
+        }
+    }
+
+    fn try_record(&mut self, now: Instant) -> bool {
+        while let Some(oldest) = self.events.front() {
+            if now.saturating_duration_since(*oldest) < self.window {
+                break;
+            }
+            self.events.pop_front();
+        }
+
+        if self.events.len() < self.limit {
+            self.events.push_back(now);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> LimitsConfig {
+        LimitsConfig {
+            event_burst: 3,
+            events_per_second: 1,
+            sessions_per_minute: 2,
+            max_rate_violations: 3,
+            ..LimitsConfig::default()
+        }
+    }
+
+    #[test]
+    fn bursts_are_allowed_then_refilled() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(3, 1);
+
+        assert!((0..3).all(|_| bucket.try_take(start)));
+        assert!(!bucket.try_take(start));
+        assert!(bucket.try_take(start + Duration::from_secs(1)));
+        assert!(!bucket.try_take(start + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn sessions_are_limited_per_window() {
+        let start = Instant::now();
+        let mut limiter = ClientLimiter::new(&limits());
+
+        assert_eq!(limiter.check_at(true, start), Verdict::Allow);
+        assert_eq!(limiter.check_at(true, start), Verdict::Allow);
+        assert_eq!(limiter.check_at(true, start), Verdict::Reject("sessions"));
+        let later = start + Duration::from_secs(60);
+        assert_eq!(limiter.check_at(true, later), Verdict::Allow);
+    }
+
+    #[test]
+    fn repeated_violations_disconnect() {
+        let start = Instant::now();
+        let mut limiter = ClientLimiter::new(&limits());
+
+        for _ in 0..3 {
+            assert_eq!(limiter.check_at(false, start), Verdict::Allow);
+        }
+        assert_eq!(limiter.check_at(false, start), Verdict::Reject("events"));
+        assert_eq!(limiter.check_at(false, start), Verdict::Reject("events"));
+        assert_eq!(limiter.check_at(false, start), Verdict::Disconnect);
+    }
+
+    #[test]
+    fn allowed_events_forgive_violations() {
+        let start = Instant::now();
+        let mut limiter = ClientLimiter::new(&limits());
+
+        for _ in 0..3 {
+            limiter.check_at(false, start);
+        }
+        assert_eq!(limiter.check_at(false, start), Verdict::Reject("events"));
+        assert_eq!(limiter.check_at(false, start), Verdict::Reject("events"));
+
+        let later = start + Duration::from_secs(1);
+        assert_eq!(limiter.check_at(false, later), Verdict::Allow);
+        assert_eq!(limiter.check_at(false, later), Verdict::Reject("events"));
+    }
+}
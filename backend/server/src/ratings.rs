@@ -0,0 +1,410 @@
+//! Skill ratings of registered players, updated from every game they finish
+//!
+//! Games of two players are rated with Elo. Games of more players are rated as though
+//! every pair of them had played each other, with the winner beating everyone and the rest drawing
+use crate::config::RatingsConfig;
+use crate::session_actor::FinishedGame;
+use crate::{data_types, handler, metrics, persistence};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, error, info, warn};
+use warp::hyper::StatusCode;
+use warp::{Rejection, Reply};
+
+type Result<T> = std::result::Result<T, Rejection>;
+
+/// How many players the leaderboard shows unless asked for fewer
+const MAX_LEADERBOARD_LENGTH: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Outcome {
+    Win,
+    Loss,
+    Draw,
+}
+
+/// The rating of a player once a game had finished
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct RatingPoint {
+    /// Seconds since the Unix epoch
+    finished_at: u64,
+    rating: f64,
+}
+
+/// A rated game as one of its players saw it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct GameRecord {
+    finished_at: u64,
+    session_id: String,
+    players: Vec<String>,
+    winner: Option<String>,
+    outcome: Outcome,
+    rating_change: f64,
+}
+
+/// Everything known about the rated games of a player
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlayerRecord {
+    id: String,
+    rating: f64,
+    wins: u32,
+    losses: u32,
+    draws: u32,
+    /// Oldest first
+    history: Vec<RatingPoint>,
+    /// Newest first
+    recent_games: Vec<GameRecord>,
+}
+
+impl PlayerRecord {
+    fn new(id: &str, config: &RatingsConfig) -> Self {
+        PlayerRecord {
+            id: id.to_string(),
+            rating: config.initial_rating.into(),
+            wins: 0,
+            losses: 0,
+            draws: 0,
+            history: Vec::new(),
+            recent_games: Vec::new(),
+        }
+    }
+}
+
+/// A row of the leaderboard
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Standing {
+    rank: usize,
+    id: String,
+    rating: i64,
+    wins: u32,
+    losses: u32,
+    draws: u32,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct RatingsFile {
+    players: Vec<PlayerRecord>,
+}
+
+/// The ratings of every registered player who finished a rated game
+#[derive(Default)]
+pub struct Ratings {
+    path: Option<PathBuf>,
+    players: HashMap<String, PlayerRecord>,
+}
+
+impl Ratings {
+    /// Read the ratings from their file, which may not exist yet
+    pub fn load(path: Option<PathBuf>) -> std::result::Result<Ratings, String> {
+        let mut ratings = Ratings {
+            path,
+            ..Ratings::default()
+        };
+        let path = match &ratings.path {
+            Some(path) if path.exists() => path,
+            _ => return Ok(ratings),
+        };
+        let contents = fs::read(path)
+            .map_err(|e| format!("could not read ratings {}: {}", path.display(), e))?;
+        let file: RatingsFile = serde_json::from_slice(&contents)
+            .map_err(|e| format!("invalid ratings {}: {}", path.display(), e))?;
+        for player in file.players {
+            ratings.players.insert(player.id.clone(), player);
+        }
+        Ok(ratings)
+    }
+
+    pub fn player(&self, id: &str) -> Option<&PlayerRecord> {
+        self.players.get(id)
+    }
+
+    /// The rating of a player, who starts from the initial rating before their first game
+    pub fn rating(&self, id: &str, config: &RatingsConfig) -> f64 {
+        self.players
+            .get(id)
+            .map(|player| player.rating)
+            .unwrap_or_else(|| config.initial_rating.into())
+    }
+
+    /// The best rated players, best first
+    pub fn leaderboard(&self, limit: usize) -> Vec<Standing> {
+        let mut players: Vec<&PlayerRecord> = self.players.values().collect();
+        players.sort_by(|a, b| b.rating.total_cmp(&a.rating).then(a.id.cmp(&b.id)));
+        players
+            .into_iter()
+            .take(limit)
+            .enumerate()
+            .map(|(index, player)| Standing {
+                rank: index + 1,
+                id: player.id.clone(),
+                rating: player.rating.round() as i64,
+                wins: player.wins,
+                losses: player.losses,
+                draws: player.draws,
+            })
+            .collect()
+    }
+
+    /// Rate a finished game between the given players, then write every rating back to the file
+    fn record(
+        &mut self,
+        game: &FinishedGame,
+        rated: &[String],
+        config: &RatingsConfig,
+    ) -> std::result::Result<(), String> {
+        let finished_at = game.finished_at;
+        let ratings: Vec<f64> = rated.iter().map(|id| self.rating(id, config)).collect();
+        let winner = game
+            .winner
+            .as_ref()
+            .and_then(|winner| rated.iter().position(|id| id == winner));
+        let changes = rating_changes(&ratings, winner, config.k_factor.into());
+
+        // a game won by a guest is a draw between the rated players, since guests never played
+        for (index, (id, change)) in rated.iter().zip(changes).enumerate() {
+            let outcome = match winner {
+                None => Outcome::Draw,
+                Some(winner) if winner == index => Outcome::Win,
+                Some(_) => Outcome::Loss,
+            };
+            let player = self
+                .players
+                .entry(id.clone())
+                .or_insert_with(|| PlayerRecord::new(id, config));
+            player.rating += change;
+            match outcome {
+                Outcome::Win => player.wins += 1,
+                Outcome::Loss => player.losses += 1,
+                Outcome::Draw => player.draws += 1,
+            }
+            player.history.push(RatingPoint {
+                finished_at,
+                rating: player.rating,
+            });
+            player.recent_games.insert(
+                0,
+                GameRecord {
+                    finished_at,
+                    session_id: game.session_id.clone(),
+                    players: game.players.clone(),
+                    winner: game.winner.clone(),
+                    outcome,
+                    rating_change: change,
+                },
+            );
+            player.recent_games.truncate(config.recent_games);
+        }
+        self.save()
+    }
+
+    fn save(&self) -> std::result::Result<(), String> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let file = RatingsFile {
+            players: self.players.values().cloned().collect(),
+        };
+        let contents = serde_json::to_vec(&file).map_err(|e| e.to_string())?;
+        persistence::write_atomically(path, &contents)
+            .map_err(|e| format!("could not write ratings {}: {}", path.display(), e))
+    }
+}
+
+/// How much the rating of each player moves after a game, given who of them won
+///
+/// Each player is scored against every other one, so the changes always add up to nothing
+fn rating_changes(ratings: &[f64], winner: Option<usize>, k_factor: f64) -> Vec<f64> {
+    let opponents = ratings.len().saturating_sub(1).max(1) as f64;
+    (0..ratings.len())
+        .map(|i| {
+            let surprise: f64 = (0..ratings.len())
+                .filter(|j| *j != i)
+                .map(|j| {
+                    let expected = 1.0 / (1.0 + 10f64.powf((ratings[j] - ratings[i]) / 400.0));
+                    let score = match winner {
+                        Some(w) if w == i => 1.0,
+                        Some(w) if w == j => 0.0,
+                        _ => 0.5,
+                    };
+                    score - expected
+                })
+                .sum();
+            k_factor * surprise / opponents
+        })
+        .collect()
+}
+
+/// Rate every finished game with at least two registered players in it, for as long as the server runs
+///
+/// Guests are left out of the rating as though they had not played
+pub async fn record_finished_games(
+    mut finished_games: broadcast::Receiver<FinishedGame>,
+    ratings: data_types::SafeRatings,
+    accounts: data_types::SafeAccounts,
+    config: data_types::SharedConfig,
+) {
+    loop {
+        let game = match finished_games.recv().await {
+            Ok(game) => game,
+            Err(RecvError::Lagged(missed)) => {
+                warn!(
+                    missed,
+                    "finished games were not rated, too many arrived at once"
+                );
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+
+        let rated: Vec<String> = {
+            let accounts = metrics::read(&accounts, "accounts").await;
+            game.players
+                .iter()
+                .filter(|id| accounts.is_registered(id))
+                .cloned()
+                .collect()
+        };
+        if rated.len() < 2 {
+            debug!(session_id = %game.session_id, "game was not rated, too few registered players");
+            continue;
+        }
+
+        let recorded =
+            metrics::write(&ratings, "ratings")
+                .await
+                .record(&game, &rated, &config.ratings);
+        match recorded {
+            Ok(()) => info!(session_id = %game.session_id, players = rated.len(), "rated game"),
+            Err(e) => error!(error = %e, "could not save ratings"),
+        }
+    }
+}
+
+/// Query parameters of the leaderboard
+#[derive(Deserialize)]
+pub struct LeaderboardParams {
+    limit: Option<usize>,
+}
+
+/// Lists the best rated players
+pub async fn leaderboard(
+    params: LeaderboardParams,
+    ratings: data_types::SafeRatings,
+) -> Result<impl Reply> {
+    let limit = params
+        .limit
+        .unwrap_or(MAX_LEADERBOARD_LENGTH)
+        .min(MAX_LEADERBOARD_LENGTH);
+    let standings = metrics::read(&ratings, "ratings").await.leaderboard(limit);
+    Ok(warp::reply::json(&standings))
+}
+
+/// Shows the rating history, record and recent games of a player
+pub async fn player(id: String, ratings: data_types::SafeRatings) -> Result<impl Reply> {
+    let id = match handler::decode_path_id(&id) {
+        Ok(id) => id,
+        Err(status) => return Ok(status.into_response()),
+    };
+    Ok(match metrics::read(&ratings, "ratings").await.player(&id) {
+        Some(player) => warp::reply::json(player).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evenly_matched_players_trade_half_the_k_factor() {
+        assert_eq!(
+            rating_changes(&[1500.0, 1500.0], Some(0), 32.0),
+            [16.0, -16.0]
+        );
+        assert_eq!(rating_changes(&[1500.0, 1500.0], None, 32.0), [0.0, 0.0]);
+
+        // an upset moves the ratings further than an expected win
+        let upset = rating_changes(&[1400.0, 1600.0], Some(0), 32.0);
+        let expected = rating_changes(&[1400.0, 1600.0], Some(1), 32.0);
+        assert!(upset[0] > expected[1]);
+    }
+
+    #[test]
+    fn multiplayer_changes_add_up_to_nothing() {
+        let changes = rating_changes(&[1500.0, 1550.0, 1450.0], Some(2), 32.0);
+        assert!(changes.iter().sum::<f64>().abs() < 1e-9);
+        assert!(changes[2] > 0.0);
+        assert!(changes[0] < 0.0 && changes[1] < 0.0);
+    }
+
+    #[test]
+    fn records_keep_the_latest_games() {
+        let config = RatingsConfig {
+            recent_games: 2,
+            ..RatingsConfig::default()
+        };
+        let mut ratings = Ratings::default();
+        let players = vec![String::from("alice"), String::from("bob")];
+        for (at, winner) in [(1, Some("alice")), (2, Some("alice")), (3, None)] {
+            let game = FinishedGame {
+                session_id: String::from("ABCDE"),
+                players: players.clone(),
+                winner: winner.map(String::from),
+                board_width: 7,
+                board_height: 6,
+                connect_length: 4,
+                moves: Vec::new(),
+                started_at: at,
+                finished_at: at,
+            };
+            ratings.record(&game, &players, &config).unwrap();
+        }
+
+        let alice = ratings.player("alice").unwrap();
+        assert_eq!((alice.wins, alice.losses, alice.draws), (2, 0, 1));
+        assert_eq!(alice.history.len(), 3);
+        assert_eq!(alice.recent_games.len(), 2);
+        assert_eq!(alice.recent_games[0].outcome, Outcome::Draw);
+        assert_eq!(ratings.player("bob").unwrap().losses, 2);
+
+        let leaderboard = ratings.leaderboard(10);
+        assert_eq!(leaderboard[0].id, "alice");
+        assert_eq!(leaderboard[1].rank, 2);
+    }
+
+    #[test]
+    fn games_won_by_guests_are_draws_between_the_rated() {
+        let config = RatingsConfig::default();
+        let mut ratings = Ratings::default();
+        let rated = vec![String::from("alice"), String::from("bob")];
+        let game = FinishedGame {
+            session_id: String::from("ABCDE"),
+            players: vec![
+                String::from("alice"),
+                String::from("guest"),
+                String::from("bob"),
+            ],
+            winner: Some(String::from("guest")),
+            board_width: 7,
+            board_height: 6,
+            connect_length: 4,
+            moves: Vec::new(),
+            started_at: 1,
+            finished_at: 1,
+        };
+        ratings.record(&game, &rated, &config).unwrap();
+
+        for id in &rated {
+            let player = ratings.player(id).unwrap();
+            assert_eq!((player.wins, player.losses, player.draws), (0, 0, 1));
+            assert_eq!(player.recent_games[0].outcome, Outcome::Draw);
+            assert_eq!(player.recent_games[0].rating_change, 0.0);
+            assert_eq!(player.recent_games[0].winner.as_deref(), Some("guest"));
+        }
+    }
+}
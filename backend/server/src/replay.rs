@@ -0,0 +1,290 @@
+//! Finished games played again move by move, showing how little each player could see
+//!
+//! A move is a blunder when its player had a win and played elsewhere, left the next player
+//! a win they could have blocked, or opened a win for the next player. A blunder is blind
+//! when it could not have been told apart from a good move on the board its player saw
+use crate::archive::ArchivedGame;
+use crate::game_engine::ShareableGameData;
+use connect_in_the_dark::types::GameState;
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BlunderKind {
+    /// The player could have won with another move
+    MissedWin,
+    /// The next player could already win, and this move could have stopped them
+    MissedBlock,
+    /// The next player can win because of this move
+    EnabledWin,
+}
+
+/// A move which lost something its player had
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Blunder {
+    kind: BlunderKind,
+    /// Where the win which was missed, or which the next player now has, could be played
+    columns: Vec<usize>,
+    /// Whether the board its player saw hid the blunder from them
+    blind: bool,
+}
+
+/// The board a player saw once a move was played
+#[derive(Serialize, Debug, PartialEq)]
+pub struct View {
+    player: String,
+    /// Only the pieces of the player, in the shape of `play_indexes`
+    board: Vec<Vec<usize>>,
+}
+
+/// A single move of a replay
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Frame {
+    /// Counted from 0
+    turn: usize,
+    player: String,
+    column: usize,
+    /// Counted from the bottom of the column
+    row: usize,
+    /// Whether the player was given a hint before playing the move
+    hinted: bool,
+    /// The whole board once the piece was dropped, in the shape of `play_indexes`
+    board: Vec<Vec<usize>>,
+    /// The board as the player saw it when choosing the move, with only their own pieces on it
+    seen: Vec<Vec<usize>>,
+    /// What every player saw once the piece was dropped, in their turn order
+    views: Vec<View>,
+    blunder: Option<Blunder>,
+}
+
+/// A finished game laid out to be stepped through move by move
+#[derive(Serialize, Debug)]
+pub struct Replay {
+    game: ArchivedGame,
+    frames: Vec<Frame>,
+}
+
+impl Replay {
+    /// Play the moves of an archived game again from an empty board
+    pub fn of(game: ArchivedGame) -> Result<Replay, String> {
+        let mut state = game.initial_state();
+        let mut frames = Vec::with_capacity(game.moves.len());
+        for (turn, played) in game.moves.iter().enumerate() {
+            let player_index = state
+                .get_player_index(&played.player)
+                .ok_or_else(|| format!("move {} was played by a stranger", turn))?;
+            let seen = state.as_shared_game_data(Some(&played.player)).play_indexes;
+            let row = state
+                .board
+                .get(played.column)
+                .and_then(|column| column.iter().position(|cell| *cell == usize::MAX))
+                .ok_or_else(|| format!("move {} was played into a full column", turn))?;
+
+            let blunder =
+                find_blunder(&state, played.column, player_index).map(|(kind, columns)| {
+                    let seen_state = GameState {
+                        board: settled(&seen, player_index, state.player_turn_order.len()),
+                        ..state.clone()
+                    };
+                    let blind = find_blunder(&seen_state, played.column, player_index)
+                        .is_none_or(|(seen_kind, _)| seen_kind != kind);
+                    Blunder {
+                        kind,
+                        columns,
+                        blind,
+                    }
+                });
+
+            state.play(played.column, player_index)?;
+            let views = state
+                .player_turn_order
+                .iter()
+                .map(|player| View {
+                    player: player.clone(),
+                    board: state.as_shared_game_data(Some(player)).play_indexes,
+                })
+                .collect();
+            frames.push(Frame {
+                turn,
+                player: played.player.clone(),
+                column: played.column,
+                row,
+                hinted: played.hinted,
+                board: state.board.clone(),
+                seen,
+                views,
+                blunder,
+            });
+        }
+        Ok(Replay { game, frames })
+    }
+}
+
+/// The board a player saw, with every cell under one of their own pieces taken by `unknown`
+///
+/// Pieces rest on the one below them, so a player knows each column is filled up to their own
+/// highest piece in it even when they cannot see by whom, and a piece they try can only land above it
+fn settled(seen: &[Vec<usize>], player_index: usize, unknown: usize) -> Vec<Vec<usize>> {
+    seen.iter()
+        .map(|column| {
+            let height = column
+                .iter()
+                .rposition(|cell| *cell == player_index)
+                .map_or(0, |row| row + 1);
+            column
+                .iter()
+                .enumerate()
+                .map(|(row, cell)| match row < height && *cell != player_index {
+                    true => unknown,
+                    false => *cell,
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The columns a player would win the game by dropping their next piece into
+fn winning_columns(state: &GameState, player_index: usize) -> Vec<usize> {
+    (0..state.board.len())
+        .filter(|column| {
+            let mut trial = state.clone();
+            matches!(trial.play(*column, player_index), Ok(true))
+        })
+        .collect()
+}
+
+/// What a player threw away by dropping their piece into a column of this board, if anything
+fn find_blunder(
+    state: &GameState,
+    column: usize,
+    player_index: usize,
+) -> Option<(BlunderKind, Vec<usize>)> {
+    let wins = winning_columns(state, player_index);
+    if !wins.is_empty() {
+        return match wins.contains(&column) {
+            true => None,
+            false => Some((BlunderKind::MissedWin, wins)),
+        };
+    }
+
+    let mut after = state.clone();
+    after.play(column, player_index).ok()?;
+    let next_index = after.turn_index;
+    if next_index == player_index {
+        return None;
+    }
+    let threats = winning_columns(state, next_index);
+    let left = winning_columns(&after, next_index);
+    if left.is_empty() {
+        return None;
+    }
+    // a single threat could have been blocked by playing on it
+    match left.iter().any(|threat| !threats.contains(threat)) {
+        true => Some((BlunderKind::EnabledWin, left)),
+        false if threats.len() == 1 => Some((BlunderKind::MissedBlock, left)),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::ArchivedMove;
+
+    fn archived_game(columns: &[usize]) -> ArchivedGame {
+        let players = vec![String::from("alice"), String::from("bob")];
+        ArchivedGame {
+            id: String::from("game"),
+            session_id: String::from("ABCDE"),
+            winner: None,
+            rule_variant: String::from("classic"),
+            board_width: 7,
+            board_height: 6,
+            connect_length: 4,
+            moves: columns
+                .iter()
+                .enumerate()
+                .map(|(turn, column)| ArchivedMove {
+                    player: players[turn % 2].clone(),
+                    column: *column,
+                    hinted: false,
+                })
+                .collect(),
+            players,
+            started_at: 0,
+            finished_at: 0,
+        }
+    }
+
+    #[test]
+    fn replays_show_what_each_player_saw() {
+        let replay = Replay::of(archived_game(&[0, 1, 0, 1, 0, 1, 0])).unwrap();
+        assert_eq!(replay.frames.len(), 7);
+
+        let second = &replay.frames[1];
+        assert_eq!(
+            (second.player.as_str(), second.column, second.row),
+            ("bob", 1, 0)
+        );
+        // bob could not see the piece alice had already dropped
+        assert_eq!(second.seen[0][0], usize::MAX);
+        assert_eq!(second.board[0][0], 0);
+        assert_eq!(second.board[1][0], 1);
+        assert_eq!(second.views[0].player, "alice");
+        assert_eq!(
+            (second.views[0].board[0][0], second.views[0].board[1][0]),
+            (0, usize::MAX)
+        );
+        assert_eq!(
+            (second.views[1].board[0][0], second.views[1].board[1][0]),
+            (usize::MAX, 1)
+        );
+
+        let last = replay.frames.last().unwrap();
+        assert_eq!((last.column, last.row), (0, 3));
+        assert_eq!(last.seen[0][..3], [0, 0, 0]);
+    }
+
+    #[test]
+    fn hidden_pieces_under_a_players_own_still_hold_them_up() {
+        // alice lines up three along the bottom, with her piece next to them resting on one of bob's
+        let replay = Replay::of(archived_game(&[1, 0, 0, 6, 2, 6, 3, 6, 0])).unwrap();
+        assert_eq!(replay.frames[8].seen[0][..2], [usize::MAX, 0]);
+        // so she could tell her last piece would not land next to the three
+        assert_eq!(
+            replay.frames[8].blunder,
+            Some(Blunder {
+                kind: BlunderKind::MissedWin,
+                columns: vec![4],
+                blind: false,
+            })
+        );
+    }
+
+    #[test]
+    fn blunders_are_found_and_marked_blind() {
+        let replay = Replay::of(archived_game(&[0, 1, 0, 1, 0, 1, 2])).unwrap();
+        assert!(replay.frames[..5]
+            .iter()
+            .all(|frame| frame.blunder.is_none()));
+
+        // bob could not see the three pieces alice stacked
+        assert_eq!(
+            replay.frames[5].blunder,
+            Some(Blunder {
+                kind: BlunderKind::MissedBlock,
+                columns: vec![0],
+                blind: true,
+            })
+        );
+        // while alice could see her own
+        assert_eq!(
+            replay.frames[6].blunder,
+            Some(Blunder {
+                kind: BlunderKind::MissedWin,
+                columns: vec![0],
+                blind: false,
+            })
+        );
+    }
+}
@@ -0,0 +1,1345 @@
+//! Every session runs as its own task which owns its `Session` and `GameState`,
+//! so that games are played concurrently instead of queueing on shared locks
+use crate::game_engine::{self, ShareableGameData};
+use crate::matchmaking::Queue;
+use crate::shared_types::{EventBuilder, ServerEvent, ServerEventCode, ServerEventDataBuilder};
+use crate::{data_types, metrics, names};
+use connect_in_the_dark::belief::{self, PlayerView};
+use connect_in_the_dark::types::{create_game_board, GameState, Move};
+use nanorand::{Rng, WyRand};
+use sessions::session_types::{Client, Session};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::{debug, error, info, warn, Instrument};
+
+/// How many finished games are held for subscribers which fall behind
+const FINISHED_GAMES_BUFFER: usize = 256;
+
+/// The outcome of a game which was played to its end
+#[derive(Debug, Clone, PartialEq)]
+pub struct FinishedGame {
+    pub session_id: String,
+    /// The players in their turn order
+    pub players: Vec<String>,
+    /// `None` when the board filled up without anyone winning
+    pub winner: Option<String>,
+    pub board_width: usize,
+    pub board_height: usize,
+    pub connect_length: usize,
+    /// Every move of the game, in order
+    pub moves: Vec<Move>,
+    /// Seconds since the Unix epoch
+    pub started_at: u64,
+    pub finished_at: u64,
+}
+
+impl FinishedGame {
+    fn new(
+        session_id: &str,
+        game_state: &GameState,
+        winner: Option<String>,
+        started_at: u64,
+    ) -> Self {
+        FinishedGame {
+            session_id: session_id.to_string(),
+            players: game_state.player_turn_order.clone(),
+            winner,
+            board_width: game_state.board.len(),
+            board_height: game_state.board.first().map_or(0, Vec::len),
+            connect_length: game_state.connect_length,
+            moves: game_state.moves.clone(),
+            started_at,
+            finished_at: unix_now(),
+        }
+    }
+}
+
+/// Seconds since the Unix epoch
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Where every session announces its finished games, for anything which keeps track of them
+pub type FinishedGames = broadcast::Sender<FinishedGame>;
+
+/// The live sessions, each reached through the handle of its task
+pub struct Registry {
+    sessions: HashMap<String, SessionHandle>,
+    /// The session each client belongs to, kept while they are disconnected so they can return to it
+    memberships: HashMap<String, String>,
+    /// The clients waiting to be matched into a new session
+    queue: Queue,
+    finished_games: FinishedGames,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry {
+            sessions: HashMap::new(),
+            memberships: HashMap::new(),
+            queue: Queue::default(),
+            finished_games: broadcast::channel(FINISHED_GAMES_BUFFER).0,
+        }
+    }
+}
+
+impl Registry {
+    /// The channel new sessions announce their finished games on
+    pub fn finished_games(&self) -> FinishedGames {
+        self.finished_games.clone()
+    }
+    pub fn subscribe_finished_games(&self) -> broadcast::Receiver<FinishedGame> {
+        self.finished_games.subscribe()
+    }
+    pub fn queue(&self) -> &Queue {
+        &self.queue
+    }
+    pub fn queue_mut(&mut self) -> &mut Queue {
+        &mut self.queue
+    }
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+    pub fn contains(&self, session_id: &str) -> bool {
+        self.sessions.contains_key(session_id)
+    }
+    pub fn get(&self, session_id: &str) -> Option<SessionHandle> {
+        self.sessions.get(session_id).cloned()
+    }
+    pub fn handles(&self) -> Vec<SessionHandle> {
+        self.sessions.values().cloned().collect()
+    }
+    pub fn insert(&mut self, session_id: &str, handle: SessionHandle) {
+        self.sessions.insert(session_id.to_string(), handle);
+    }
+    /// Forget a session along with every membership of it
+    pub fn remove(&mut self, session_id: &str) {
+        self.sessions.remove(session_id);
+        self.memberships
+            .retain(|_, member_of| member_of != session_id);
+    }
+    pub fn session_of(&self, client_id: &str) -> Option<String> {
+        self.memberships.get(client_id).cloned()
+    }
+    /// The handle of the session a client belongs to
+    pub fn session_of_client(&self, client_id: &str) -> Option<(String, SessionHandle)> {
+        let session_id = self.session_of(client_id)?;
+        let handle = self.get(&session_id)?;
+        Some((session_id, handle))
+    }
+    pub fn join(&mut self, client_id: &str, session_id: &str) {
+        self.memberships
+            .insert(client_id.to_string(), session_id.to_string());
+    }
+    pub fn leave(&mut self, client_id: &str) {
+        self.memberships.remove(client_id);
+    }
+}
+
+/// Whether a client made it into a session
+#[derive(Debug, PartialEq)]
+pub enum JoinOutcome {
+    Joined,
+    /// The client was told why they could not join
+    Refused,
+}
+
+/// Work sent to the task of a session
+///
+/// Commands answer through `done` once they have been handled,
+/// so that callers can reply after every event the command caused
+enum Command {
+    Join {
+        client: Client,
+        request_id: Option<String>,
+        done: oneshot::Sender<JoinOutcome>,
+    },
+    /// Answers whether no active client is left
+    Leave {
+        client_id: String,
+        done: oneshot::Sender<bool>,
+    },
+    Connect {
+        client: Client,
+    },
+    /// Answers whether no active client is left
+    Disconnect {
+        client_id: String,
+        done: oneshot::Sender<bool>,
+    },
+    Describe {
+        client_id: String,
+        request_id: Option<String>,
+        done: oneshot::Sender<()>,
+    },
+    /// Answers whether a reply was sent to the client
+    StartGame {
+        client_id: String,
+        request_id: Option<String>,
+        hint_budget: Option<u32>,
+        done: oneshot::Sender<bool>,
+    },
+    /// Answers whether a reply was sent to the client
+    Play {
+        client_id: String,
+        request_id: Option<String>,
+        column: usize,
+        done: oneshot::Sender<bool>,
+    },
+    /// Answers whether a reply was sent to the client
+    Hint {
+        client_id: String,
+        request_id: Option<String>,
+        done: oneshot::Sender<bool>,
+    },
+    Snapshot {
+        done: oneshot::Sender<(Session, Option<GameState>)>,
+    },
+    /// Answers whether there was a game to end
+    EndGame {
+        done: oneshot::Sender<bool>,
+    },
+    /// Answers whether a reply was sent to the client
+    Rename {
+        client_id: String,
+        display_name: String,
+        request_id: Option<String>,
+        done: oneshot::Sender<bool>,
+    },
+    /// Answers `None` when the client is not a member, else whether no active client is left
+    Kick {
+        client_id: String,
+        done: oneshot::Sender<Option<bool>>,
+    },
+    /// Stops the task unless a client is still active, answering whether it stopped
+    Close {
+        done: oneshot::Sender<bool>,
+    },
+}
+
+/// A cheap reference to the task of a session
+///
+/// Every request resolves to `None` once the task has stopped
+#[derive(Clone)]
+pub struct SessionHandle {
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl SessionHandle {
+    pub async fn join(&self, client: Client, request_id: &Option<String>) -> Option<JoinOutcome> {
+        let request_id = request_id.clone();
+        self.request(|done| Command::Join {
+            client,
+            request_id,
+            done,
+        })
+        .await
+    }
+    pub async fn leave(&self, client_id: &str) -> Option<bool> {
+        let client_id = client_id.to_string();
+        self.request(|done| Command::Leave { client_id, done })
+            .await
+    }
+    pub fn connect(&self, client: Client) {
+        let _ = self.commands.send(Command::Connect { client });
+    }
+    pub async fn disconnect(&self, client_id: &str) -> Option<bool> {
+        let client_id = client_id.to_string();
+        self.request(|done| Command::Disconnect { client_id, done })
+            .await
+    }
+    pub async fn describe(&self, client_id: &str, request_id: &Option<String>) -> Option<()> {
+        let client_id = client_id.to_string();
+        let request_id = request_id.clone();
+        self.request(|done| Command::Describe {
+            client_id,
+            request_id,
+            done,
+        })
+        .await
+    }
+    pub async fn start_game(
+        &self,
+        client_id: &str,
+        request_id: &Option<String>,
+        hint_budget: Option<u32>,
+    ) -> Option<bool> {
+        let client_id = client_id.to_string();
+        let request_id = request_id.clone();
+        self.request(|done| Command::StartGame {
+            client_id,
+            request_id,
+            hint_budget,
+            done,
+        })
+        .await
+    }
+    pub async fn hint(&self, client_id: &str, request_id: &Option<String>) -> Option<bool> {
+        let client_id = client_id.to_string();
+        let request_id = request_id.clone();
+        self.request(|done| Command::Hint {
+            client_id,
+            request_id,
+            done,
+        })
+        .await
+    }
+    pub async fn play(
+        &self,
+        client_id: &str,
+        request_id: &Option<String>,
+        column: usize,
+    ) -> Option<bool> {
+        let client_id = client_id.to_string();
+        let request_id = request_id.clone();
+        self.request(|done| Command::Play {
+            client_id,
+            request_id,
+            column,
+            done,
+        })
+        .await
+    }
+    pub async fn snapshot(&self) -> Option<(Session, Option<GameState>)> {
+        self.request(|done| Command::Snapshot { done }).await
+    }
+    pub async fn end_game(&self) -> Option<bool> {
+        self.request(|done| Command::EndGame { done }).await
+    }
+    pub async fn rename(
+        &self,
+        client_id: &str,
+        display_name: &str,
+        request_id: &Option<String>,
+    ) -> Option<bool> {
+        let client_id = client_id.to_string();
+        let display_name = display_name.to_string();
+        let request_id = request_id.clone();
+        self.request(|done| Command::Rename {
+            client_id,
+            display_name,
+            request_id,
+            done,
+        })
+        .await
+    }
+    pub async fn kick(&self, client_id: &str) -> Option<Option<bool>> {
+        let client_id = client_id.to_string();
+        self.request(|done| Command::Kick { client_id, done }).await
+    }
+    pub async fn close(&self) -> Option<bool> {
+        self.request(|done| Command::Close { done }).await
+    }
+
+    /// Whether both handles reach the same task
+    pub fn is_same(&self, other: &SessionHandle) -> bool {
+        self.commands.same_channel(&other.commands)
+    }
+
+    async fn request<T>(&self, command: impl FnOnce(oneshot::Sender<T>) -> Command) -> Option<T> {
+        let (done, answer) = oneshot::channel();
+        self.commands.send(command(done)).ok()?;
+        answer.await.ok()
+    }
+}
+
+/// Start the task of a session, with the connections of its members who are active
+pub fn spawn(
+    session: Session,
+    game_state: Option<GameState>,
+    connections: Vec<Client>,
+    finished_games: FinishedGames,
+    config: data_types::SharedConfig,
+) -> SessionHandle {
+    let (commands, receiver) = mpsc::unbounded_channel();
+    // the task outlives the event which created it, so it starts a span of its own
+    let span = tracing::info_span!(parent: None, "session", session_id = %session.id);
+    if game_state.is_some() {
+        metrics::GAMES_IN_PROGRESS.inc();
+    }
+    let actor = SessionActor::new(session, game_state, connections, finished_games, config);
+    tokio::spawn(actor.run(receiver).instrument(span));
+    SessionHandle { commands }
+}
+
+struct SessionActor {
+    session: Session,
+    game_state: Option<GameState>,
+    /// Whether the game was won or drawn, leaving its final board on show until the next one starts
+    game_over: bool,
+    /// Seconds since the Unix epoch
+    game_started_at: u64,
+    /// How many hints each player may ask for in the game
+    hint_budget: u32,
+    /// How many hints each player asked for in the game
+    hints_used: HashMap<String, u32>,
+    /// The column suggested to the player whose turn it is, until they play or find it full
+    turn_hint: Option<usize>,
+    /// Whether the player whose turn it is was charged for a hint, which they are not charged for again
+    turn_charged: bool,
+    /// The columns each player found to be full by playing into them
+    full_columns: HashMap<String, Vec<usize>>,
+    /// The connections of the members who are active
+    connections: HashMap<String, Client>,
+    finished_games: FinishedGames,
+    config: data_types::SharedConfig,
+}
+
+impl SessionActor {
+    fn new(
+        session: Session,
+        game_state: Option<GameState>,
+        connections: Vec<Client>,
+        finished_games: FinishedGames,
+        config: data_types::SharedConfig,
+    ) -> Self {
+        let mut hints_used = HashMap::new();
+        if let Some(game_state) = &game_state {
+            for played in game_state.moves.iter().filter(|played| played.hinted) {
+                *hints_used
+                    .entry(game_state.player_turn_order[played.player_index].clone())
+                    .or_default() += 1;
+            }
+        }
+        SessionActor {
+            session,
+            // a restored game is only known to be over once someone plays into it
+            game_over: false,
+            game_state,
+            // the budget a restored game was started with was not kept
+            hint_budget: config.hints.default_budget,
+            hints_used,
+            turn_hint: None,
+            turn_charged: false,
+            full_columns: HashMap::new(),
+            // when a restored game started was not kept, so it is counted from the restart
+            game_started_at: unix_now(),
+            connections: connections
+                .into_iter()
+                .map(|client| (client.id.clone(), client))
+                .collect(),
+            finished_games,
+            config,
+        }
+    }
+
+    async fn run(mut self, mut commands: mpsc::UnboundedReceiver<Command>) {
+        while let Some(command) = commands.recv().await {
+            if !self.handle(command).await {
+                break;
+            }
+        }
+        if self.game_state.is_some() && !self.game_over {
+            metrics::GAMES_IN_PROGRESS.dec();
+        }
+        debug!("session stopped");
+    }
+
+    /// Run a single command, returning whether the session should keep running
+    async fn handle(&mut self, command: Command) -> bool {
+        match command {
+            Command::Join {
+                client,
+                request_id,
+                done,
+            } => {
+                let _ = done.send(self.join(client, &request_id));
+            }
+            Command::Leave { client_id, done } => {
+                self.leave(&client_id);
+                let _ = done.send(self.is_empty());
+            }
+            Command::Connect { client } => {
+                if self.session.contains_client(&client.id) {
+                    self.session.set_client_active_status(&client.id, true);
+                    self.connections.insert(client.id.clone(), client);
+                }
+            }
+            Command::Disconnect { client_id, done } => {
+                self.session.set_client_active_status(&client_id, false);
+                self.connections.remove(&client_id);
+                let _ = done.send(self.is_empty());
+            }
+            Command::Describe {
+                client_id,
+                request_id,
+                done,
+            } => {
+                self.describe(&client_id, &request_id);
+                let _ = done.send(());
+            }
+            Command::StartGame {
+                client_id,
+                request_id,
+                hint_budget,
+                done,
+            } => {
+                let _ = done.send(self.start_game(&client_id, &request_id, hint_budget));
+            }
+            Command::Hint {
+                client_id,
+                request_id,
+                done,
+            } => {
+                let _ = done.send(self.hint(&client_id, &request_id).await);
+            }
+            Command::Play {
+                client_id,
+                request_id,
+                column,
+                done,
+            } => {
+                let _ = done.send(self.play(&client_id, &request_id, column));
+            }
+            Command::Snapshot { done } => {
+                let _ = done.send((self.session.clone(), self.game_state.clone()));
+            }
+            Command::EndGame { done } => {
+                let _ = done.send(self.end_game());
+            }
+            Command::Rename {
+                client_id,
+                display_name,
+                request_id,
+                done,
+            } => {
+                let _ = done.send(self.rename(&client_id, &display_name, &request_id));
+            }
+            Command::Kick { client_id, done } => {
+                if self.session.contains_client(&client_id) {
+                    info!(client_id = %client_id, "client was kicked from session");
+                    let playing = !self.game_over
+                        && self.game_state.as_ref().is_some_and(|game_state| {
+                            game_state.get_player_index(&client_id).is_some()
+                        });
+                    self.leave(&client_id);
+                    // the turns of a kicked player would never be played, so their game ends
+                    if playing {
+                        self.end_game();
+                    }
+                    let _ = done.send(Some(self.is_empty()));
+                } else {
+                    let _ = done.send(None);
+                }
+            }
+            Command::Close { done } => {
+                let closing = self.is_empty();
+                let _ = done.send(closing);
+                return !closing;
+            }
+        }
+        true
+    }
+
+    /// Whether none of the members are active
+    fn is_empty(&self) -> bool {
+        self.session.get_clients_with_active_status(true).is_empty()
+    }
+
+    fn join(&mut self, client: Client, request_id: &Option<String>) -> JoinOutcome {
+        // do not allow sessions to grow past their limit
+        if self.session.client_statuses.len() >= self.config.limits.max_session_clients {
+            info!(client_id = %client.id, "client was not allowed into full session");
+            reply(
+                &client,
+                request_id,
+                game_engine::quick_server_error("session_full", "This session is full."),
+            );
+            return JoinOutcome::Refused;
+        }
+        // do not allow clients to join an active game
+        if self.game_state.is_some() {
+            info!(client_id = %client.id, "client was not allowed into in-progess session");
+            reply(
+                &client,
+                request_id,
+                EventBuilder::default()
+                    .event_code(ServerEventCode::CannotJoinInProgress)
+                    .data(
+                        ServerEventDataBuilder::default()
+                            .session_id(self.session.id.clone())
+                            .build()
+                            .unwrap(),
+                    )
+                    .build()
+                    .unwrap(),
+            );
+            return JoinOutcome::Refused;
+        }
+
+        info!(client_id = %client.id, "adding client into session");
+        let display_name = names::unique(
+            &client.display_name,
+            self.session.display_names.values().map(String::as_str),
+        );
+        self.session.insert_client(&client.id, true);
+        self.session.set_display_name(&client.id, &display_name);
+        // notify all clients in the session that the client has joined
+        let joined = EventBuilder::default()
+            .event_code(ServerEventCode::ClientJoined)
+            .data(
+                ServerEventDataBuilder::default()
+                    .session_id(self.session.id.clone())
+                    .client_id(client.id.clone())
+                    .session_client_ids(self.session.get_client_ids())
+                    .display_names(self.display_names())
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        self.connections.insert(client.id.clone(), client);
+        self.notify_session(&joined);
+        JoinOutcome::Joined
+    }
+
+    fn leave(&mut self, client_id: &str) {
+        // notify all clients in the sessions that the client will be leaving
+        self.notify_session(
+            &EventBuilder::default()
+                .event_code(ServerEventCode::ClientLeft)
+                .data(
+                    ServerEventDataBuilder::default()
+                        .client_id(client_id.to_string())
+                        .display_names(self.display_names())
+                        .build()
+                        .unwrap(),
+                )
+                .build()
+                .unwrap(),
+        );
+        self.session.remove_client(client_id);
+        self.connections.remove(client_id);
+        info!(client_id, "removed client from session");
+
+        // if the session is not empty, make someone else the owner
+        if !self.is_empty() {
+            self.session.owner = self.session.get_client_ids()[0].clone();
+        }
+    }
+
+    fn describe(&self, client_id: &str, request_id: &Option<String>) {
+        let mut server_data = ServerEventDataBuilder::default()
+            .session_id(self.session.id.clone())
+            .session_client_ids(self.session.get_client_ids())
+            .display_names(self.display_names())
+            .build()
+            .unwrap();
+        if let Some(game_state) = &self.game_state {
+            server_data.game_data = Some(game_state.as_shared_game_data(Some(client_id)));
+        }
+
+        self.reply_to(
+            client_id,
+            request_id,
+            EventBuilder::default()
+                .event_code(ServerEventCode::SessionResponse)
+                .data(server_data)
+                .build()
+                .unwrap(),
+        );
+    }
+
+    fn start_game(
+        &mut self,
+        client_id: &str,
+        request_id: &Option<String>,
+        hint_budget: Option<u32>,
+    ) -> bool {
+        let hints = &self.config.hints;
+        let hint_budget = match hint_budget {
+            None => hints.default_budget,
+            Some(_) if client_id != self.session.owner => {
+                self.reply_to(
+                    client_id,
+                    request_id,
+                    game_engine::quick_server_error(
+                        "not_session_owner",
+                        "Only the session owner can choose the hint budget.",
+                    ),
+                );
+                return true;
+            }
+            Some(budget) if budget > hints.max_budget => {
+                self.reply_to(
+                    client_id,
+                    request_id,
+                    game_engine::quick_server_error(
+                        "invalid_hint_budget",
+                        &format!("Players can be given at most {} hints.", hints.max_budget),
+                    ),
+                );
+                return true;
+            }
+            Some(budget) => budget,
+        };
+        let rules = &self.config.rules;
+        let player_turn_order = match game_engine::initialize_game_data(
+            &self.session.get_client_ids(),
+            rules.min_players,
+        ) {
+            Ok(player_turn_order) => player_turn_order,
+            Err(msg) => {
+                warn!(error = %msg, "could not start game");
+                self.reply_to(
+                    client_id,
+                    request_id,
+                    game_engine::quick_server_error("not_enough_players", &msg),
+                );
+                return true;
+            }
+        };
+        let game_state = GameState {
+            turn_index: 0,
+            player_turn_order,
+            board: create_game_board(rules.board_width, rules.board_height),
+            connect_length: rules.connect_length,
+            moves: Vec::new(),
+        };
+
+        // signal the game start
+        self.notify_session(
+            &EventBuilder::default()
+                .event_code(ServerEventCode::GameStarted)
+                .data(
+                    ServerEventDataBuilder::default()
+                        .game_data(game_state.as_shared_game_data(Some(client_id)))
+                        .display_names(self.display_names())
+                        .hints_left(hint_budget)
+                        .build()
+                        .unwrap(),
+                )
+                .build()
+                .unwrap(),
+        );
+        // signal the turn start
+        self.notify_session(
+            &EventBuilder::default()
+                .event_code(ServerEventCode::TurnStart)
+                .data(
+                    ServerEventDataBuilder::default()
+                        .client_id(game_state.get_turn_player())
+                        .game_data(game_state.as_shared_game_data(Some(client_id)))
+                        .display_names(self.display_names())
+                        .build()
+                        .unwrap(),
+                )
+                .build()
+                .unwrap(),
+        );
+
+        // a game which is over was already counted out when it ended
+        if self.game_state.replace(game_state).is_none() || self.game_over {
+            metrics::GAMES_IN_PROGRESS.inc();
+        }
+        self.game_over = false;
+        self.game_started_at = unix_now();
+        self.hint_budget = hint_budget;
+        self.hints_used.clear();
+        self.turn_hint = None;
+        self.turn_charged = false;
+        self.full_columns.clear();
+        false
+    }
+
+    fn play(&mut self, client_id: &str, request_id: &Option<String>, column: usize) -> bool {
+        let display_names = self.display_names();
+        let game_state = match self.game_state.as_mut() {
+            Some(game_state) => game_state,
+            None => return false,
+        };
+        if self.game_over {
+            self.reply_to(
+                client_id,
+                request_id,
+                game_engine::quick_server_error(
+                    "game_over",
+                    "The game is over, start a new one to keep playing.",
+                ),
+            );
+            return true;
+        }
+        if game_state.get_turn_player() != client_id {
+            self.reply_to(
+                client_id,
+                request_id,
+                game_engine::quick_server_error("not_your_turn", "It is not your turn to play."),
+            );
+            return true;
+        }
+        let player_index = match game_state.get_player_index(client_id) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let played = game_state.play(column, player_index);
+        if played.is_ok() {
+            self.turn_hint = None;
+            if std::mem::take(&mut self.turn_charged) {
+                if let Some(last) = game_state.moves.last_mut() {
+                    last.hinted = true;
+                }
+            }
+        }
+        match played {
+            // if the move was a winning move, then notify everyone that the game is over
+            Ok(true) => {
+                metrics::GAMES_FINISHED.with_label_values(&["win"]).inc();
+                metrics::GAMES_IN_PROGRESS.dec();
+                self.game_over = true;
+                let _ = self.finished_games.send(FinishedGame::new(
+                    &self.session.id,
+                    game_state,
+                    Some(client_id.to_string()),
+                    self.game_started_at,
+                ));
+                let ended = EventBuilder::default()
+                    .event_code(ServerEventCode::GameEnded)
+                    .data(
+                        ServerEventDataBuilder::default()
+                            .client_id(client_id)
+                            .game_data(game_state.as_shared_game_data(None))
+                            .display_names(display_names.clone())
+                            .hints_used(self.hints_used.clone())
+                            .build()
+                            .unwrap(),
+                    )
+                    .build()
+                    .unwrap();
+                self.notify_session(&ended);
+            }
+            // a full board without a winner is a draw
+            Ok(false) if game_state.is_full() => {
+                metrics::GAMES_FINISHED.with_label_values(&["draw"]).inc();
+                metrics::GAMES_IN_PROGRESS.dec();
+                self.game_over = true;
+                let _ = self.finished_games.send(FinishedGame::new(
+                    &self.session.id,
+                    game_state,
+                    None,
+                    self.game_started_at,
+                ));
+                let ended = EventBuilder::default()
+                    .event_code(ServerEventCode::GameEnded)
+                    .message("The game is a draw.")
+                    .data(
+                        ServerEventDataBuilder::default()
+                            .game_data(game_state.as_shared_game_data(None))
+                            .display_names(display_names.clone())
+                            .hints_used(self.hints_used.clone())
+                            .build()
+                            .unwrap(),
+                    )
+                    .build()
+                    .unwrap();
+                self.notify_session(&ended);
+            }
+            // else continue emitting the game format
+            Ok(false) => {
+                for (client_name, client) in &self.connections {
+                    game_engine::notify_client(
+                        &EventBuilder::default()
+                            .event_code(ServerEventCode::TurnStart)
+                            .data(
+                                ServerEventDataBuilder::default()
+                                    .client_id(game_state.get_turn_player())
+                                    .game_data(game_state.as_shared_game_data(Some(client_name)))
+                                    .display_names(display_names.clone())
+                                    .build()
+                                    .unwrap(),
+                            )
+                            .build()
+                            .unwrap(),
+                        client,
+                    );
+                }
+            }
+            Err(e) => {
+                warn!(client_id, column, error = %e, "player failed to play");
+                // the player now knows the column is full, which their hints may use
+                if column < game_state.board.len() {
+                    let known = self.full_columns.entry(client_id.to_string()).or_default();
+                    if !known.contains(&column) {
+                        known.push(column);
+                    }
+                }
+                // a hint into the column is worked out again from what the player now knows
+                if self.turn_hint == Some(column) {
+                    self.turn_hint = None;
+                }
+                self.reply_to(
+                    client_id,
+                    request_id,
+                    game_engine::quick_server_error(
+                        "column_full",
+                        "This column has reached its max.",
+                    ),
+                );
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Suggest a column to the player whose turn it is, from only what they can see
+    ///
+    /// The boards are searched off the async workers, while the session waits for the result
+    async fn hint(&mut self, client_id: &str, request_id: &Option<String>) -> bool {
+        let game_state = match &self.game_state {
+            Some(game_state) => game_state,
+            None => return false,
+        };
+        let refusal = if self.game_over {
+            Some((
+                "game_over",
+                "The game is over, start a new one to keep playing.",
+            ))
+        } else if game_state.get_turn_player() != client_id {
+            Some(("not_your_turn", "It is not your turn to play."))
+        } else if !self.turn_charged
+            && self.hints_used.get(client_id).copied().unwrap_or(0) >= self.hint_budget
+        {
+            Some(("no_hints_left", "You have no hints left in this game."))
+        } else {
+            None
+        };
+        if let Some((error_type, msg)) = refusal {
+            self.reply_to(
+                client_id,
+                request_id,
+                game_engine::quick_server_error(error_type, msg),
+            );
+            return true;
+        }
+
+        let column = match self.turn_hint {
+            Some(column) => Some(column),
+            None => {
+                let hints = &self.config.hints;
+                let view = PlayerView {
+                    board: game_state.as_shared_game_data(Some(client_id)).play_indexes,
+                    player_index: game_state.turn_index,
+                    players: game_state.player_turn_order.len(),
+                    move_count: game_state
+                        .board
+                        .iter()
+                        .flatten()
+                        .filter(|cell| **cell != usize::MAX)
+                        .count(),
+                    connect_length: game_state.connect_length,
+                    full_columns: self
+                        .full_columns
+                        .get(client_id)
+                        .cloned()
+                        .unwrap_or_default(),
+                };
+                let (samples, depth) = (hints.samples, hints.depth);
+                let seed = WyRand::new().generate::<u64>();
+                let column = match tokio::task::spawn_blocking(move || {
+                    belief::evaluate(&view, samples, depth, seed).best_column
+                })
+                .await
+                {
+                    Ok(column) => column,
+                    Err(e) => {
+                        error!(client_id, error = %e, "could not work out a hint");
+                        None
+                    }
+                };
+                if let Some(column) = column {
+                    info!(client_id, column, "player was given a hint");
+                    self.turn_hint = Some(column);
+                    if !std::mem::replace(&mut self.turn_charged, true) {
+                        *self.hints_used.entry(client_id.to_string()).or_default() += 1;
+                    }
+                }
+                column
+            }
+        };
+        let column = match column {
+            Some(column) => column,
+            None => {
+                self.reply_to(
+                    client_id,
+                    request_id,
+                    game_engine::quick_server_error(
+                        "no_hint",
+                        "No hint could be found for this board.",
+                    ),
+                );
+                return true;
+            }
+        };
+
+        let used = self.hints_used.get(client_id).copied().unwrap_or(0);
+        self.reply_to(
+            client_id,
+            request_id,
+            EventBuilder::default()
+                .event_code(ServerEventCode::Hint)
+                .data(
+                    ServerEventDataBuilder::default()
+                        .column(column)
+                        .hints_left(self.hint_budget.saturating_sub(used))
+                        .build()
+                        .unwrap(),
+                )
+                .build()
+                .unwrap(),
+        );
+        true
+    }
+
+    /// Stop the game in progress without a winner, so that a new one can be started
+    fn end_game(&mut self) -> bool {
+        let game_state = match self.game_state.take() {
+            Some(game_state) => game_state,
+            None => return false,
+        };
+        // a game which is over was already counted when it ended
+        if !self.game_over {
+            metrics::GAMES_IN_PROGRESS.dec();
+            metrics::GAMES_FINISHED
+                .with_label_values(&["ended_by_admin"])
+                .inc();
+        }
+        self.game_over = false;
+        info!("game was ended by an admin");
+        self.notify_session(
+            &EventBuilder::default()
+                .event_code(ServerEventCode::GameEnded)
+                .data(
+                    ServerEventDataBuilder::default()
+                        .game_data(game_state.as_shared_game_data(None))
+                        .display_names(self.display_names())
+                        .hints_used(self.hints_used.clone())
+                        .build()
+                        .unwrap(),
+                )
+                .build()
+                .unwrap(),
+        );
+        true
+    }
+
+    /// Change the name of a member, unless another member is already shown with a lookalike
+    fn rename(&mut self, client_id: &str, display_name: &str, request_id: &Option<String>) -> bool {
+        let taken = self
+            .session
+            .display_names
+            .iter()
+            .any(|(id, other)| id != client_id && names::is_confusable(display_name, other));
+        if taken {
+            self.reply_to(
+                client_id,
+                request_id,
+                game_engine::quick_server_error(
+                    "display_name_taken",
+                    "Someone in this session already has this name.",
+                ),
+            );
+            return true;
+        }
+
+        info!(client_id, display_name, "client changed their display name");
+        self.session.set_display_name(client_id, display_name);
+        self.notify_session(
+            &EventBuilder::default()
+                .event_code(ServerEventCode::DisplayNameChanged)
+                .data(
+                    ServerEventDataBuilder::default()
+                        .session_id(self.session.id.clone())
+                        .client_id(client_id.to_string())
+                        .display_names(self.display_names())
+                        .build()
+                        .unwrap(),
+                )
+                .build()
+                .unwrap(),
+        );
+        false
+    }
+
+    /// The name each member of the session is shown with
+    fn display_names(&self) -> HashMap<String, String> {
+        self.session
+            .client_statuses
+            .keys()
+            .map(|id| (id.clone(), self.session.get_display_name(id).to_string()))
+            .collect()
+    }
+
+    /// Send an update to every active client in the session
+    fn notify_session(&self, update: &ServerEvent) {
+        for client in self.connections.values() {
+            game_engine::notify_client(update, client);
+        }
+    }
+
+    /// Send a direct reply to a member which made a request
+    fn reply_to(&self, client_id: &str, request_id: &Option<String>, reply_event: ServerEvent) {
+        match self.connections.get(client_id) {
+            Some(client) => reply(client, request_id, reply_event),
+            None => warn!(client_id, "could not find connection of client"),
+        }
+    }
+}
+
+/// Send a direct reply to the client which made a request, echoing the id of the request
+fn reply(client: &Client, request_id: &Option<String>, mut reply_event: ServerEvent) {
+    reply_event.request_id = request_id.clone();
+    game_engine::notify_client(&reply_event, client);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use sessions::session_types::Encoding;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn sessions_only_close_once_no_one_is_active() {
+        let mut session = Session {
+            id: String::from("ABCDE"),
+            owner: String::from("alice"),
+            client_statuses: HashMap::new(),
+            display_names: HashMap::new(),
+        };
+        session.insert_client("alice", true);
+        let alice = Client {
+            id: String::from("alice"),
+            display_name: String::from("Alice"),
+            sender: None,
+            encoding: Encoding::Json,
+        };
+        let handle = spawn(
+            session,
+            None,
+            vec![alice],
+            Registry::default().finished_games(),
+            Arc::new(Config::default()),
+        );
+
+        assert_eq!(handle.close().await, Some(false));
+        assert_eq!(handle.disconnect("alice").await, Some(true));
+        assert_eq!(handle.close().await, Some(true));
+        assert!(handle.snapshot().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn members_are_never_shown_with_lookalike_names() {
+        let mut session = Session {
+            id: String::from("ABCDE"),
+            owner: String::from("alice"),
+            client_statuses: HashMap::new(),
+            display_names: HashMap::new(),
+        };
+        session.insert_client("alice", true);
+        session.set_display_name("alice", "Alice");
+        let handle = spawn(
+            session,
+            None,
+            Vec::new(),
+            Registry::default().finished_games(),
+            Arc::new(Config::default()),
+        );
+        let bob = Client {
+            id: String::from("bob"),
+            display_name: String::from("AIice"),
+            sender: None,
+            encoding: Encoding::Json,
+        };
+
+        assert_eq!(handle.join(bob, &None).await, Some(JoinOutcome::Joined));
+        let (session, _) = handle.snapshot().await.unwrap();
+        assert_eq!(session.get_display_name("bob"), "AIice 2");
+
+        assert_eq!(handle.rename("bob", "alice", &None).await, Some(true));
+        assert_eq!(handle.rename("bob", "Bob", &None).await, Some(false));
+        assert_eq!(handle.rename("alice", "Alice", &None).await, Some(false));
+        let (session, _) = handle.snapshot().await.unwrap();
+        assert_eq!(session.get_display_name("bob"), "Bob");
+    }
+
+    #[tokio::test]
+    async fn hints_are_limited_to_the_budget_of_the_owner() {
+        let _games = metrics::GAMES_IN_PROGRESS_TESTS.lock().await;
+        let mut session = Session {
+            id: String::from("ABCDE"),
+            owner: String::from("alice"),
+            client_statuses: HashMap::new(),
+            display_names: HashMap::new(),
+        };
+        session.insert_client("alice", true);
+        session.insert_client("bob", true);
+        let handle = spawn(
+            session,
+            None,
+            Vec::new(),
+            Registry::default().finished_games(),
+            Arc::new(Config::default()),
+        );
+
+        assert_eq!(handle.start_game("bob", &None, Some(1)).await, Some(true));
+        assert_eq!(
+            handle.start_game("alice", &None, Some(99)).await,
+            Some(true)
+        );
+        assert!(handle.snapshot().await.unwrap().1.is_none());
+        assert_eq!(
+            handle.start_game("alice", &None, Some(1)).await,
+            Some(false)
+        );
+
+        let (_, game_state) = handle.snapshot().await.unwrap();
+        let order = game_state.unwrap().player_turn_order;
+        assert_eq!(handle.hint(&order[1], &None).await, Some(true));
+        assert_eq!(handle.hint(&order[0], &None).await, Some(true));
+        assert_eq!(handle.play(&order[0], &None, 3).await, Some(false));
+        assert_eq!(handle.play(&order[1], &None, 3).await, Some(false));
+        // the only hint was used on the first move
+        assert_eq!(handle.hint(&order[0], &None).await, Some(true));
+        assert_eq!(handle.play(&order[0], &None, 2).await, Some(false));
+
+        let (_, game_state) = handle.snapshot().await.unwrap();
+        let hinted: Vec<bool> = game_state
+            .unwrap()
+            .moves
+            .iter()
+            .map(|played| played.hinted)
+            .collect();
+        assert_eq!(hinted, [true, false, false]);
+    }
+
+    #[tokio::test]
+    async fn hints_into_a_full_column_are_worked_out_again() {
+        let mut session = Session {
+            id: String::from("ABCDE"),
+            owner: String::from("alice"),
+            client_statuses: HashMap::new(),
+            display_names: HashMap::new(),
+        };
+        session.insert_client("alice", true);
+        session.insert_client("bob", true);
+        // alice cannot see that bob's pieces fill the first column up to the top
+        let mut board = create_game_board(7, 6);
+        for (row, cell) in board[0].iter_mut().enumerate() {
+            *cell = row % 2;
+        }
+        let game_state = GameState {
+            board,
+            turn_index: 0,
+            player_turn_order: vec![String::from("alice"), String::from("bob")],
+            connect_length: 4,
+            moves: Vec::new(),
+        };
+        let mut actor = SessionActor::new(
+            session,
+            Some(game_state),
+            Vec::new(),
+            Registry::default().finished_games(),
+            Arc::new(Config::default()),
+        );
+        // as though a hint had suggested the first column
+        actor.turn_hint = Some(0);
+        actor.turn_charged = true;
+        actor.hints_used.insert(String::from("alice"), 1);
+
+        assert!(actor.play("alice", &None, 0));
+        assert_eq!(actor.turn_hint, None);
+        assert!(actor.hint("alice", &None).await);
+        let column = actor.turn_hint.unwrap();
+        assert_ne!(column, 0);
+        // the hint for the turn was already paid for
+        assert_eq!(actor.hints_used["alice"], 1);
+
+        assert!(!actor.play("alice", &None, column));
+        let game_state = actor.game_state.as_ref().unwrap();
+        assert!(game_state.moves.last().unwrap().hinted);
+    }
+
+    #[tokio::test]
+    async fn kicking_a_player_ends_their_game() {
+        let _games = metrics::GAMES_IN_PROGRESS_TESTS.lock().await;
+        let mut session = Session {
+            id: String::from("ABCDE"),
+            owner: String::from("alice"),
+            client_statuses: HashMap::new(),
+            display_names: HashMap::new(),
+        };
+        for id in ["alice", "bob", "carol"] {
+            session.insert_client(id, true);
+        }
+        let handle = spawn(
+            session,
+            None,
+            Vec::new(),
+            Registry::default().finished_games(),
+            Arc::new(Config::default()),
+        );
+        assert_eq!(handle.start_game("alice", &None, None).await, Some(false));
+        let (_, game_state) = handle.snapshot().await.unwrap();
+        let order = game_state.unwrap().player_turn_order;
+
+        // the player to move is kicked
+        assert_eq!(handle.kick(&order[0]).await, Some(Some(false)));
+        let (session, game_state) = handle.snapshot().await.unwrap();
+        assert!(!session.contains_client(&order[0]));
+        assert!(game_state.is_none());
+
+        // the others may play a new game without them
+        assert_eq!(handle.start_game(&order[1], &None, None).await, Some(false));
+        let (_, game_state) = handle.snapshot().await.unwrap();
+        let order = game_state.unwrap().player_turn_order;
+        assert_eq!(order.len(), 2);
+
+        // a player who is not to move is kicked
+        assert_eq!(handle.kick(&order[1]).await, Some(Some(false)));
+        assert!(handle.snapshot().await.unwrap().1.is_none());
+    }
+
+    #[tokio::test]
+    async fn games_stop_counting_as_in_progress_once_won() {
+        let _games = metrics::GAMES_IN_PROGRESS_TESTS.lock().await;
+        let mut session = Session {
+            id: String::from("ABCDE"),
+            owner: String::from("alice"),
+            client_statuses: HashMap::new(),
+            display_names: HashMap::new(),
+        };
+        session.insert_client("alice", true);
+        session.insert_client("bob", true);
+        let mut actor = SessionActor::new(
+            session,
+            None,
+            Vec::new(),
+            Registry::default().finished_games(),
+            Arc::new(Config::default()),
+        );
+        let before = metrics::GAMES_IN_PROGRESS.get();
+
+        assert!(!actor.start_game("alice", &None, None));
+        assert_eq!(metrics::GAMES_IN_PROGRESS.get(), before + 1);
+        let order = actor.game_state.as_ref().unwrap().player_turn_order.clone();
+        for _ in 0..3 {
+            assert!(!actor.play(&order[0], &None, 0));
+            assert!(!actor.play(&order[1], &None, 1));
+        }
+        assert!(!actor.play(&order[0], &None, 0));
+        assert!(actor.game_over);
+        assert_eq!(metrics::GAMES_IN_PROGRESS.get(), before);
+
+        // neither ending the finished game nor starting the next counts it out twice
+        assert!(actor.end_game());
+        assert_eq!(metrics::GAMES_IN_PROGRESS.get(), before);
+        assert!(!actor.start_game("alice", &None, None));
+        assert_eq!(metrics::GAMES_IN_PROGRESS.get(), before + 1);
+        assert!(actor.end_game());
+        assert_eq!(metrics::GAMES_IN_PROGRESS.get(), before);
+    }
+}
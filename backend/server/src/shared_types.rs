@@ -2,19 +2,26 @@
  * This file contains type defintions which are shared between the front and back end applications
  */
 use derive_builder::Builder;
+use schemars::{JsonSchema, JsonSchema_repr};
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
+use std::collections::HashMap;
+use ts_rs::TS;
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, TS, JsonSchema)]
 pub struct GameData {
     pub turn_index: usize,
     pub player_order: Vec<String>,
     pub play_indexes: Vec<Vec<usize>>,
 }
 
-#[derive(Deserialize, Serialize, Builder)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Builder, TS, JsonSchema)]
+#[ts(optional_fields = nullable)]
 pub struct Event<Code, PayloadType> {
     pub event_code: Code,
+    /// Client supplied identifier which the server echoes on direct replies
+    #[builder(setter(into, strip_option), default)]
+    pub request_id: Option<String>,
     #[builder(setter(into, strip_option), default)]
     pub message: Option<String>,
     #[builder(setter(into, strip_option), default)]
@@ -24,7 +31,8 @@ pub struct Event<Code, PayloadType> {
 pub type ServerEvent = Event<ServerEventCode, ServerEventData>;
 pub type ClientEvent = Event<ClientEventCode, ClientEventData>;
 
-#[derive(Serialize, Clone, Builder)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Builder, TS, JsonSchema)]
+#[ts(optional_fields = nullable)]
 pub struct ServerEventData {
     #[builder(setter(into, strip_option), default)]
     pub session_id: Option<String>,
@@ -34,9 +42,40 @@ pub struct ServerEventData {
     pub session_client_ids: Option<Vec<String>>,
     #[builder(setter(into, strip_option), default)]
     pub game_data: Option<GameData>,
+    #[builder(setter(into, strip_option), default)]
+    pub protocol_version: Option<u32>,
+    #[builder(setter(into, strip_option), default)]
+    pub rule_variants: Option<Vec<String>>,
+    #[builder(setter(into, strip_option), default)]
+    pub features: Option<Vec<String>>,
+    /// Wire format the server will send events in, either `json` or `msgpack`
+    #[builder(setter(into, strip_option), default)]
+    pub encoding: Option<String>,
+    /// Secret an event stream client must send with every POSTed event
+    #[builder(setter(into, strip_option), default)]
+    pub connection_token: Option<String>,
+    /// Seconds until the server closes every connection
+    #[builder(setter(into, strip_option), default)]
+    pub shutdown_seconds: Option<u32>,
+    /// The name to show for each client id in the event
+    #[builder(setter(into, strip_option), default)]
+    pub display_names: Option<HashMap<String, String>>,
+    /// The tournament an event is about
+    #[builder(setter(into, strip_option), default)]
+    pub tournament_id: Option<String>,
+    /// The column a hint suggests playing
+    #[builder(setter(into, strip_option), default)]
+    pub column: Option<usize>,
+    /// How many more hints the client can ask for in the game
+    #[builder(setter(into, strip_option), default)]
+    pub hints_left: Option<u32>,
+    /// How many hints each player asked for in a finished game
+    #[builder(setter(into, strip_option), default)]
+    pub hints_used: Option<HashMap<String, u32>>,
 }
 
-#[derive(Deserialize, Builder)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Builder, TS, JsonSchema)]
+#[ts(optional_fields = nullable)]
 pub struct ClientEventData {
     #[builder(setter(into, strip_option), default)]
     pub target_ids: Option<Vec<String>>,
@@ -44,9 +83,27 @@ pub struct ClientEventData {
     pub session_id: Option<String>,
     #[builder(setter(into, strip_option), default)]
     pub column: Option<usize>,
+    #[builder(setter(into, strip_option), default)]
+    pub protocol_version: Option<u32>,
+    /// Wire format the client wants to receive events in, either `json` or `msgpack`
+    #[builder(setter(into, strip_option), default)]
+    pub encoding: Option<String>,
+    /// The name the client wants to be shown with
+    #[builder(setter(into, strip_option), default)]
+    pub display_name: Option<String>,
+    /// How many players the client wants to be matched into a game of
+    #[builder(setter(into, strip_option), default)]
+    pub player_count: Option<u32>,
+    /// The rule set the client wants to be matched into a game of
+    #[builder(setter(into, strip_option), default)]
+    pub rule_variant: Option<String>,
+    /// How many hints each player may ask for in the game being started, which only the session owner can choose
+    #[builder(setter(into, strip_option), default)]
+    pub hint_budget: Option<u32>,
 }
 
-#[derive(Serialize_repr, Clone)]
+#[derive(Deserialize_repr, Serialize_repr, Debug, Clone, PartialEq, TS, JsonSchema_repr)]
+#[ts(repr(enum))]
 #[repr(u8)]
 pub enum ServerEventCode {
     /**
@@ -63,9 +120,32 @@ pub enum ServerEventCode {
      */
     TurnStart,
     LogicError,
+    /**
+     * Protocol Related
+     */
+    Acknowledged,
+    Welcome,
+    /**
+     * Server Related
+     */
+    ServerShuttingDown,
+    Announcement,
+    /**
+     * Session Related
+     */
+    DisplayNameChanged,
+    /**
+     * Tournament Related
+     */
+    TournamentUpdated,
+    /**
+     * Game Related
+     */
+    Hint,
 }
 
-#[derive(Deserialize_repr)]
+#[derive(Deserialize_repr, Serialize_repr, Debug, Clone, PartialEq, TS, JsonSchema_repr)]
+#[ts(repr(enum))]
 #[repr(u8)]
 pub enum ClientEventCode {
     /**
@@ -80,4 +160,21 @@ pub enum ClientEventCode {
      */
     StartGame,
     Play,
+    /**
+     * Protocol Related Events
+     */
+    Hello,
+    /**
+     * Session Related Events
+     */
+    SetDisplayName,
+    /**
+     * Matchmaking Related Events
+     */
+    JoinQueue,
+    LeaveQueue,
+    /**
+     * Game Related Events
+     */
+    RequestHint,
 }
@@ -0,0 +1,84 @@
+use crate::shared_types::{EventBuilder, ServerEventCode, ServerEventDataBuilder};
+use crate::{data_types, game_engine, metrics, persistence};
+use sessions::sink::Outbound;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{error, info};
+
+/// Close code sent to every client when the server goes away
+const CLOSE_GOING_AWAY: u16 = 1001;
+
+/// How long closed connections get to finish before the process exits anyway
+pub const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Resolves once the process is asked to stop with SIGINT or SIGTERM
+#[cfg(unix)]
+pub async fn signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("could not listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("received SIGINT"),
+        _ = terminate.recv() => info!("received SIGTERM"),
+    }
+}
+
+/// Resolves once the process is asked to stop with Ctrl-C
+#[cfg(not(unix))]
+pub async fn signal() {
+    let _ = tokio::signal::ctrl_c().await;
+    info!("received Ctrl-C");
+}
+
+/// Warn every client that the server is going away, save the live sessions, then close every connection
+pub async fn drain(
+    clients: &data_types::SafeClients,
+    sessions: &data_types::SafeSessions,
+    config: &data_types::SharedConfig,
+) {
+    let countdown = config.grace.shutdown_seconds;
+    info!(countdown, "warning clients of shutdown");
+    game_engine::notify_all(
+        &EventBuilder::default()
+            .event_code(ServerEventCode::ServerShuttingDown)
+            .data(
+                ServerEventDataBuilder::default()
+                    .shutdown_seconds(countdown)
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap(),
+        clients,
+    )
+    .await;
+    // nobody needs warning time when nobody is connected
+    if !metrics::read(clients, "clients").await.is_empty() {
+        sleep(config.grace.shutdown()).await;
+    }
+
+    // sessions are saved before the connections close, since closing them empties the sessions
+    if let Some(path) = &config.persistence.snapshot_path {
+        match persistence::save(path, sessions).await {
+            Ok(saved) => info!(saved, path = %path.display(), "saved sessions"),
+            Err(e) => error!(error = %e, "could not save sessions"),
+        }
+    }
+
+    for client in metrics::read(clients, "clients").await.values() {
+        if let Some(sender) = &client.sender {
+            let _ = sender.send(Outbound::close(
+                CLOSE_GOING_AWAY,
+                "the server is shutting down",
+            ));
+        }
+    }
+
+    // give every transport the chance to flush its close and unregister the client
+    let mut waited = Duration::from_secs(0);
+    let step = Duration::from_millis(100);
+    while waited < CLOSE_TIMEOUT && !metrics::read(clients, "clients").await.is_empty() {
+        sleep(step).await;
+        waited += step;
+    }
+}
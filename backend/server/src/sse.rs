@@ -0,0 +1,177 @@
+use crate::rate_limit::{ClientLimiter, Verdict};
+use crate::shared_types::ClientEvent;
+use crate::{codec, data_types, game_engine, metrics, protocol, transport, ws};
+use futures::{future, Stream, StreamExt};
+use nanoid::nanoid;
+use sessions::session_types::Encoding;
+use sessions::sink::Outbound;
+use std::convert::Infallible;
+use tracing::{info, instrument, Instrument};
+use warp::hyper::StatusCode;
+use warp::sse::Event;
+
+/// A client connected over Server-Sent Events
+pub struct SseConnection {
+    /// Secret the client must send with every POSTed event
+    token: String,
+    limiter: ClientLimiter,
+}
+
+/// The Initial Setup for a Server-Sent Events Connection
+///
+/// Events reach the client through the same `Client.sender` as a websocket would use,
+/// but are drained into an event stream instead of a socket.
+/// The stream opens with a `Welcome` carrying the token the client must send with every POST
+#[instrument(name = "client", skip_all, fields(client_id = %id))]
+pub async fn client_connection(
+    id: String,
+    display_name: String,
+    clients: data_types::SafeClients,
+    sessions: data_types::SafeSessions,
+    sse_connections: data_types::SafeSseConnections,
+    config: data_types::SharedConfig,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    let (client_sender, client_rcv) = transport::channel();
+
+    let token = nanoid!();
+    metrics::write(&sse_connections, "sse_connections")
+        .await
+        .insert(
+            id.clone(),
+            SseConnection {
+                token: token.clone(),
+                limiter: ClientLimiter::new(&config.limits),
+            },
+        );
+
+    // event streams are text only, so the client always receives JSON
+    let mut welcome = protocol::welcome_event(Encoding::Json, &id, &display_name);
+    if let Some(data) = welcome.data.as_mut() {
+        data.connection_token = Some(token.clone());
+    }
+    let _ = client_sender.send(codec::encode(&welcome, Encoding::Json));
+
+    ws::register_client(
+        &id,
+        display_name,
+        client_sender,
+        Encoding::Json,
+        &clients,
+        &sessions,
+    )
+    .await;
+
+    // dropped along with the stream once the client goes away
+    let disconnect = Disconnect {
+        id,
+        token,
+        clients,
+        sessions,
+        sse_connections,
+        config,
+    };
+
+    client_rcv
+        // the server closing the connection ends the stream
+        .take_while(|message| future::ready(!matches!(message, Outbound::Close { .. })))
+        .filter_map(|message| {
+            future::ready(match message {
+                Outbound::Text(text) => Some(text),
+                _ => None,
+            })
+        })
+        .map(move |text| {
+            let _ = &disconnect;
+            Ok(Event::default().data(text))
+        })
+}
+
+/// Handle a Client event that was POSTed alongside an open event stream
+#[instrument(name = "client", skip_all, fields(client_id = %id))]
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_client_event(
+    id: &str,
+    token: &str,
+    client_event: ClientEvent,
+    clients: &data_types::SafeClients,
+    sessions: &data_types::SafeSessions,
+    sse_connections: &data_types::SafeSseConnections,
+    config: &data_types::SharedConfig,
+) -> StatusCode {
+    let verdict = {
+        let mut connections = metrics::write(sse_connections, "sse_connections").await;
+        let connection = match connections.get_mut(id) {
+            Some(connection) if connection.token == token => connection,
+            Some(_) => return StatusCode::FORBIDDEN,
+            None => return StatusCode::NOT_FOUND,
+        };
+        ws::admit_event(
+            id,
+            &client_event,
+            &mut connection.limiter,
+            clients,
+            sessions,
+        )
+        .await
+    };
+
+    match verdict {
+        Verdict::Allow => {
+            game_engine::handle_event(id, client_event, clients, sessions, config).await;
+            StatusCode::ACCEPTED
+        }
+        Verdict::Reject(_) => StatusCode::TOO_MANY_REQUESTS,
+        Verdict::Disconnect => {
+            info!("disconnecting abusive client");
+            // closing the sender ends the event stream, which runs the normal disconnect path
+            if let Some(sender) = metrics::read(clients, "clients")
+                .await
+                .get(id)
+                .and_then(|client| client.sender.as_ref())
+            {
+                let _ = sender.send(Outbound::close(
+                    protocol::CLOSE_RATE_LIMITED,
+                    "rate limit exceeded",
+                ));
+            }
+            StatusCode::TOO_MANY_REQUESTS
+        }
+    }
+}
+
+/// Runs the normal disconnect path when an event stream is dropped
+struct Disconnect {
+    id: String,
+    token: String,
+    clients: data_types::SafeClients,
+    sessions: data_types::SafeSessions,
+    sse_connections: data_types::SafeSseConnections,
+    config: data_types::SharedConfig,
+}
+
+impl Drop for Disconnect {
+    fn drop(&mut self) {
+        let id = self.id.clone();
+        let token = self.token.clone();
+        let clients = self.clients.clone();
+        let sessions = self.sessions.clone();
+        let sse_connections = self.sse_connections.clone();
+        let config = self.config.clone();
+
+        let span = tracing::info_span!("client", client_id = %id);
+
+        tokio::spawn(
+            async move {
+                {
+                    let mut tokens = metrics::write(&sse_connections, "sse_connections").await;
+                    if tokens.get(&id).map(|connection| &connection.token) != Some(&token) {
+                        return;
+                    }
+                    tokens.remove(&id);
+                }
+                ws::unregister_client(&id, &clients, &sessions, &config).await;
+            }
+            .instrument(span),
+        );
+    }
+}
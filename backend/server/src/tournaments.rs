@@ -0,0 +1,1037 @@
+//! Tournaments between registered players, played as single elimination, Swiss or round robin
+//!
+//! Operators create and start tournaments, while players register themselves with their login.
+//! Every game of a round gets a session of its own, and the round moves on once each of its games finished.
+//! Tournaments are forgotten when the server stops
+use crate::session_actor::FinishedGame;
+use crate::shared_types::{EventBuilder, ServerEventCode, ServerEventDataBuilder};
+use crate::{data_types, game_engine, handler, metrics};
+use nanoid::nanoid;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{info, warn};
+use warp::hyper::StatusCode;
+use warp::{Rejection, Reply};
+
+type Result<T> = std::result::Result<T, Rejection>;
+
+/// The most players a single tournament takes
+const MAX_PARTICIPANTS: usize = 128;
+
+/// The longest name of a tournament, in characters
+const MAX_NAME_LENGTH: usize = 50;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    /// Losers are out, and the last one standing wins
+    SingleElimination,
+    /// Players meet others with the same score, for a set number of rounds
+    Swiss,
+    /// Every player meets every other one once
+    RoundRobin,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Registering,
+    Running,
+    Finished,
+}
+
+/// A game between two players, or a bye for a single one
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Pairing {
+    players: Vec<String>,
+    /// The session the game is played in once it was seated
+    session_id: Option<String>,
+    finished: bool,
+    /// `None` for a draw, or when no one showed up
+    winner: Option<String>,
+    /// Whether the game was decided by someone not being connected when it was due to start
+    forfeit: bool,
+    /// Whether a session is being started for the game, so that it is only seated once
+    #[serde(skip)]
+    seating: bool,
+}
+
+impl Pairing {
+    fn game(players: Vec<String>) -> Self {
+        Pairing {
+            players,
+            session_id: None,
+            finished: false,
+            winner: None,
+            forfeit: false,
+            seating: false,
+        }
+    }
+
+    fn bye(player: String) -> Self {
+        Pairing {
+            winner: Some(player.clone()),
+            finished: true,
+            ..Pairing::game(vec![player])
+        }
+    }
+
+    fn is_bye(&self) -> bool {
+        self.players.len() == 1
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Round {
+    number: usize,
+    pairings: Vec<Pairing>,
+}
+
+/// A row of the standings of a tournament
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Standing {
+    rank: usize,
+    id: String,
+    seed: usize,
+    points: f64,
+    wins: u32,
+    losses: u32,
+    draws: u32,
+    byes: u32,
+    /// Only ever set in single elimination
+    eliminated: bool,
+    /// The last round the player took part in
+    #[serde(skip)]
+    reached: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Tournament {
+    id: String,
+    name: String,
+    format: Format,
+    status: Status,
+    /// In the order they registered, then by seed once the tournament started
+    participants: Vec<String>,
+    /// How many rounds will be played, known once the tournament started
+    total_rounds: Option<usize>,
+    /// The number of Swiss rounds asked for when the tournament was created
+    #[serde(skip)]
+    swiss_rounds: Option<usize>,
+    rounds: Vec<Round>,
+    champion: Option<String>,
+}
+
+impl Tournament {
+    pub fn new(name: String, format: Format, swiss_rounds: Option<usize>) -> Self {
+        Tournament {
+            id: nanoid!(10),
+            name,
+            format,
+            status: Status::Registering,
+            participants: Vec::new(),
+            total_rounds: None,
+            swiss_rounds,
+            rounds: Vec::new(),
+            champion: None,
+        }
+    }
+
+    /// Add a player before the tournament starts
+    pub fn register(&mut self, id: &str) -> std::result::Result<(), &'static str> {
+        if self.status != Status::Registering {
+            return Err("the tournament has already started");
+        }
+        if self
+            .participants
+            .iter()
+            .any(|participant| participant == id)
+        {
+            return Err("already registered");
+        }
+        if self.participants.len() >= MAX_PARTICIPANTS {
+            return Err("the tournament is full");
+        }
+        self.participants.push(id.to_string());
+        Ok(())
+    }
+
+    /// Seed the participants by their rating, best first, and pair the first round
+    pub fn start(&mut self, rating: impl Fn(&str) -> f64) -> std::result::Result<(), &'static str> {
+        if self.status != Status::Registering {
+            return Err("the tournament has already started");
+        }
+        if self.participants.len() < 2 {
+            return Err("at least 2 players must register first");
+        }
+        self.participants
+            .sort_by(|a, b| rating(b).total_cmp(&rating(a)));
+
+        let players = self.participants.len();
+        self.total_rounds = Some(match self.format {
+            Format::SingleElimination => players.next_power_of_two().trailing_zeros() as usize,
+            Format::RoundRobin => players + players % 2 - 1,
+            Format::Swiss => self
+                .swiss_rounds
+                .unwrap_or_else(|| players.next_power_of_two().trailing_zeros() as usize)
+                .clamp(1, players - 1),
+        });
+        self.status = Status::Running;
+        self.begin_round();
+        Ok(())
+    }
+
+    fn current_round(&self) -> Option<&Round> {
+        self.rounds
+            .last()
+            .filter(|_| self.status == Status::Running)
+    }
+
+    fn current_round_mut(&mut self) -> Option<&mut Round> {
+        let running = self.status == Status::Running;
+        self.rounds.last_mut().filter(|_| running)
+    }
+
+    /// The games of the current round which still need a session, by their round and index in it
+    ///
+    /// The games are held until `seated` or `forfeit` is called for them, so that no one else seats them
+    fn games_to_seat(&mut self) -> Vec<(usize, usize, Vec<String>)> {
+        let number = self.rounds.len();
+        self.current_round_mut()
+            .map(|round| {
+                round
+                    .pairings
+                    .iter_mut()
+                    .enumerate()
+                    .filter(|(_, pairing)| {
+                        !pairing.finished && pairing.session_id.is_none() && !pairing.seating
+                    })
+                    .map(|(index, pairing)| {
+                        pairing.seating = true;
+                        (number, index, pairing.players.clone())
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The pairing of the current round, unless the tournament moved past the given round
+    fn held_pairing(&mut self, number: usize, pairing: usize) -> Option<&mut Pairing> {
+        match self.rounds.len() == number {
+            true => self.current_round_mut()?.pairings.get_mut(pairing),
+            false => None,
+        }
+    }
+
+    /// Give a held game its session, or `None` to have it seated again later
+    fn seated(&mut self, number: usize, pairing: usize, session_id: Option<String>) {
+        if let Some(pairing) = self.held_pairing(number, pairing) {
+            pairing.seating = false;
+            pairing.session_id = session_id;
+        }
+    }
+
+    /// Decide a game some of whose players were not there to play it
+    ///
+    /// When no one showed up, the better seed still goes through in single elimination
+    fn forfeit(&mut self, number: usize, pairing: usize, present: &[String]) {
+        let format = self.format;
+        let seeds = self.participants.clone();
+        let pairing = match self.held_pairing(number, pairing) {
+            Some(pairing) => pairing,
+            None => return,
+        };
+        pairing.seating = false;
+        pairing.finished = true;
+        pairing.forfeit = true;
+        pairing.winner = match present {
+            [winner] => Some(winner.clone()),
+            _ if format == Format::SingleElimination => seeds
+                .iter()
+                .find(|seed| pairing.players.contains(seed))
+                .cloned(),
+            _ => None,
+        };
+    }
+
+    /// Record how a game of the current round ended, returning whether it belonged to it
+    ///
+    /// Drawn games are played again in single elimination, since someone has to go through
+    fn record(&mut self, session_id: &str, winner: Option<String>) -> bool {
+        let elimination = self.format == Format::SingleElimination;
+        let pairing = match self.current_round_mut().and_then(|round| {
+            round.pairings.iter_mut().find(|pairing| {
+                !pairing.finished && pairing.session_id.as_deref() == Some(session_id)
+            })
+        }) {
+            Some(pairing) => pairing,
+            None => return false,
+        };
+        if let Some(winner) = &winner {
+            if !pairing.players.contains(winner) {
+                return false;
+            }
+        }
+        if winner.is_none() && elimination {
+            pairing.session_id = None;
+            return true;
+        }
+        pairing.finished = true;
+        pairing.winner = winner;
+        true
+    }
+
+    /// Settle a game of the current round by hand
+    fn report(
+        &mut self,
+        pairing: usize,
+        winner: Option<String>,
+    ) -> std::result::Result<(), &'static str> {
+        let elimination = self.format == Format::SingleElimination;
+        let pairing = match self.current_round_mut() {
+            Some(round) => round
+                .pairings
+                .get_mut(pairing)
+                .ok_or("the current round has no such pairing")?,
+            None => return Err("the tournament is not running"),
+        };
+        if pairing.finished {
+            return Err("the pairing is already decided");
+        }
+        match &winner {
+            Some(winner) if !pairing.players.contains(winner) => {
+                return Err("the winner must be one of the players of the pairing")
+            }
+            None if elimination => return Err("single elimination games need a winner"),
+            _ => {}
+        }
+        pairing.finished = true;
+        pairing.winner = winner;
+        Ok(())
+    }
+
+    /// Move on once every game of the current round finished, returning whether it did
+    fn advance(&mut self) -> bool {
+        let complete = match self.current_round() {
+            Some(round) => round.pairings.iter().all(|pairing| pairing.finished),
+            None => false,
+        };
+        if !complete {
+            return false;
+        }
+        if Some(self.rounds.len()) == self.total_rounds {
+            self.status = Status::Finished;
+            self.champion = self.standings().first().map(|standing| standing.id.clone());
+        } else {
+            self.begin_round();
+        }
+        true
+    }
+
+    fn begin_round(&mut self) {
+        let number = self.rounds.len() + 1;
+        let pairings = match self.format {
+            Format::SingleElimination => self.elimination_pairings(),
+            Format::Swiss => self.swiss_pairings(),
+            Format::RoundRobin => self.round_robin_pairings(number - 1),
+        };
+        self.rounds.push(Round { number, pairings });
+    }
+
+    /// The first round follows the usual bracket, so that the best seeds only meet at the end.
+    /// Each later round pairs up the winners of neighbouring games
+    fn elimination_pairings(&self) -> Vec<Pairing> {
+        let players: Vec<Option<String>> = match self.rounds.last() {
+            None => bracket_order(self.participants.len().next_power_of_two())
+                .into_iter()
+                .map(|seed| self.participants.get(seed).cloned())
+                .collect(),
+            Some(round) => round
+                .pairings
+                .iter()
+                .map(|pairing| pairing.winner.clone())
+                .collect(),
+        };
+        players
+            .chunks(2)
+            .map(|pair| match pair {
+                [Some(a), Some(b)] => Pairing::game(vec![a.clone(), b.clone()]),
+                [Some(player), None] | [None, Some(player)] | [Some(player)] => {
+                    Pairing::bye(player.clone())
+                }
+                _ => unreachable!("every pair of the bracket has a player"),
+            })
+            .collect()
+    }
+
+    /// Players are paired down the standings with the next one they have not met yet,
+    /// and the lowest ranked player who has not had a bye sits out when they are odd
+    fn swiss_pairings(&self) -> Vec<Pairing> {
+        let mut unpaired: Vec<String> = self
+            .standings()
+            .into_iter()
+            .map(|standing| standing.id)
+            .collect();
+        let mut pairings = Vec::new();
+        if unpaired.len() % 2 == 1 {
+            let byes = self.byes();
+            let sitting_out = unpaired
+                .iter()
+                .rposition(|id| !byes.contains(id))
+                .unwrap_or(unpaired.len() - 1);
+            pairings.push(Pairing::bye(unpaired.remove(sitting_out)));
+        }
+        while !unpaired.is_empty() {
+            let player = unpaired.remove(0);
+            let opponent = unpaired
+                .iter()
+                .position(|other| !self.have_met(&player, other))
+                .unwrap_or(0);
+            let opponent = unpaired.remove(opponent);
+            pairings.push(Pairing::game(vec![player, opponent]));
+        }
+        pairings
+    }
+
+    /// Pairs of the circle method, where every player but the first moves one seat each round
+    fn round_robin_pairings(&self, round: usize) -> Vec<Pairing> {
+        let mut seats: Vec<Option<String>> = self.participants.iter().cloned().map(Some).collect();
+        if seats.len() % 2 == 1 {
+            seats.push(None);
+        }
+        let turns = round % (seats.len() - 1);
+        seats[1..].rotate_right(turns);
+        (0..seats.len() / 2)
+            .filter_map(|i| match (&seats[i], &seats[seats.len() - 1 - i]) {
+                (Some(a), Some(b)) => Some(Pairing::game(vec![a.clone(), b.clone()])),
+                // sitting out a round robin scores nothing, since everyone does it once
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn have_met(&self, a: &str, b: &str) -> bool {
+        self.rounds
+            .iter()
+            .flat_map(|round| &round.pairings)
+            .any(|pairing| {
+                pairing.players.iter().any(|player| player == a)
+                    && pairing.players.iter().any(|player| player == b)
+            })
+    }
+
+    fn byes(&self) -> Vec<String> {
+        self.rounds
+            .iter()
+            .flat_map(|round| &round.pairings)
+            .filter(|pairing| pairing.is_bye())
+            .map(|pairing| pairing.players[0].clone())
+            .collect()
+    }
+
+    /// Every participant ranked by how far they went and how many points they scored,
+    /// with a win worth 1 point, a draw half and a Swiss bye 1
+    pub fn standings(&self) -> Vec<Standing> {
+        let mut standings: Vec<Standing> = self
+            .participants
+            .iter()
+            .enumerate()
+            .map(|(seed, id)| Standing {
+                rank: 0,
+                id: id.clone(),
+                seed: seed + 1,
+                points: 0.0,
+                wins: 0,
+                losses: 0,
+                draws: 0,
+                byes: 0,
+                eliminated: false,
+                reached: 0,
+            })
+            .collect();
+        let seeds: HashMap<String, usize> = self
+            .participants
+            .iter()
+            .enumerate()
+            .map(|(seed, id)| (id.clone(), seed))
+            .collect();
+
+        for round in &self.rounds {
+            for pairing in &round.pairings {
+                for player in &pairing.players {
+                    let standing = &mut standings[seeds[player]];
+                    standing.reached = round.number;
+                    if !pairing.finished {
+                        continue;
+                    }
+                    match &pairing.winner {
+                        _ if pairing.is_bye() => {
+                            standing.byes += 1;
+                            if self.format == Format::Swiss {
+                                standing.points += 1.0;
+                            }
+                        }
+                        Some(winner) if winner == player => {
+                            standing.wins += 1;
+                            standing.points += 1.0;
+                        }
+                        Some(_) => {
+                            standing.losses += 1;
+                            standing.eliminated = self.format == Format::SingleElimination;
+                        }
+                        None if pairing.forfeit => standing.losses += 1,
+                        None => {
+                            standing.draws += 1;
+                            standing.points += 0.5;
+                        }
+                    }
+                }
+            }
+        }
+
+        standings.sort_by(|a, b| {
+            let progress = |standing: &Standing| match self.format {
+                Format::SingleElimination => (!standing.eliminated, standing.reached),
+                _ => (true, 0),
+            };
+            progress(b)
+                .cmp(&progress(a))
+                .then(b.points.total_cmp(&a.points))
+                .then(a.seed.cmp(&b.seed))
+        });
+        for (index, standing) in standings.iter_mut().enumerate() {
+            standing.rank = index + 1;
+        }
+        standings
+    }
+}
+
+/// The seeds of a bracket of the given size, from its top to its bottom, counted from 0
+///
+/// Each neighbouring pair meets in the first round, and the best seeds end up in opposite halves
+fn bracket_order(size: usize) -> Vec<usize> {
+    let mut order = vec![0];
+    while order.len() < size {
+        let seeds = order.len() * 2;
+        order = order
+            .into_iter()
+            .flat_map(|seed| [seed, seeds - 1 - seed])
+            .collect();
+    }
+    order
+}
+
+/// Every tournament, by its id
+pub type Tournaments = HashMap<String, Tournament>;
+
+/// Seat the games of the current round which need it and move through every round which is
+/// already decided, keeping the participants up to date
+///
+/// Players who are not connected when their game is due forfeit it. The tournaments are not
+/// held while sessions are started, so the games are seated under their round and index
+async fn progress(
+    id: &str,
+    tournaments: &data_types::SafeTournaments,
+    clients: &data_types::SafeClients,
+    sessions: &data_types::SafeSessions,
+    config: &data_types::SharedConfig,
+) {
+    loop {
+        let games = match metrics::write(tournaments, "tournaments").await.get_mut(id) {
+            Some(tournament) => tournament.games_to_seat(),
+            None => return,
+        };
+        for (round, pairing, players) in games {
+            let members: Vec<_> = {
+                let clients = metrics::read(clients, "clients").await;
+                players
+                    .iter()
+                    .filter_map(|id| clients.get(id).cloned())
+                    .collect()
+            };
+            if members.len() < players.len() {
+                let present: Vec<String> = members.into_iter().map(|client| client.id).collect();
+                info!(tournament_id = %id, ?players, ?present, "game was forfeited");
+                if let Some(tournament) =
+                    metrics::write(tournaments, "tournaments").await.get_mut(id)
+                {
+                    tournament.forfeit(round, pairing, &present);
+                }
+                continue;
+            }
+            let session_id = game_engine::start_game_between(members, sessions, config).await;
+            if session_id.is_none() {
+                warn!(tournament_id = %id, ?players, "could not seat tournament game");
+            }
+            if let Some(tournament) = metrics::write(tournaments, "tournaments").await.get_mut(id) {
+                tournament.seated(round, pairing, session_id);
+            }
+        }
+
+        let tournament = {
+            let mut tournaments = metrics::write(tournaments, "tournaments").await;
+            let tournament = match tournaments.get_mut(id) {
+                Some(tournament) => tournament,
+                None => return,
+            };
+            if !tournament.advance() {
+                return;
+            }
+            tournament.clone()
+        };
+        announce(&tournament, clients).await;
+        if tournament.status == Status::Finished {
+            info!(tournament_id = %id, champion = ?tournament.champion, "tournament finished");
+            return;
+        }
+    }
+}
+
+/// Tell the connected participants that a tournament moved on
+async fn announce(tournament: &Tournament, clients: &data_types::SafeClients) {
+    let message = match (tournament.status, &tournament.champion) {
+        (Status::Finished, Some(champion)) => {
+            format!("{} has finished, {} won!", tournament.name, champion)
+        }
+        (Status::Finished, None) => format!("{} has finished.", tournament.name),
+        _ => format!(
+            "Round {} of {} has started.",
+            tournament.rounds.len(),
+            tournament.name
+        ),
+    };
+    notify_participants(tournament, message, clients).await;
+}
+
+async fn notify_participants(
+    tournament: &Tournament,
+    message: String,
+    clients: &data_types::SafeClients,
+) {
+    let event = EventBuilder::default()
+        .event_code(ServerEventCode::TournamentUpdated)
+        .message(message)
+        .data(
+            ServerEventDataBuilder::default()
+                .tournament_id(tournament.id.clone())
+                .build()
+                .unwrap(),
+        )
+        .build()
+        .unwrap();
+    let clients = metrics::read(clients, "clients").await;
+    for id in &tournament.participants {
+        if let Some(client) = clients.get(id) {
+            game_engine::notify_client(&event, client);
+        }
+    }
+}
+
+/// Record the results of tournament games as they finish, for as long as the server runs
+pub async fn record_finished_games(
+    mut finished_games: broadcast::Receiver<FinishedGame>,
+    tournaments: data_types::SafeTournaments,
+    clients: data_types::SafeClients,
+    sessions: data_types::SafeSessions,
+    config: data_types::SharedConfig,
+) {
+    loop {
+        let game = match finished_games.recv().await {
+            Ok(game) => game,
+            Err(RecvError::Lagged(missed)) => {
+                warn!(missed, "finished games were not checked for tournaments");
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+
+        let tournament = metrics::write(&tournaments, "tournaments")
+            .await
+            .values_mut()
+            .find_map(|tournament| {
+                tournament
+                    .record(&game.session_id, game.winner.clone())
+                    .then(|| tournament.clone())
+            });
+        let tournament = match tournament {
+            Some(tournament) => tournament,
+            None => continue,
+        };
+        let message = match &game.winner {
+            Some(winner) => format!("{} won their game in {}.", winner, tournament.name),
+            None => format!("A game in {} was drawn.", tournament.name),
+        };
+        notify_participants(&tournament, message, &clients).await;
+        progress(&tournament.id, &tournaments, &clients, &sessions, &config).await;
+    }
+}
+
+/// A summary of a tournament in the list of them
+#[derive(Serialize)]
+struct TournamentSummary<'a> {
+    id: &'a str,
+    name: &'a str,
+    format: Format,
+    status: Status,
+    participants: usize,
+    round: usize,
+    total_rounds: Option<usize>,
+}
+
+/// A tournament along with its standings
+#[derive(Serialize)]
+struct TournamentView<'a> {
+    #[serde(flatten)]
+    tournament: &'a Tournament,
+    standings: Vec<Standing>,
+}
+
+/// Lists every tournament
+pub async fn list(tournaments: data_types::SafeTournaments) -> Result<impl Reply> {
+    let tournaments = metrics::read(&tournaments, "tournaments").await;
+    let summaries: Vec<TournamentSummary> = tournaments
+        .values()
+        .map(|tournament| TournamentSummary {
+            id: &tournament.id,
+            name: &tournament.name,
+            format: tournament.format,
+            status: tournament.status,
+            participants: tournament.participants.len(),
+            round: tournament.rounds.len(),
+            total_rounds: tournament.total_rounds,
+        })
+        .collect();
+    Ok(warp::reply::json(&summaries))
+}
+
+/// Shows a tournament with its rounds and standings
+pub async fn get(
+    id: String,
+    tournaments: data_types::SafeTournaments,
+) -> Result<warp::reply::Response> {
+    let id = match handler::decode_path_id(&id) {
+        Ok(id) => id,
+        Err(status) => return Ok(status.into_response()),
+    };
+    let tournaments = metrics::read(&tournaments, "tournaments").await;
+    Ok(match tournaments.get(&id) {
+        Some(tournament) => warp::reply::json(&TournamentView {
+            tournament,
+            standings: tournament.standings(),
+        })
+        .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    })
+}
+
+/// Registers the account a request is logged into for a tournament
+pub async fn register(
+    id: String,
+    token: Option<String>,
+    tournaments: data_types::SafeTournaments,
+    accounts: data_types::SafeAccounts,
+) -> Result<warp::reply::Response> {
+    let id = match handler::decode_path_id(&id) {
+        Ok(id) => id,
+        Err(status) => return Ok(status.into_response()),
+    };
+    let username = match token {
+        Some(token) => metrics::read(&accounts, "accounts")
+            .await
+            .authenticate(&token),
+        None => None,
+    };
+    let username = match username {
+        Some(username) => username,
+        None => return Ok(problem("log in to register", StatusCode::UNAUTHORIZED)),
+    };
+
+    let mut tournaments = metrics::write(&tournaments, "tournaments").await;
+    let tournament = match tournaments.get_mut(&id) {
+        Some(tournament) => tournament,
+        None => return Ok(StatusCode::NOT_FOUND.into_response()),
+    };
+    Ok(match tournament.register(&username) {
+        Ok(()) => {
+            info!(tournament_id = %id, username = %username, "player registered for tournament");
+            StatusCode::CREATED.into_response()
+        }
+        Err(message) => problem(message, StatusCode::CONFLICT),
+    })
+}
+
+/// What an operator sends to create a tournament
+#[derive(Deserialize)]
+pub struct NewTournament {
+    name: String,
+    format: Format,
+    /// Only used by Swiss tournaments, which otherwise play enough rounds to find a single winner
+    rounds: Option<usize>,
+}
+
+/// Creates a tournament which players can register for
+pub async fn create(
+    new: NewTournament,
+    tournaments: data_types::SafeTournaments,
+) -> Result<warp::reply::Response> {
+    let name = new.name.trim().to_string();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Ok(problem(
+            "the name must be between 1 and 50 characters",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+    if new.rounds == Some(0) {
+        return Ok(problem(
+            "a tournament needs at least 1 round",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let tournament = Tournament::new(name, new.format, new.rounds);
+    info!(tournament_id = %tournament.id, name = %tournament.name, "admin created tournament");
+    let reply = warp::reply::with_status(warp::reply::json(&tournament), StatusCode::CREATED);
+    metrics::write(&tournaments, "tournaments")
+        .await
+        .insert(tournament.id.clone(), tournament);
+    Ok(reply.into_response())
+}
+
+/// Closes registration and starts the first round
+pub async fn start(
+    id: String,
+    tournaments: data_types::SafeTournaments,
+    ratings: data_types::SafeRatings,
+    clients: data_types::SafeClients,
+    sessions: data_types::SafeSessions,
+    config: data_types::SharedConfig,
+) -> Result<warp::reply::Response> {
+    let id = match handler::decode_path_id(&id) {
+        Ok(id) => id,
+        Err(status) => return Ok(status.into_response()),
+    };
+    let mut held = metrics::write(&tournaments, "tournaments").await;
+    let tournament = match held.get_mut(&id) {
+        Some(tournament) => tournament,
+        None => return Ok(StatusCode::NOT_FOUND.into_response()),
+    };
+
+    let known: HashMap<String, f64> = {
+        let ratings = metrics::read(&ratings, "ratings").await;
+        tournament
+            .participants
+            .iter()
+            .map(|id| (id.clone(), ratings.rating(id, &config.ratings)))
+            .collect()
+    };
+    if let Err(message) = tournament.start(|id| known[id]) {
+        return Ok(problem(message, StatusCode::CONFLICT));
+    }
+    info!(tournament_id = %id, players = tournament.participants.len(), "admin started tournament");
+    let started = tournament.clone();
+    drop(held);
+    announce(&started, &clients).await;
+    progress(&id, &tournaments, &clients, &sessions, &config).await;
+
+    let tournaments = metrics::read(&tournaments, "tournaments").await;
+    Ok(match tournaments.get(&id) {
+        Some(tournament) => warp::reply::json(&TournamentView {
+            tournament,
+            standings: tournament.standings(),
+        })
+        .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    })
+}
+
+/// The result of a game which an operator settles by hand
+#[derive(Deserialize)]
+pub struct ResultReport {
+    /// The index of the game in the current round
+    pairing: usize,
+    /// `None` for a draw
+    winner: Option<String>,
+}
+
+/// Settles a game of the current round, e.g. one whose players left before it ended
+pub async fn report(
+    id: String,
+    report: ResultReport,
+    tournaments: data_types::SafeTournaments,
+    clients: data_types::SafeClients,
+    sessions: data_types::SafeSessions,
+    config: data_types::SharedConfig,
+) -> Result<warp::reply::Response> {
+    let id = match handler::decode_path_id(&id) {
+        Ok(id) => id,
+        Err(status) => return Ok(status.into_response()),
+    };
+    let reported = match metrics::write(&tournaments, "tournaments")
+        .await
+        .get_mut(&id)
+    {
+        Some(tournament) => tournament.report(report.pairing, report.winner),
+        None => return Ok(StatusCode::NOT_FOUND.into_response()),
+    };
+    if let Err(message) = reported {
+        return Ok(problem(message, StatusCode::CONFLICT));
+    }
+    info!(tournament_id = %id, pairing = report.pairing, "admin settled tournament game");
+    progress(&id, &tournaments, &clients, &sessions, &config).await;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+fn problem(message: &str, status: StatusCode) -> warp::reply::Response {
+    warp::reply::with_status(message.to_string(), status).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tournament(format: Format, players: usize) -> Tournament {
+        let mut tournament = Tournament::new(String::from("Cup"), format, None);
+        for player in 0..players {
+            tournament.register(&format!("p{}", player)).unwrap();
+        }
+        // earlier registrations are rated higher, so they are also the better seeds
+        tournament
+            .start(|id| -id[1..].parse::<f64>().unwrap())
+            .unwrap();
+        tournament
+    }
+
+    /// Play out every game of the current round, the better seed winning unless it is an upset
+    fn play_round(tournament: &mut Tournament, upset: bool) {
+        for (round, pairing, players) in tournament.games_to_seat() {
+            let session_id = format!("r{}g{}", round, pairing);
+            tournament.seated(round, pairing, Some(session_id.clone()));
+            let seed = |id: &String| tournament.participants.iter().position(|p| p == id);
+            let better_first = seed(&players[0]) < seed(&players[1]);
+            let winner = players[usize::from(better_first == upset)].clone();
+            assert!(tournament.record(&session_id, Some(winner)));
+        }
+    }
+
+    #[test]
+    fn brackets_keep_the_best_seeds_apart() {
+        assert_eq!(bracket_order(4), [0, 3, 1, 2]);
+        assert_eq!(bracket_order(8), [0, 7, 3, 4, 1, 6, 2, 5]);
+    }
+
+    #[test]
+    fn single_elimination_plays_down_to_a_champion() {
+        let mut tournament = tournament(Format::SingleElimination, 6);
+        assert_eq!(tournament.total_rounds, Some(3));
+        // the two best seeds get byes in a bracket of 8
+        let byes = tournament.byes();
+        assert_eq!(byes, ["p0", "p1"]);
+
+        while tournament.status == Status::Running {
+            play_round(&mut tournament, false);
+            assert!(tournament.advance());
+        }
+        assert_eq!(tournament.champion.as_deref(), Some("p0"));
+        let standings = tournament.standings();
+        assert_eq!(standings[1].id, "p1");
+        assert!(standings[1].eliminated);
+        assert!(!standings[0].eliminated);
+    }
+
+    #[test]
+    fn elimination_draws_are_replayed() {
+        let mut tournament = tournament(Format::SingleElimination, 2);
+        tournament.games_to_seat();
+        tournament.seated(1, 0, Some(String::from("A")));
+        assert!(tournament.record("A", None));
+        assert!(!tournament.advance());
+        assert_eq!(tournament.games_to_seat().len(), 1);
+    }
+
+    #[test]
+    fn round_robin_meets_everyone_once() {
+        let mut tournament = tournament(Format::RoundRobin, 5);
+        assert_eq!(tournament.total_rounds, Some(5));
+        while tournament.status == Status::Running {
+            play_round(&mut tournament, false);
+            tournament.advance();
+        }
+        for a in &tournament.participants {
+            for b in &tournament.participants {
+                let meetings = tournament
+                    .rounds
+                    .iter()
+                    .flat_map(|round| &round.pairings)
+                    .filter(|pairing| pairing.players.contains(a) && pairing.players.contains(b))
+                    .count();
+                assert_eq!(meetings, if a == b { 4 } else { 1 });
+            }
+        }
+        let standings = tournament.standings();
+        assert_eq!(standings[0].points, 4.0);
+        assert_eq!(standings[4].points, 0.0);
+    }
+
+    #[test]
+    fn swiss_avoids_rematches_and_gives_one_bye_each() {
+        let mut tournament = tournament(Format::Swiss, 5);
+        assert_eq!(tournament.total_rounds, Some(3));
+        while tournament.status == Status::Running {
+            play_round(&mut tournament, true);
+            tournament.advance();
+        }
+        let byes = tournament.byes();
+        assert_eq!(byes.len(), 3);
+        assert!(byes
+            .iter()
+            .all(|id| byes.iter().filter(|other| *other == id).count() == 1));
+        let games: Vec<_> = tournament
+            .rounds
+            .iter()
+            .flat_map(|round| &round.pairings)
+            .filter(|pairing| !pairing.is_bye())
+            .map(|pairing| {
+                let mut players = pairing.players.clone();
+                players.sort();
+                players
+            })
+            .collect();
+        for game in &games {
+            assert_eq!(games.iter().filter(|other| *other == game).count(), 1);
+        }
+    }
+
+    #[test]
+    fn games_are_only_seated_once() {
+        let mut tournament = tournament(Format::RoundRobin, 4);
+        let games = tournament.games_to_seat();
+        assert_eq!(games.len(), 2);
+        // held games are not handed out again while their sessions start
+        assert!(tournament.games_to_seat().is_empty());
+
+        // a game which could not be seated is tried again
+        tournament.seated(1, 0, None);
+        tournament.seated(1, 1, Some(String::from("A")));
+        assert_eq!(tournament.games_to_seat(), [(1, 0, games[0].2.clone())]);
+
+        // sessions started for a round which is already over are not written back
+        tournament.report(0, None).unwrap();
+        tournament.report(1, None).unwrap();
+        assert!(tournament.advance());
+        tournament.seated(1, 0, Some(String::from("B")));
+        assert!(tournament.rounds[1]
+            .pairings
+            .iter()
+            .all(|pairing| pairing.session_id.is_none()));
+    }
+
+    #[test]
+    fn absent_players_forfeit() {
+        let mut tournament = tournament(Format::Swiss, 2);
+        tournament.games_to_seat();
+        tournament.forfeit(1, 0, &[String::from("p1")]);
+        assert!(tournament.advance());
+        assert_eq!(tournament.champion.as_deref(), Some("p1"));
+    }
+}
@@ -0,0 +1,26 @@
+//! The channel between the server and whichever transport a client is connected over
+use sessions::sink::{ClientSink, Outbound, SinkClosed};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+/// Queues the messages of a client for the task which owns their connection
+#[derive(Debug)]
+struct ChannelSink(mpsc::UnboundedSender<Outbound>);
+
+impl ClientSink for ChannelSink {
+    fn send(&self, message: Outbound) -> Result<(), SinkClosed> {
+        self.0.send(message).map_err(|_| SinkClosed)
+    }
+}
+
+/// A sink for a new connection, along with the messages its transport has to deliver
+///
+/// The stream ends once every copy of the sink has been dropped
+pub fn channel() -> (Arc<dyn ClientSink>, UnboundedReceiverStream<Outbound>) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    (
+        Arc::new(ChannelSink(sender)),
+        UnboundedReceiverStream::new(receiver),
+    )
+}
@@ -1,21 +1,30 @@
-use crate::{data_types, game_engine};
-use futures::{FutureExt, StreamExt};
+use crate::rate_limit::{ClientLimiter, Verdict};
+use crate::shared_types::{ClientEvent, ClientEventCode};
+use crate::transport;
+use crate::{codec, data_types, game_engine, metrics, names, protocol};
+use futures::stream::SplitStream;
+use futures::{future, FutureExt, StreamExt};
 use sessions::session_types;
-use tokio::sync::mpsc::{self};
-use urlencoding::decode;
+use sessions::sink::{ClientSink, Outbound};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{interval_at, sleep, timeout, Instant};
+use tracing::{debug, error, info, instrument, warn, Instrument, Span};
 use warp::ws::{Message, WebSocket};
 
-/// The Initial Setup for a WebSocket Connection
+/// How long an abusive client has to acknowledge being disconnected
+const CLOSE_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The Initial Setup for a WebSocket Connection, for a client id already decoded from the path
+#[instrument(name = "client", skip_all, fields(client_id = tracing::field::Empty))]
 pub async fn client_connection(
     ws: WebSocket,
-    connection_id: String,
+    id: String,
     clients: data_types::SafeClients,
     sessions: data_types::SafeSessions,
-    game_states: data_types::SafeGameStates,
+    config: data_types::SharedConfig,
 ) {
-    // Decode the strings coming in over URL parameters so we dont get things like '%20'
-    // for spaces in our clients map
-    let id = decode(&connection_id).expect("UTF-8").to_string();
+    Span::current().record("client_id", id.as_str());
     //======================================================
     // Splits the WebSocket into a Sink + Stream:
     // Sink - Pools the messages to get send to the client
@@ -23,58 +32,121 @@ pub async fn client_connection(
     //======================================================
     let (client_ws_sender, mut client_ws_rcv) = ws.split();
     //======================================================
-    // Gets an Unbounced Channel that can transport messages
-    // between asynchronous tasks:
-    // Sender - front end of the channel
-    // Receiver - recieves the sender messages
+    // Gets a Sink that can transport messages between
+    // asynchronous tasks:
+    // Sink - front end of the channel
+    // Stream - recieves the messages sent into the sink
     //======================================================
-    let (client_sender, client_rcv) = mpsc::unbounded_channel();
+    let (client_sender, client_rcv) = transport::channel();
     //======================================================
     // Spawn a thread to forward messages
     // from our channel into our WebSocket Sink
     // between asynchronous tasks using the same Client object
     //======================================================
-    tokio::task::spawn(client_rcv.forward(client_ws_sender).map(|result| {
-        if let Err(e) = result {
-            eprintln!("[ERROR] failed to send websocket msg: {}", e);
-        }
-    }));
+    tokio::task::spawn(
+        client_rcv
+            .map(|message| Ok(to_message(message)))
+            .forward(client_ws_sender)
+            .map(|result| {
+                if let Err(e) = result {
+                    error!(error = %e, "failed to send websocket msg");
+                }
+            })
+            .in_current_span(),
+    );
     //======================================================
     // From now on we can use our client_sender.send(val: T)
     // to send messages to a given client websocket
     //======================================================
 
     //======================================================
-    // Create a new Client and insert them into the Map
+    // The client must open with a Hello stating a protocol
+    // version we understand before it is allowed to play
     //======================================================
-    clients.write().await.insert(
-        id.clone(),
-        session_types::Client {
-            id: id.clone(),
-            sender: Some(client_sender),
-            session_id: get_client_session_id(&id, &sessions).await,
-        },
-    );
+    let handshake = timeout(
+        config.grace.handshake(),
+        negotiate_protocol(&id, &mut client_ws_rcv, &client_sender),
+    )
+    .await
+    .unwrap_or(Err((
+        protocol::CLOSE_HANDSHAKE_REQUIRED,
+        "no Hello event was received in time",
+    )));
+    let (encoding, display_name) = match handshake {
+        Ok(negotiated) => negotiated,
+        Err((code, reason)) => {
+            info!(code, reason, "closing connection, handshake failed");
+            metrics::error("handshake_failed");
+            if let Err(e) = client_sender.send(Outbound::close(code, reason)) {
+                error!(error = %e, "failed to close websocket");
+            }
+            return;
+        }
+    };
 
-    if let Some(client) = clients.read().await.get(&id) {
-        handle_client_connect(&client, &sessions).await;
-    }
+    //======================================================
+    // Create a new Client and insert them into the Map
+    //======================================================
+    register_client(
+        &id,
+        display_name,
+        client_sender.clone(),
+        encoding,
+        &clients,
+        &sessions,
+    )
+    .await;
     //======================================================
     // Synchronously wait for messages from the
-    // Client Receiver Stream until an error occurs
+    // Client Receiver Stream until an error occurs,
+    // the client is disconnected for abuse,
+    // or it stops answering the heartbeat pings
     //======================================================
-    while let Some(result) = client_ws_rcv.next().await {
+    let mut limiter = ClientLimiter::new(&config.limits);
+    let mut heartbeat = interval_at(
+        Instant::now() + config.grace.heartbeat(),
+        config.grace.heartbeat(),
+    );
+    let mut last_heard = Instant::now();
+    loop {
+        let result = tokio::select! {
+            result = client_ws_rcv.next() => match result {
+                Some(result) => result,
+                None => break,
+            },
+            _ = heartbeat.tick() => {
+                if last_heard.elapsed() >= config.grace.heartbeat_timeout() {
+                    info!("closing connection, client stopped answering pings");
+                    metrics::error("heartbeat_timeout");
+                    let _ = client_sender.send(Outbound::close(
+                        protocol::CLOSE_HEARTBEAT_TIMEOUT,
+                        "no pong was received in time",
+                    ));
+                    break;
+                }
+                let _ = client_sender.send(Outbound::Ping(Vec::new()));
+                continue;
+            }
+        };
+        // any frame at all, pongs included, shows the client is still there
+        last_heard = Instant::now();
         // Check that there was no error actually obtaining the Message
         match result {
             Ok(msg) => {
-                handle_client_msg(&id, msg, &clients, &sessions, &game_states).await;
+                let closed =
+                    handle_client_msg(&id, msg, &mut limiter, &clients, &sessions, &config).await;
+                if let Some((code, reason)) = closed {
+                    info!(code, reason, "disconnecting abusive client");
+                    let _ = client_sender.send(Outbound::close(code, reason));
+                    // read until the client acknowledges the close, since dropping a socket
+                    // with unread frames resets it and loses the replies already sent
+                    let drain = (&mut client_ws_rcv).for_each(|_| future::ready(()));
+                    let _ = timeout(CLOSE_DRAIN_TIMEOUT, drain).await;
+                    break;
+                }
             }
             Err(e) => {
-                eprintln!(
-                    "[ERROR] failed to recieve websocket message for id: {} :: error: {}",
-                    id.clone(),
-                    e,
-                );
+                error!(error = %e, "failed to recieve websocket message");
             }
         }
     }
@@ -82,106 +154,308 @@ pub async fn client_connection(
     // Remove the Client from the Map
     // when they are finished using the socket (or error)
     //======================================================
-    if let Some(client) = clients.write().await.remove(&id) {
-        handle_client_disconnect(&client, &sessions, &game_states).await;
+    unregister_client(&id, &clients, &sessions, &config).await;
+}
+
+/// Insert a newly connected Client into the Map and reactivate them in their previous session
+///
+/// Shared by every transport, which only differ in what drains the sender
+pub async fn register_client(
+    id: &str,
+    display_name: String,
+    sender: Arc<dyn ClientSink>,
+    encoding: session_types::Encoding,
+    clients: &data_types::SafeClients,
+    sessions: &data_types::SafeSessions,
+) {
+    let client = session_types::Client {
+        id: id.to_string(),
+        display_name,
+        sender: Some(sender),
+        encoding,
+    };
+    metrics::write(clients, "clients")
+        .await
+        .insert(id.to_string(), client.clone());
+
+    handle_client_connect(client, sessions).await;
+}
+
+/// Remove a Client from the Map once their connection has closed
+pub async fn unregister_client(
+    id: &str,
+    clients: &data_types::SafeClients,
+    sessions: &data_types::SafeSessions,
+    config: &data_types::SharedConfig,
+) {
+    if let Some(client) = metrics::write(clients, "clients").await.remove(id) {
+        handle_client_disconnect(&client, sessions, config).await;
+    }
+}
+
+/// Frame a message for the websocket
+fn to_message(message: Outbound) -> Message {
+    match message {
+        Outbound::Text(text) => Message::text(text),
+        Outbound::Binary(bytes) => Message::binary(bytes),
+        Outbound::Ping(payload) => Message::ping(payload),
+        Outbound::Close { code, reason } => Message::close_with(code, reason),
+    }
+}
+
+/// Wait for the opening `Hello` of a client and answer it with the capabilities of the server
+///
+/// Resolves to the encoding the client asked to receive events in and the name it is shown with,
+/// or fails with the close code and reason the connection should be closed with
+async fn negotiate_protocol(
+    id: &str,
+    client_ws_rcv: &mut SplitStream<WebSocket>,
+    client_sender: &Arc<dyn ClientSink>,
+) -> Result<(session_types::Encoding, String), (u16, &'static str)> {
+    while let Some(Ok(msg)) = client_ws_rcv.next().await {
+        // pings may arrive before the handshake
+        if let Ok("ping") | Ok("ping\n") = msg.to_str() {
+            continue;
+        }
+
+        let hello = match codec::decode(&msg) {
+            Ok(event) if event.event_code == ClientEventCode::Hello => event,
+            _ => break,
+        };
+        let data = hello.data.as_ref();
+
+        let version = data.and_then(|data| data.protocol_version);
+        if !version.map(protocol::is_supported_version).unwrap_or(false) {
+            return Err((
+                protocol::CLOSE_UNSUPPORTED_PROTOCOL,
+                "unsupported protocol version",
+            ));
+        }
+
+        let encoding = match data.and_then(|data| data.encoding.as_deref()) {
+            None => session_types::Encoding::Json,
+            Some(name) => match codec::parse_encoding(name) {
+                Some(encoding) => encoding,
+                None => return Err((protocol::CLOSE_UNSUPPORTED_ENCODING, "unsupported encoding")),
+            },
+        };
+
+        let display_name = match data.and_then(|data| data.display_name.as_deref()) {
+            None => names::default_for(id),
+            Some(requested) => match names::validate(requested) {
+                Ok(display_name) => display_name,
+                Err(_) => {
+                    return Err((
+                        protocol::CLOSE_INVALID_DISPLAY_NAME,
+                        "display name not allowed",
+                    ))
+                }
+            },
+        };
+
+        let mut welcome = protocol::welcome_event(encoding, id, &display_name);
+        welcome.request_id = hello.request_id;
+        let _ = client_sender.send(codec::encode(&welcome, encoding));
+        return Ok((encoding, display_name));
     }
+
+    Err((
+        protocol::CLOSE_HANDSHAKE_REQUIRED,
+        "the first event must be a Hello",
+    ))
 }
 
 /// Handle messages from an open receiving websocket
 async fn handle_client_msg(
     id: &str,
     msg: Message,
+    limiter: &mut ClientLimiter,
     clients: &data_types::SafeClients,
     sessions: &data_types::SafeSessions,
-    game_states: &data_types::SafeGameStates,
-) {
+    config: &data_types::SharedConfig,
+) -> Option<(u16, &'static str)> {
     //======================================================
-    // Ensure the Message Parses to String
+    // ignore pings
     //======================================================
-    let message = match msg.to_str() {
-        Ok(v) => v,
-        Err(_) => {
-            eprintln!("[WARN] websocket message: '{:?}' was not handled", msg);
-            return;
+    if let Ok("ping") | Ok("ping\n") = msg.to_str() {
+        debug!("ignoring ping");
+        return None;
+    }
+    //======================================================
+    // Only text and binary frames carry events
+    //======================================================
+    if msg.is_close() {
+        debug!("client closed the connection");
+        return None;
+    }
+    if msg.is_ping() || msg.is_pong() {
+        return None;
+    }
+    if !msg.is_text() && !msg.is_binary() {
+        warn!(message = ?msg, "websocket message was not handled");
+        return None;
+    }
+    if msg.as_bytes().len() > config.limits.max_message_bytes {
+        metrics::RATE_LIMITED
+            .with_label_values(&["message_size"])
+            .inc();
+        metrics::ABUSE_DISCONNECTS
+            .with_label_values(&["message_size"])
+            .inc();
+        return Some((protocol::CLOSE_MESSAGE_TOO_BIG, "message too big"));
+    }
+    //======================================================
+    // Game Session Related Events
+    //======================================================
+    match codec::decode(&msg) {
+        Ok(client_event) => {
+            match admit_event(id, &client_event, limiter, clients, sessions).await {
+                Verdict::Allow => {
+                    game_engine::handle_event(id, client_event, clients, sessions, config).await
+                }
+                Verdict::Reject(_) => {}
+                Verdict::Disconnect => {
+                    return Some((protocol::CLOSE_RATE_LIMITED, "rate limit exceeded"))
+                }
+            }
         }
+        Err(e) => {
+            // malformed events still count towards the rate limit
+            if limiter.check(false) == Verdict::Disconnect {
+                metrics::ABUSE_DISCONNECTS
+                    .with_label_values(&["rate"])
+                    .inc();
+                return Some((protocol::CLOSE_RATE_LIMITED, "rate limit exceeded"));
+            }
+            game_engine::reject_event(id, &e, clients).await;
+        }
+    }
+    None
+}
+
+/// Hold an event to the client's rate limits, telling the client when it was dropped
+///
+/// Shared by every transport
+pub async fn admit_event(
+    id: &str,
+    client_event: &ClientEvent,
+    limiter: &mut ClientLimiter,
+    clients: &data_types::SafeClients,
+    sessions: &data_types::SafeSessions,
+) -> Verdict {
+    let creates_session = match client_event.event_code {
+        ClientEventCode::CreateSession => true,
+        // joining a session which does not exist yet reserves it
+        ClientEventCode::JoinSession => match client_event
+            .data
+            .as_ref()
+            .and_then(|data| data.session_id.as_ref())
+        {
+            Some(session_id) => !metrics::read(sessions, "sessions")
+                .await
+                .contains(session_id),
+            None => false,
+        },
+        _ => false,
     };
 
-    match message {
-        //======================================================
-        // ignore pings
-        //======================================================
-        "ping" | "ping\n" => {
-            println!("[INFO] ignoring ping...");
+    let verdict = limiter.check(creates_session);
+    match verdict {
+        Verdict::Allow => {}
+        Verdict::Reject(limit) => {
+            warn!(limit, "dropping event over the rate limit");
+            metrics::RATE_LIMITED.with_label_values(&[limit]).inc();
+            let msg = match limit {
+                "sessions" => "Too many sessions were created, try again in a minute.",
+                _ => "Too many events were sent, slow down.",
+            };
+            game_engine::refuse_event(id, &client_event.request_id, "rate_limited", msg, clients)
+                .await;
         }
-        //======================================================
-        // Game Session Related Events
-        //======================================================
-        _ => {
-            game_engine::handle_event(id, message, clients, sessions, game_states).await;
+        Verdict::Disconnect => {
+            metrics::ABUSE_DISCONNECTS
+                .with_label_values(&["rate"])
+                .inc();
         }
     }
+    verdict
 }
 
 /// If a client exists in a session, then set their status to inactive.
 ///
 /// If setting inactive status would leave no other active member, remove the session
+/// once its grace period has passed without anyone reconnecting
 async fn handle_client_disconnect(
     client: &session_types::Client,
     sessions: &data_types::SafeSessions,
-    game_states: &data_types::SafeGameStates,
+    config: &data_types::SharedConfig,
 ) {
-    println!("[INFO] {} disconnected", client.id);
-    if let Some(session_id) = &client.session_id {
-        let mut session_empty = false;
-        // remove the client from the session and check if the session become empty
-        if let Some(session) = sessions.write().await.get_mut(session_id) {
-            session.set_client_active_status(&client.id, false);
-            session_empty = session.get_clients_with_active_status(true).is_empty();
-        }
+    info!(client_id = %client.id, "client disconnected");
+    metrics::write(sessions, "sessions")
+        .await
+        .queue_mut()
+        .leave(&client.id);
+    if let Some((session_id, session)) = game_engine::client_session(&client.id, sessions).await {
+        // set the client inactive and check if the session became empty
+        let session_empty = session.disconnect(&client.id).await.unwrap_or(false);
         // remove the session if empty
         if session_empty {
-            cleanup_session(session_id, sessions, game_states).await;
+            let grace = config.grace.empty_session();
+            if grace.as_secs() == 0 {
+                cleanup_session(&session_id, sessions).await;
+            } else {
+                schedule_cleanup(session_id, sessions, grace);
+            }
         }
     }
 }
 
-/// If a client exists in a session, then set their status to active
-async fn handle_client_connect(
-    client: &session_types::Client,
-    sessions: &data_types::SafeSessions,
-) {
-    println!("[INFO] {} connected", client.id);
-    if let Some(session_id) = &client.session_id {
-        if let Some(session) = sessions.write().await.get_mut(session_id) {
-            session.set_client_active_status(&client.id, true);
+/// Remove a session once the grace period has passed, unless one of its clients became active again
+pub fn schedule_cleanup(session_id: String, sessions: &data_types::SafeSessions, grace: Duration) {
+    let sessions = sessions.clone();
+    tokio::spawn(
+        async move {
+            sleep(grace).await;
+            cleanup_session(&session_id, &sessions).await;
         }
-    }
+        .in_current_span(),
+    );
 }
 
-/// Gets the SessionID of a client if it exists
-async fn get_client_session_id(
-    client_id: &str,
-    sessions: &data_types::SafeSessions,
-) -> Option<String> {
-    for session in sessions.read().await.values() {
-        if session.contains_client(client_id) {
-            return Some(session.id.clone());
-        }
+/// If a client exists in a session, then set their status to active
+async fn handle_client_connect(client: session_types::Client, sessions: &data_types::SafeSessions) {
+    info!(client_id = %client.id, "client connected");
+    if let Some((_, session)) = game_engine::client_session(&client.id, sessions).await {
+        session.connect(client);
     }
-    return None;
 }
 
-/// Remove a sessions and the possible game state that accompanies it
-pub async fn cleanup_session(
-    session_id: &str,
-    sessions: &data_types::SafeSessions,
-    game_states: &data_types::SafeGameStates,
-) {
-    // remove session
-    sessions.write().await.remove(session_id);
-    // remove possible game state
-    game_states.write().await.remove(session_id);
+/// Stop a session and forget it, unless one of its clients is active
+pub async fn cleanup_session(session_id: &str, sessions: &data_types::SafeSessions) {
+    let session = match metrics::read(sessions, "sessions").await.get(session_id) {
+        Some(session) => session,
+        None => return,
+    };
+    // the registry is not held while the session stops, since its task may be busy.
+    // a session which already stopped answers nothing, and is forgotten all the same
+    if session.close().await == Some(false) {
+        debug!(
+            session_id,
+            "session was not removed, a client became active again"
+        );
+        return;
+    }
+
+    let mut registry = metrics::write(sessions, "sessions").await;
+    // clients who tried to join while it stopped have created the session again under its id
+    if !registry
+        .get(session_id)
+        .is_some_and(|current| current.is_same(&session))
+    {
+        return;
+    }
+    registry.remove(session_id);
     // log status
-    println!("[INFO] removed empty session");
-    println!("[INFO] sessions live: {}", sessions.read().await.len());
+    let sessions_live = registry.len();
+    info!(session_id, sessions_live, "removed empty session");
 }
@@ -1 +1,2 @@
 pub mod session_types;
+pub mod sink;
@@ -1,16 +1,26 @@
+use crate::sink::ClientSink;
 use std::collections::HashMap;
-use tokio::sync::mpsc;
-use warp::ws::Message;
+use std::sync::Arc;
 
 pub type Clients = HashMap<String, Client>;
 pub type Sessions = HashMap<String, Session>;
 
+/// Wire format that events sent to a client are serialized with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    MessagePack,
+}
+
 /// Data Stored for a Single User
 #[derive(Debug, Clone)]
 pub struct Client {
+    /// Opaque and stable, identifying the client across reconnections
     pub id: String,
-    pub session_id: Option<String>,
-    pub sender: Option<mpsc::UnboundedSender<std::result::Result<Message, warp::Error>>>,
+    /// The name shown to other players, which the client may change
+    pub display_name: String,
+    pub sender: Option<Arc<dyn ClientSink>>,
+    pub encoding: Encoding,
 }
 
 /// Data Stored for a Game Sessions
@@ -19,6 +29,8 @@ pub struct Session {
     pub id: String,
     pub owner: String,
     pub client_statuses: HashMap<String, bool>,
+    /// The name each member is shown with, unique within the session
+    pub display_names: HashMap<String, String>,
 }
 impl Session {
     pub fn get_num_clients(&self) -> usize {
@@ -29,13 +41,13 @@ impl Session {
     }
     pub fn get_client_ids(&self) -> Vec<String> {
         self.client_statuses
-            .clone()
-            .into_iter()
-            .map(|(id, _)| id)
+            .keys()
+            .cloned()
             .collect::<Vec<String>>()
     }
     pub fn remove_client(&mut self, id: &str) {
         self.client_statuses.remove(id);
+        self.display_names.remove(id);
     }
     pub fn insert_client(&mut self, id: &str, is_active: bool) {
         self.client_statuses.insert(id.to_string(), is_active);
@@ -48,13 +60,22 @@ impl Session {
             .map(|(id, _)| id)
             .collect::<Vec<String>>()
     }
+    /// The name a member is shown with, falling back to their id
+    pub fn get_display_name<'a>(&'a self, id: &'a str) -> &'a str {
+        self.display_names.get(id).map(String::as_str).unwrap_or(id)
+    }
+    pub fn set_display_name(&mut self, id: &str, display_name: &str) {
+        self.display_names
+            .insert(id.to_string(), display_name.to_string());
+    }
     pub fn set_client_active_status(&mut self, id: &str, is_active: bool) {
-        if self.client_statuses.get(id).is_some() {
+        if self.client_statuses.contains_key(id) {
             self.client_statuses.insert(id.to_string(), is_active);
         } else {
-            println!(
-                "[WARN] tried to set active_status of client: {} but id was not found in session",
-                id
+            tracing::warn!(
+                client_id = id,
+                session_id = %self.id,
+                "tried to set active_status of a client that was not found in session"
             );
         }
     }
@@ -0,0 +1,43 @@
+use std::fmt;
+
+/// A message on its way out to a client, whichever transport ends up carrying it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outbound {
+    Text(String),
+    Binary(Vec<u8>),
+    /// Checks that the client is still there, for transports which can ask
+    Ping(Vec<u8>),
+    /// Ends the connection with a close code and the reason for it
+    Close {
+        code: u16,
+        reason: String,
+    },
+}
+
+impl Outbound {
+    pub fn close(code: u16, reason: &str) -> Self {
+        Outbound::Close {
+            code,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+/// The connection a message could not be sent over has already closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SinkClosed;
+
+impl fmt::Display for SinkClosed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the connection to the client has closed")
+    }
+}
+
+impl std::error::Error for SinkClosed {}
+
+/// Where the messages for a single client are sent
+///
+/// Implemented by each transport, which delivers the messages in its own framing
+pub trait ClientSink: fmt::Debug + Send + Sync {
+    fn send(&self, message: Outbound) -> Result<(), SinkClosed>;
+}
@@ -23,7 +23,7 @@ export default function GameComponent(): JSX.Element {
 
   const { data } = useGameData()
   const { connection } = useServerConnection()
-  const { getUser } = useSessionData()
+  const { getUser, getName } = useSessionData()
 
   const getColor = (index: number): string => {
     if (index >= data.player_order.length) return ''
@@ -48,7 +48,7 @@ export default function GameComponent(): JSX.Element {
                   borderBottomWidth="0.5rem"
                   borderBottomColor={getColor(index)}
                 >
-                  {player == getUser() ? 'You' : player}
+                  {player == getUser() ? 'You' : getName(player)}
                 </Tag>
                 {data.player_order[data.turn_index] != player || <ArrowUpIcon />}
               </VStack>
@@ -80,6 +80,9 @@ export default function GameComponent(): JSX.Element {
           )}
         </HStack>
       </Stack>
+      <Button onClick={() => connection?.request_hint()}>
+        Hint
+      </Button>
       <Button onClick={() => connection?.startGame()}>
         Restart
       </Button>
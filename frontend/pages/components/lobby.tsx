@@ -20,19 +20,26 @@ export default function LobbyComponent(): JSX.Element {
   const notify = useNotify()
 
   const { connection } = useServerConnection()
-  const { getSession, getUser, getUsers } = useSessionData()
+  const { getSession, getUser, getUsers, getName } = useSessionData()
   const { setScreen } = useScreen()
 
+  const [name, setName] = React.useState(getName(getUser()))
+
   const copyRoomLink = () =>
     navigator.clipboard.writeText(location.protocol + '//' + location.host + '?roomid=' + getSession())
       .then(() => notify('Link Copied ✓'))
 
   return (
     <Stack>
-      <InputGroup>
-        <InputLeftAddon>ID</InputLeftAddon>
-        <Input value={getUser()} readOnly />
-      </InputGroup>
+      <form onSubmit={e => {
+        e.preventDefault()
+        connection?.setDisplayName(name)
+      }}>
+        <InputGroup>
+          <InputLeftAddon>Name</InputLeftAddon>
+          <Input value={name} onChange={event => setName(event.target.value)} />
+        </InputGroup>
+      </form>
 
       <Button onClick={() => {
         connection?.disconnect()
@@ -67,7 +74,7 @@ export default function LobbyComponent(): JSX.Element {
 
       <Divider pt={5} />
 
-      <UserList users={getUsers()} />
+      <UserList users={getUsers().map(getName)} />
 
     </Stack>
   )
@@ -5,7 +5,6 @@ import { Button } from '@chakra-ui/button'
 import { Input } from '@chakra-ui/input'
 
 import { useLogin } from 'providers/server-connecton.provider'
-import { useSessionData } from 'providers/session.provider'
 import { Screen, useScreen } from 'providers/screen.provider'
 
 import { APP_NAME } from 'environment'
@@ -13,24 +12,26 @@ import { APP_NAME } from 'environment'
 export default function LoginComponent(): JSX.Element {
 
   const { setScreen } = useScreen()
-  const { setUser, getUser } = useSessionData()
   const login = useLogin()
 
+  const [name, setName] = React.useState('')
+  React.useEffect(() => { setName(localStorage.getItem('displayname') || '') }, [])
+
   return (
     <>
       <Title />
 
       <form onSubmit={e => {
         e.preventDefault()
-        login({ user: getUser(), success: () => setScreen(Screen.Menu) })
+        login({ name, success: () => setScreen(Screen.Menu) })
       }}>
 
         <Stack>
           <Input
-            label="UserID"
+            label="Name"
             placeholder="Enter a name"
-            value={getUser()}
-            onChange={event => setUser(event.target.value)}
+            value={name}
+            onChange={event => setName(event.target.value)}
           />
           <Button type="submit" >
             Connect
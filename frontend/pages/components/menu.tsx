@@ -75,13 +75,14 @@ function JoinSessionComponent({ goBack }: { goBack: () => void }) {
 function NavigateComponent({ join }: { join: () => void }) {
   const { setScreen } = useScreen()
   const { connection } = useServerConnection()
-  const { getUser } = useSessionData()
+  const { getUser, getName } = useSessionData()
+  const [searching, setSearching] = React.useState(false)
 
   return (
     <Stack>
       <InputGroup>
-        <InputLeftAddon>ID</InputLeftAddon>
-        <Input label="UserID" value={getUser()} readOnly />
+        <InputLeftAddon>Name</InputLeftAddon>
+        <Input label="Name" value={getName(getUser())} readOnly />
       </InputGroup>
       <Button onClick={() => {
         connection?.disconnect()
@@ -91,6 +92,12 @@ function NavigateComponent({ join }: { join: () => void }) {
       </Button>
       <Button onClick={() => join()}> Join Room </Button>
       <Button onClick={() => connection?.create_session()}> Create Room </Button>
+      <Button onClick={() => {
+        searching ? connection?.leave_queue() : connection?.join_queue()
+        setSearching(!searching)
+      }}>
+        {searching ? 'Stop Searching' : 'Find Match'}
+      </Button>
     </Stack>
   )
 }
\ No newline at end of file
@@ -11,7 +11,6 @@ import { useNotify } from 'providers/notification.provider'
 import { getRoomId } from 'providers/route-updater.provider'
 import { Screen, useScreen } from 'providers/screen.provider'
 import { useLogin, useServerConnection } from 'providers/server-connecton.provider'
-import { useSessionData } from 'providers/session.provider'
 
 import { verifySessionID } from 'utils/websocket-client'
 import { useRouter } from 'next/router'
@@ -25,9 +24,8 @@ export default function RoomJoinModal(): JSX.Element {
   const { setScreen } = useScreen()
   const { isOpen, onOpen, onClose } = useDisclosure()
   const { connection } = useServerConnection()
-  const { getUser, setUser } = useSessionData()
-
-  const [cachedUser, setCachedUser] = useCached('userid')
+  const [name, setName] = React.useState('')
+  const [cachedName, setCachedName] = useCached('displayname')
   const [roomid, setRoomid] = React.useState<string>('')
 
   React.useEffect(() => {
@@ -45,7 +43,7 @@ export default function RoomJoinModal(): JSX.Element {
 
   }, [])
 
-  React.useEffect(() => { setUser(cachedUser) }, [cachedUser])
+  React.useEffect(() => { setName(cachedName) }, [cachedName])
 
   return (
     <Modal
@@ -59,13 +57,13 @@ export default function RoomJoinModal(): JSX.Element {
           e => {
             e.preventDefault()
             login({
-              user: getUser(),
+              name,
               success: () => {
                 connection?.join_session(roomid)
                 onClose()
-                setCachedUser(getUser())
+                setCachedName(name)
               },
-              failure: () => setCachedUser('')
+              failure: () => setCachedName('')
             })
           }
         }>
@@ -79,16 +77,16 @@ export default function RoomJoinModal(): JSX.Element {
             </VStack>
           </ModalHeader>
           {
-            cachedUser
+            cachedName
               ? (
                 <>
                   <ModalBody>
                     <Center>
                       <HStack>
                         <Text>Join as: </Text>
-                        <Tag size="lg"> {getUser()} </Tag>
+                        <Tag size="lg"> {name} </Tag>
                         <Text>?</Text>
-                        <Button colorScheme="blue" onClick={() => setCachedUser('')}>
+                        <Button colorScheme="blue" onClick={() => setCachedName('')}>
                           No
                         </Button>
                         <Button colorScheme="blue" type="submit">
@@ -104,10 +102,10 @@ export default function RoomJoinModal(): JSX.Element {
                   <ModalBody>
                     <HStack>
                       <Input
-                        label="UserID"
+                        label="Name"
                         placeholder="Enter a name"
-                        value={getUser()}
-                        onChange={event => setUser(event.target.value)}
+                        value={name}
+                        onChange={event => setName(event.target.value)}
                       />
                       <Button colorScheme="blue" type="submit">
                         Connect
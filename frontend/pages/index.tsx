@@ -30,7 +30,7 @@ export default function Home(): JSX.Element {
 
   const { getScreen, setScreen } = useScreen()
   const { setConnection } = useServerConnection()
-  const { setSession, getUser, getUsers, setUsers } = useSessionData()
+  const { setSession, getUser, getUsers, setUsers, getName, setNames } = useSessionData()
   const { setTurnIndex, setPlayIndexes, setPlayerOrder } = useGameData()
 
   // run once on init
@@ -38,6 +38,7 @@ export default function Home(): JSX.Element {
 
     const newGameServerConnection = new ServerConnection({
       [ServerEventCode.ClientJoined]: (response: ServerEvent) => {
+        setNames(response.data?.display_names)
         if (response.data?.client_id == getUser()) {
           setSession(response.data?.session_id || '')
           notify(response.data?.session_client_ids?.length == 1
@@ -46,22 +47,24 @@ export default function Home(): JSX.Element {
           )
           setScreen(Screen.Lobby)
         } else {
-          notify('User ' + response.data?.client_id + ' Joined!')
+          notify('User ' + getName(response.data?.client_id || '') + ' Joined!')
         }
         setUsers(response.data?.session_client_ids || [])
       },
       [ServerEventCode.ClientLeft]: (response: ServerEvent) => {
+        setNames(response.data?.display_names)
         if (response.data?.client_id == getUser()) {
           setSession('')
           setUsers([])
           notify('Left the Session.')
           setScreen(Screen.Menu)
         } else {
-          notify('User ' + response.data?.client_id + ' Left!')
+          notify('User ' + getName(response.data?.client_id || '') + ' Left!')
         }
         setUsers(getUsers().filter(id => id != response.data?.client_id))
       },
       [ServerEventCode.GameStarted]: (response: ServerEvent) => {
+        setNames(response.data?.display_names)
         setPlayerOrder(response.data?.game_data?.player_order || [])
         setPlayIndexes(response.data?.game_data?.play_indexes || [])
         setTurnIndex(response.data?.game_data?.turn_index || 0)
@@ -70,6 +73,7 @@ export default function Home(): JSX.Element {
         setScreen(Screen.Game)
       },
       [ServerEventCode.SessionResponse]: (response: ServerEvent) => {
+        setNames(response.data?.display_names)
         setSession(response.data?.session_id || '')
         setUsers(response.data?.session_client_ids || [])
 
@@ -86,6 +90,7 @@ export default function Home(): JSX.Element {
         }
       },
       [ServerEventCode.TurnStart]: (response: ServerEvent) => {
+        setNames(response.data?.display_names)
         setPlayerOrder(response.data?.game_data?.player_order || [])
         setPlayIndexes(response.data?.game_data?.play_indexes || [])
         setTurnIndex(response.data?.game_data?.turn_index || 0)
@@ -99,7 +104,37 @@ export default function Home(): JSX.Element {
       },
       [ServerEventCode.GameEnded]: (response: ServerEvent) => {
         setPlayIndexes(response.data?.game_data?.play_indexes || [])
-        notify(response.data?.client_id + ' won!' || '')
+        // drawn games and games ended by the server have no winner
+        notify(response.data?.client_id ? getName(response.data.client_id) + ' won!' : (response.message || 'The game was ended by the server.'))
+        const hinted = Object.entries(response.data?.hints_used || {}).filter(([, count]) => (count || 0) > 0)
+        if (hinted.length) notify('Hints used: ' + hinted.map(([id, count]) => getName(id) + ' ' + count).join(', '))
+      },
+      [ServerEventCode.Acknowledged]: () => {
+        // requests are not correlated by the UI yet
+      },
+      [ServerEventCode.Welcome]: (response: ServerEvent) => {
+        // the server accepted our protocol version, and states the name we are shown with
+        setNames(response.data?.display_names)
+      },
+      [ServerEventCode.ServerShuttingDown]: (response: ServerEvent) => {
+        notify('The server is restarting in ' + response.data?.shutdown_seconds + ' seconds.')
+      },
+      [ServerEventCode.Announcement]: (response: ServerEvent) => {
+        notify(response.message || '')
+      },
+      [ServerEventCode.DisplayNameChanged]: (response: ServerEvent) => {
+        const id = response.data?.client_id || ''
+        const previous = getName(id)
+        setNames(response.data?.display_names)
+        if (id == getUser()) notify('You are now ' + getName(id) + '.')
+        else notify(previous + ' is now ' + getName(id) + '.')
+      },
+      [ServerEventCode.TournamentUpdated]: (response: ServerEvent) => {
+        // standings are fetched from the REST api, the event only says what changed
+        notify(response.message || '')
+      },
+      [ServerEventCode.Hint]: (response: ServerEvent) => {
+        notify('Try column ' + ((response.data?.column ?? 0) + 1) + ' (' + response.data?.hints_left + ' hints left)')
       },
     })
 
@@ -27,23 +27,36 @@ type LoginEvents = {
   close?: () => void
 }
 
+/**
+ * The opaque id identifying this tab to the server, kept across reloads so the tab can rejoin its session
+ */
+function getClientId(): string {
+  let id = sessionStorage.getItem('clientid')
+  if (!id) {
+    id = Array.from(crypto.getRandomValues(new Uint8Array(12)), b => b.toString(16).padStart(2, '0')).join('')
+    sessionStorage.setItem('clientid', id)
+  }
+  return id
+}
+
 export function useLogin() {
 
   const notify = useNotify()
   const { setUser } = useSessionData()
   const { connection } = useServerConnection()
 
-  return ({ user, success = () => 0, failure = () => 0, close = () => 0 }: { user: string } & LoginEvents) => {
-    connection?.connect(user, {
+  return ({ name, success = () => 0, failure = () => 0, close = () => 0 }: { name: string } & LoginEvents) => {
+    const user = getClientId()
+    connection?.connect(user, name, {
       open: () => {
         notify('Connected.')
         setUser(user)
-        localStorage.setItem('userid', user)
+        localStorage.setItem('displayname', name)
         connection.fetchSession()
         success()
       },
       error: () => {
-        notify('Error: ID may already be taken.')
+        notify('Error: this tab may already be connected.')
         failure()
       },
       close: () => {
@@ -5,6 +5,7 @@ type SessionData = {
   session: string
   user: string
   users: string[]
+  names: Record<string, string>
   logs: string[]
 }
 
@@ -15,10 +16,12 @@ type SessionDataStore = {
   getUser: Accessor<SessionData['user']>
   getUsers: Accessor<SessionData['users']>
   getLogs: Accessor<SessionData['logs']>
+  getName: (id: string) => string
 
   setSession: Mutator<SessionData['session']>
   setUser: Mutator<SessionData['user']>
   setUsers: Mutator<SessionData['users']>
+  setNames: Mutator<{ [id in string]?: string } | null | undefined>
   log: Mutator<string>
 }
 
@@ -27,6 +30,7 @@ export const useSessionData = create<SessionDataStore>((set, get) => ({
     session: '',
     user: '',
     users: [],
+    names: {},
     logs: [],
   },
 
@@ -34,10 +38,14 @@ export const useSessionData = create<SessionDataStore>((set, get) => ({
   getUser: () => get().data.user,
   getUsers: () => get().data.users,
   getLogs: () => get().data.logs,
+  getName: id => get().data.names[id] || id,
 
   setSession: session => set(state => { state.data.session = session }),
   setUser: user => set(state => { state.data.user = user }),
   setUsers: users => set(state => { state.data.users = users }),
+  setNames: names => set(state => {
+    if (names) state.data.names = { ...state.data.names, ...names } as Record<string, string>
+  }),
   log: log => set(state => {
     console.log(log)
     state.data.logs = [...state.data.logs, log]
@@ -1,34 +1,98 @@
 /**
  * This file contains type defintions which are shared between the front and back end applications
+ *
+ * It is generated from backend/server/src/shared_types.rs with `cargo run --bin gen-types`
+ * and should not be edited by hand
  */
 
 export type GameData = {
-     turn_index: number,
-     player_order: Array<string>,
-     play_indexes: Array<Array<number>>,
-}
+    turn_index: number,
+    player_order: Array<string>,
+    play_indexes: Array<Array<number>>,
+};
 
 export type Event<Code, PayloadType> = {
-     event_code: Code,
-     message?: string,
-     data?: PayloadType,
-}
+    event_code: Code,
+    /**
+     * Client supplied identifier which the server echoes on direct replies
+     */
+    request_id?: string | null,
+    message?: string | null,
+    data?: PayloadType | null,
+};
 
-export type ServerEvent = Event<ServerEventCode, ServerEventData>
-export type ClientEvent = Event<ClientEventCode, ClientEventData>
+export type ServerEvent = Event<ServerEventCode, ServerEventData>;
+
+export type ClientEvent = Event<ClientEventCode, ClientEventData>;
 
 export type ServerEventData = {
-     session_id?: string,
-     client_id?: string,
-     session_client_ids?: Array<string>,
-     game_data?: GameData,
-}
+    session_id?: string | null,
+    client_id?: string | null,
+    session_client_ids?: Array<string> | null,
+    game_data?: GameData | null,
+    protocol_version?: number | null,
+    rule_variants?: Array<string> | null,
+    features?: Array<string> | null,
+    /**
+     * Wire format the server will send events in, either `json` or `msgpack`
+     */
+    encoding?: string | null,
+    /**
+     * Secret an event stream client must send with every POSTed event
+     */
+    connection_token?: string | null,
+    /**
+     * Seconds until the server closes every connection
+     */
+    shutdown_seconds?: number | null,
+    /**
+     * The name to show for each client id in the event
+     */
+    display_names?: { [key in string]?: string } | null,
+    /**
+     * The tournament an event is about
+     */
+    tournament_id?: string | null,
+    /**
+     * The column a hint suggests playing
+     */
+    column?: number | null,
+    /**
+     * How many more hints the client can ask for in the game
+     */
+    hints_left?: number | null,
+    /**
+     * How many hints each player asked for in a finished game
+     */
+    hints_used?: { [key in string]?: number } | null,
+};
 
 export type ClientEventData = {
-     target_ids?: Array<string>,
-     session_id?: string,
-     column?: number,
-}
+    target_ids?: Array<string> | null,
+    session_id?: string | null,
+    column?: number | null,
+    protocol_version?: number | null,
+    /**
+     * Wire format the client wants to receive events in, either `json` or `msgpack`
+     */
+    encoding?: string | null,
+    /**
+     * The name the client wants to be shown with
+     */
+    display_name?: string | null,
+    /**
+     * How many players the client wants to be matched into a game of
+     */
+    player_count?: number | null,
+    /**
+     * The rule set the client wants to be matched into a game of
+     */
+    rule_variant?: string | null,
+    /**
+     * How many hints each player may ask for in the game being started, which only the session owner can choose
+     */
+    hint_budget?: number | null,
+};
 
 export enum ServerEventCode {
     /**
@@ -45,6 +109,28 @@ export enum ServerEventCode {
      */
     TurnStart,
     LogicError,
+    /**
+     * Protocol Related
+     */
+    Acknowledged,
+    Welcome,
+    /**
+     * Server Related
+     */
+    ServerShuttingDown,
+    Announcement,
+    /**
+     * Session Related
+     */
+    DisplayNameChanged,
+    /**
+     * Tournament Related
+     */
+    TournamentUpdated,
+    /**
+     * Game Related
+     */
+    Hint,
 }
 
 export enum ClientEventCode {
@@ -60,4 +146,21 @@ export enum ClientEventCode {
      */
     StartGame,
     Play,
+    /**
+     * Protocol Related Events
+     */
+    Hello,
+    /**
+     * Session Related Events
+     */
+    SetDisplayName,
+    /**
+     * Matchmaking Related Events
+     */
+    JoinQueue,
+    LeaveQueue,
+    /**
+     * Game Related Events
+     */
+    RequestHint,
 }
@@ -2,6 +2,11 @@ import { environment } from 'environment'
 import { IMessageEvent, w3cwebsocket as W3CWebSocket } from 'websocket'
 import { ClientEvent, ClientEventCode, ServerEvent, ServerEventCode } from './shared-types'
 
+/**
+ * Version of the event protocol this client speaks, stated in the Hello handshake
+ */
+export const PROTOCOL_VERSION = 1
+
 
 export class ServerConnection {
   private socket: W3CWebSocket | null = null
@@ -19,7 +24,7 @@ export class ServerConnection {
     }
   }
 
-  public connect(userId: string, callbacks: {
+  public connect(userId: string, displayName: string, callbacks: {
     open: () => void
     close: () => void
     error: (err: any) => void
@@ -27,7 +32,13 @@ export class ServerConnection {
     const setupConnection = () => {
       this.socket = new W3CWebSocket(getWebSocketUri() + '/' + userId)
       this.socket.onmessage = this.eventHandler
-      this.socket.onopen = () => callbacks.open()
+      this.socket.onopen = () => {
+        this.send_message({
+          event_code: ClientEventCode.Hello,
+          data: { protocol_version: PROTOCOL_VERSION, display_name: displayName },
+        })
+        callbacks.open()
+      }
       this.socket.onclose = () => callbacks.close()
       this.socket.onerror = (err: any) => callbacks.error(err)
     }
@@ -55,6 +66,10 @@ export class ServerConnection {
   public leave_session = (): void => this.send_message({ event_code: ClientEventCode.LeaveSession })
   public fetchSession = (): void => this.send_message({ event_code: ClientEventCode.SessionRequest })
   public startGame = (): void => this.send_message({ event_code: ClientEventCode.StartGame })
+  public setDisplayName = (display_name: string): void => this.send_message({ event_code: ClientEventCode.SetDisplayName, data: { display_name } })
+  public join_queue = (player_count?: number): void => this.send_message({ event_code: ClientEventCode.JoinQueue, data: { player_count } })
+  public leave_queue = (): void => this.send_message({ event_code: ClientEventCode.LeaveQueue })
+  public request_hint = (): void => this.send_message({ event_code: ClientEventCode.RequestHint })
 
   public join_session(session_id: string, errorCallback?: (err: string) => void): void {
     const errors = verifySessionID(session_id)